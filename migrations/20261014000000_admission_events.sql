@@ -0,0 +1,22 @@
+CREATE TABLE admission_events (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    -- Unix timestamp in milliseconds
+    timestamp INTEGER NOT NULL,
+    direction TEXT NOT NULL,
+    protocol TEXT NOT NULL,
+    status TEXT NOT NULL,
+    app TEXT NOT NULL,
+    room TEXT NOT NULL,
+    streamer TEXT,
+    client_address TEXT NOT NULL,
+    -- "allowed" or "denied" for the opening requests, NULL for the closing ones
+    outcome TEXT,
+    reason TEXT,
+    sampled INTEGER NOT NULL
+);
+
+CREATE INDEX admission_events_timestamp ON admission_events (timestamp);
+CREATE INDEX admission_events_room ON admission_events (room);
+CREATE INDEX admission_events_streamer ON admission_events (streamer);
+CREATE INDEX admission_events_outcome ON admission_events (outcome);
+CREATE INDEX admission_events_protocol ON admission_events (protocol);
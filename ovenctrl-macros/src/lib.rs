@@ -0,0 +1,280 @@
+//! Derive macros of oven-ctrl
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    meta::ParseNestedMeta, parse_macro_input, spanned::Spanned, Attribute, Data, DeriveInput, Expr,
+    ExprLit, Fields, GenericArgument, Lit, LitStr, Meta, PathArguments, Token, Type,
+};
+
+/// Generate a `config_doc()` function returning the documentation of the fields of a
+/// configuration struct, as `Vec<crate::config_help::ConfigField>`.
+///
+/// The description of a field is the first paragraph of its doc comment, which is required. Its
+/// default is computed from its `#[serde(default)]` or `#[serde(default = "...")]` attribute, then
+/// formatted by `crate::config_help::display_default`. The fields without a default are
+/// documented as `none` when they are an `Option`, and as `required` otherwise.
+///
+/// `#[config_doc(...)]` changes the documentation of a field:
+/// - `default = "..."`: text of the default, e.g. when it depends on other fields
+/// - `ty = "..."`: type shown instead of the one derived from the Rust type, e.g. for enums
+/// - `nested`: also document the fields of the type of the field (or of the values of an
+///   `Option`, array or table), which must derive `ConfigDoc` too
+#[proc_macro_derive(ConfigDoc, attributes(config_doc))]
+pub fn derive_config_doc(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+enum SerdeDefault {
+    None,
+    /// `#[serde(default)]`
+    Trait,
+    /// `#[serde(default = "path")]`
+    Function(syn::ExprPath),
+}
+
+#[derive(Default)]
+struct ConfigDocAttributes {
+    default: Option<LitStr>,
+    ty: Option<LitStr>,
+    nested: bool,
+}
+
+/// Skip the value of an attribute argument that is not used
+fn skip(meta: &ParseNestedMeta) -> syn::Result<()> {
+    if meta.input.peek(Token![=]) {
+        meta.value()?.parse::<Expr>()?;
+    } else if meta.input.peek(syn::token::Paren) {
+        meta.parse_nested_meta(|meta| skip(&meta))?;
+    }
+    Ok(())
+}
+
+/// `default` and `rename` arguments of the `#[serde]` attributes
+fn serde_attributes(attrs: &[Attribute]) -> syn::Result<(SerdeDefault, Option<String>)> {
+    let mut default = SerdeDefault::None;
+    let mut rename = None;
+
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("default") {
+                default = match meta.input.peek(Token![=]) {
+                    true => SerdeDefault::Function(meta.value()?.parse::<LitStr>()?.parse()?),
+                    false => SerdeDefault::Trait,
+                };
+            } else if meta.path.is_ident("rename") && meta.input.peek(Token![=]) {
+                rename = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else {
+                skip(&meta)?;
+            }
+            Ok(())
+        })?;
+    }
+
+    Ok((default, rename))
+}
+
+fn config_doc_attributes(attrs: &[Attribute]) -> syn::Result<ConfigDocAttributes> {
+    let mut parsed = ConfigDocAttributes::default();
+
+    for attr in attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("config_doc"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("default") {
+                parsed.default = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("ty") {
+                parsed.ty = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("nested") {
+                parsed.nested = true;
+            } else {
+                return Err(meta.error("expected `default`, `ty` or `nested`"));
+            }
+            Ok(())
+        })?;
+    }
+
+    Ok(parsed)
+}
+
+/// First paragraph of the doc comment, on a single line and without the code spans markers
+fn description(attrs: &[Attribute]) -> String {
+    let lines = attrs.iter().filter_map(|attr| match &attr.meta {
+        Meta::NameValue(doc) if doc.path.is_ident("doc") => match &doc.value {
+            Expr::Lit(ExprLit {
+                lit: Lit::Str(line),
+                ..
+            }) => Some(line.value()),
+            _ => None,
+        },
+        _ => None,
+    });
+
+    lines
+        .map(|line| line.trim().replace('`', ""))
+        .take_while(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Last segment of a type path, with its type arguments
+fn type_segment(ty: &Type) -> Option<(String, Vec<&Type>)> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+
+    let arguments = match &segment.arguments {
+        PathArguments::AngleBracketed(arguments) => arguments
+            .args
+            .iter()
+            .filter_map(|argument| match argument {
+                GenericArgument::Type(ty) => Some(ty),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    Some((segment.ident.to_string(), arguments))
+}
+
+fn plural(name: String) -> String {
+    match name.ends_with('s') {
+        true => name + "es",
+        false => name + "s",
+    }
+}
+
+/// Name of a type in the configuration files
+fn type_name(ty: &Type) -> String {
+    if matches!(ty, Type::Tuple(_) | Type::Array(_)) {
+        return "array".to_owned();
+    }
+    let Some((ident, arguments)) = type_segment(ty) else {
+        return "table".to_owned();
+    };
+
+    let name = match (ident.as_str(), arguments.as_slice()) {
+        ("Option" | "Box", [inner]) => return type_name(inner),
+        ("Vec" | "HashSet" | "BTreeSet", [inner]) => {
+            return format!("array of {}", plural(type_name(inner)))
+        }
+        ("bool", _) => "boolean",
+        ("u8" | "u16" | "u32" | "u64" | "usize" | "i32" | "i64", _) => "integer",
+        ("f32" | "f64", _) => "float",
+        ("String", _) => "string",
+        ("Url", _) => "URL",
+        ("IpAddr", _) => "IP address",
+        ("IpNet", _) => "network",
+        ("PathBuf", _) => "path",
+        _ => "table",
+    };
+    name.to_owned()
+}
+
+/// Type of the values of a field, e.g. of the values of a table
+fn value_type(ty: &Type) -> &Type {
+    match type_segment(ty) {
+        Some((ident, arguments)) => match (ident.as_str(), arguments.as_slice()) {
+            ("Option" | "Box" | "Vec" | "HashSet" | "BTreeSet", [inner]) => value_type(inner),
+            ("HashMap" | "BTreeMap", [_, value]) => value_type(value),
+            _ => ty,
+        },
+        None => ty,
+    }
+}
+
+fn is_option(ty: &Type) -> bool {
+    type_segment(ty).is_some_and(|(ident, _)| ident == "Option")
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new(
+            input.span(),
+            "ConfigDoc can only be derived for structs",
+        ));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(syn::Error::new(
+            input.span(),
+            "ConfigDoc can only be derived for structs with named fields",
+        ));
+    };
+    let (container_default, _) = serde_attributes(&input.attrs)?;
+
+    let mut docs = Vec::new();
+    for field in &fields.named {
+        let ident = field.ident.as_ref().expect("the fields are named");
+        let ty = &field.ty;
+        let (default, rename) = serde_attributes(&field.attrs)?;
+        let attributes = config_doc_attributes(&field.attrs)?;
+
+        let name = rename.unwrap_or_else(|| ident.to_string());
+        let description = description(&field.attrs);
+        if description.is_empty() {
+            return Err(syn::Error::new(
+                field.span(),
+                format!("missing documentation of the configuration field `{name}`"),
+            ));
+        }
+        let type_name = match attributes.ty {
+            Some(ty) => ty.value(),
+            None => type_name(ty),
+        };
+
+        let default = match (attributes.default, default, &container_default) {
+            (Some(text), _, _) => quote!(::std::string::String::from(#text)),
+            (None, SerdeDefault::Function(path), _) => {
+                quote!(crate::config_help::display_default(&#path()))
+            }
+            (None, SerdeDefault::Trait, _) => quote!(crate::config_help::display_default(
+                &<#ty as ::core::default::Default>::default()
+            )),
+            (None, SerdeDefault::None, SerdeDefault::Trait) => {
+                quote!(crate::config_help::display_default(
+                    &<Self as ::core::default::Default>::default().#ident
+                ))
+            }
+            (None, SerdeDefault::None, _) if is_option(ty) => {
+                quote!(::std::string::String::from("none"))
+            }
+            (None, SerdeDefault::None, _) => quote!(::std::string::String::from("required")),
+        };
+
+        let nested = match attributes.nested {
+            true => {
+                let value = value_type(ty);
+                quote!(<#value>::config_doc())
+            }
+            false => quote!(::std::vec::Vec::new()),
+        };
+
+        docs.push(quote! {
+            crate::config_help::ConfigField {
+                name: #name,
+                ty: #type_name,
+                default: #default,
+                description: #description,
+                fields: #nested,
+            }
+        });
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics #name #ty_generics #where_clause {
+            /// Documentation of the fields, in their declaration order
+            pub fn config_doc() -> ::std::vec::Vec<crate::config_help::ConfigField> {
+                ::std::vec![#(#docs),*]
+            }
+        }
+    })
+}
@@ -0,0 +1,607 @@
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    marker::PhantomData,
+    sync::Arc,
+    time::Instant,
+};
+
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    extract::{FromRequestParts, Path, Query, State},
+    http::{header, request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Json, Router,
+};
+
+use futures_util::StreamExt;
+use ovenctrl_macros::ConfigDoc;
+use subtle::ConstantTimeEq;
+use time::OffsetDateTime;
+
+use crate::{
+    admin_ws, analytics,
+    events::{EventFilter, EventPage, Outcome},
+    invites, log_tail,
+    metrics::AdmissionMetricsSnapshot,
+    quota,
+    sessions::{ConnectionAttempt, IngestSession},
+    stream_events, OvenCtrlConfig, OvenCtrlState, RoomPasswordList,
+};
+
+/// Operations that can be granted to an administration token
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    ReadConfig,
+    WriteStreamers,
+    WriteRooms,
+    KickViewers,
+    ReadMetrics,
+    ManageState,
+    ReadLogs,
+    ManageWebhooks,
+}
+
+/// Administration token only allowed to perform some operations
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, ConfigDoc)]
+pub struct AdminToken {
+    /// Bearer token of the requests
+    pub token: String,
+    /// Operations the token is allowed to perform, e.g. `read_metrics`
+    #[config_doc(ty = "array of permissions")]
+    pub permissions: HashSet<Permission>,
+}
+
+/// Type level [Permission], used to parametrize [RequiredPermission]
+pub trait Scope {
+    const PERMISSION: Permission;
+}
+
+macro_rules! scopes {
+    ($($permission:ident),* $(,)?) => {
+        $(
+            pub struct $permission;
+
+            impl Scope for $permission {
+                const PERMISSION: Permission = Permission::$permission;
+            }
+        )*
+    };
+}
+
+pub mod scope {
+    use super::{Permission, Scope};
+
+    scopes!(
+        ReadConfig,
+        WriteStreamers,
+        WriteRooms,
+        ReadMetrics,
+        ManageState,
+        ReadLogs,
+        ManageWebhooks
+    );
+}
+
+pub(crate) fn has_admin_tokens(config: &OvenCtrlConfig) -> bool {
+    config.admin_token.is_some() || !config.admin_tokens.is_empty()
+}
+
+/// Check that `token` grants `permission`, or only that it is a valid token when `None`
+pub(crate) fn authorize(
+    config: &OvenCtrlConfig,
+    token: &str,
+    permission: Option<Permission>,
+) -> Result<(), StatusCode> {
+    let token = token.as_bytes();
+    if config
+        .admin_token
+        .as_ref()
+        .is_some_and(|admin_token| bool::from(admin_token.as_bytes().ct_eq(token)))
+    {
+        return Ok(());
+    }
+
+    // Every scoped token is compared, so that the time taken does not tell which one matched
+    let scoped = config.admin_tokens.iter().fold(None, |matched, scoped| {
+        match bool::from(scoped.token.as_bytes().ct_eq(token)) {
+            true => Some(scoped),
+            false => matched,
+        }
+    });
+    let Some(scoped) = scoped else {
+        tracing::warn!("Invalid admin token");
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+
+    match permission {
+        Some(permission) if !scoped.permissions.contains(&permission) => {
+            tracing::warn!("Admin token is missing the {permission:?} permission");
+            Err(StatusCode::FORBIDDEN)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Extractor rejecting requests whose bearer token does not grant `S::PERMISSION`.
+///
+/// The `admin_token` grants every permission. Requests are answered with `404 Not Found` when no
+/// administration token is configured at all.
+pub struct RequiredPermission<S>(PhantomData<S>);
+
+#[async_trait]
+impl<S: Scope> FromRequestParts<Arc<OvenCtrlState>> for RequiredPermission<S> {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<OvenCtrlState>,
+    ) -> Result<Self, Self::Rejection> {
+        let config = state.config();
+        if !has_admin_tokens(&config) {
+            return Err(StatusCode::NOT_FOUND);
+        }
+
+        let Some(token) = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+        else {
+            tracing::warn!("Missing admin token");
+            return Err(StatusCode::UNAUTHORIZED);
+        };
+
+        authorize(&config, token, Some(S::PERMISSION))?;
+
+        Ok(Self(PhantomData))
+    }
+}
+
+#[tracing::instrument(skip(_permission, state))]
+async fn export(
+    _permission: RequiredPermission<scope::ReadConfig>,
+    State(state): State<Arc<OvenCtrlState>>,
+) -> Result<Response, StatusCode> {
+    let config = toml::to_string_pretty(&state.config().redacted()).map_err(|err| {
+        tracing::error!("Could not serialize configuration: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/toml"),
+            (
+                header::CONTENT_DISPOSITION,
+                r#"attachment; filename="ovenctrl-config.toml""#,
+            ),
+        ],
+        config,
+    )
+        .into_response())
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum MergeMode {
+    /// Replace the imported sections of the configuration
+    Replace,
+    /// Add new entries, and the missing rooms and passwords of existing entries
+    #[default]
+    Merge,
+    /// Only add new entries
+    Diff,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct ImportQuery {
+    #[serde(default)]
+    merge_mode: MergeMode,
+}
+
+#[derive(serde::Deserialize, Debug, Default)]
+pub(crate) struct Import {
+    pub(crate) streamers: Option<HashMap<String, String>>,
+    pub(crate) rooms: Option<HashMap<String, RoomPasswordList>>,
+    pub(crate) allowed_streams: Option<HashMap<String, HashSet<String>>>,
+    pub(crate) room_category: Option<HashMap<String, String>>,
+}
+
+#[derive(serde::Serialize, Debug, Default)]
+pub(crate) struct ImportSummary {
+    pub(crate) added: usize,
+    updated: usize,
+    skipped: usize,
+}
+
+impl ImportSummary {
+    /// Import the entries of `imported` into `existing`.
+    ///
+    /// `merge` combines an imported entry into an existing one, returning whether it changed.
+    fn import<V>(
+        &mut self,
+        mode: MergeMode,
+        existing: &mut HashMap<String, V>,
+        imported: Option<HashMap<String, V>>,
+        merge: impl Fn(&mut V, V) -> bool,
+    ) {
+        let Some(imported) = imported else {
+            return;
+        };
+
+        if mode == MergeMode::Replace {
+            existing.clear();
+        }
+
+        for (name, value) in imported {
+            match existing.entry(name) {
+                Entry::Vacant(entry) => {
+                    entry.insert(value);
+                    self.added += 1;
+                }
+                Entry::Occupied(mut entry) => {
+                    if mode == MergeMode::Merge && merge(entry.get_mut(), value) {
+                        self.updated += 1;
+                    } else {
+                        self.skipped += 1;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The `env:` keys are only resolved in the configuration files: resolving them at runtime would
+/// let the administration API export any environment variable of the controller
+pub(crate) fn reject_env_keys(config: &OvenCtrlConfig) -> anyhow::Result<()> {
+    match config
+        .streamers
+        .iter()
+        .find(|(_, key)| key.starts_with("env:"))
+    {
+        Some((name, _)) => anyhow::bail!(
+            "the key of streamer {name} can only reference an environment variable in the \
+             configuration files"
+        ),
+        None => Ok(()),
+    }
+}
+
+/// Merge `import` into `config` according to `mode`
+pub(crate) fn apply_import(
+    config: &mut OvenCtrlConfig,
+    mode: MergeMode,
+    import: Import,
+) -> anyhow::Result<ImportSummary> {
+    let mut summary = ImportSummary::default();
+
+    // Changing the key of a streamer is not a merge
+    summary.import(mode, &mut config.streamers, import.streamers, |_, _| false);
+    summary.import(
+        mode,
+        &mut config.rooms,
+        import.rooms,
+        RoomPasswordList::merge,
+    );
+    summary.import(
+        mode,
+        &mut config.allowed_streams,
+        import.allowed_streams,
+        |rooms, imported| {
+            let before = rooms.len();
+            rooms.extend(imported);
+            rooms.len() != before
+        },
+    );
+    summary.import(
+        mode,
+        &mut config.room_category,
+        import.room_category,
+        |category, imported| {
+            let changed = *category != imported;
+            *category = imported;
+            changed
+        },
+    );
+
+    reject_env_keys(config)?;
+
+    Ok(summary)
+}
+
+/// Load streamers and rooms in bulk into the runtime configuration
+#[tracing::instrument(skip(_streamers, _rooms, state, import))]
+async fn import(
+    _streamers: RequiredPermission<scope::WriteStreamers>,
+    _rooms: RequiredPermission<scope::WriteRooms>,
+    State(state): State<Arc<OvenCtrlState>>,
+    Query(query): Query<ImportQuery>,
+    Json(import): Json<Import>,
+) -> Result<Json<ImportSummary>, (StatusCode, String)> {
+    let summary = state
+        .update_config(|config| apply_import(config, query.merge_mode, import))
+        .map_err(|err| (StatusCode::BAD_REQUEST, format!("{err:#}")))?;
+
+    tracing::info!(
+        "Imported configuration: {} added, {} updated, {} skipped",
+        summary.added,
+        summary.updated,
+        summary.skipped
+    );
+
+    Ok(Json(summary))
+}
+
+#[tracing::instrument(skip(_permission, state))]
+async fn admission_metrics(
+    _permission: RequiredPermission<scope::ReadMetrics>,
+    State(state): State<Arc<OvenCtrlState>>,
+) -> Json<AdmissionMetricsSnapshot> {
+    Json(state.metrics.snapshot())
+}
+
+#[derive(serde::Serialize, Debug)]
+struct Diagnostics {
+    ome_api: String,
+    ome_api_latency_ms: Option<u128>,
+    ome_version: Option<String>,
+    uptime_secs: u64,
+}
+
+#[tracing::instrument(skip(_permission, state))]
+async fn diagnostics(
+    _permission: RequiredPermission<scope::ReadMetrics>,
+    State(state): State<Arc<OvenCtrlState>>,
+) -> Json<Diagnostics> {
+    let mut diagnostics = Diagnostics {
+        ome_api: "not configured".into(),
+        ome_api_latency_ms: None,
+        ome_version: None,
+        uptime_secs: state.metrics.uptime().as_secs(),
+    };
+
+    if state.ome_api.is_some() {
+        let start = Instant::now();
+        match state.check_ome_api().await {
+            Ok(()) => diagnostics.ome_api = "ok".into(),
+            Err(err) => {
+                tracing::warn!("{err:#}");
+                diagnostics.ome_api = format!("{err:#}");
+            }
+        }
+        diagnostics.ome_api_latency_ms = Some(start.elapsed().as_millis());
+        // The version seen at startup is still reported while OME is unreachable
+        diagnostics.ome_version = state.ome_version();
+    }
+
+    Json(diagnostics)
+}
+
+#[tracing::instrument(skip(_permission, state))]
+async fn streamer_sessions(
+    _permission: RequiredPermission<scope::ReadMetrics>,
+    State(state): State<Arc<OvenCtrlState>>,
+    Path(name): Path<String>,
+) -> Result<Json<Vec<IngestSession>>, StatusCode> {
+    if !state.config().streamers.contains_key(&name) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    Ok(Json(state.ingest_sessions.of_streamer(&name)))
+}
+
+#[tracing::instrument(skip(_permission, state))]
+async fn streamer_attempts(
+    _permission: RequiredPermission<scope::ReadMetrics>,
+    State(state): State<Arc<OvenCtrlState>>,
+    Path(name): Path<String>,
+) -> Result<Json<Vec<ConnectionAttempt>>, StatusCode> {
+    if !state.config().streamers.contains_key(&name) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    Ok(Json(state.ingest_sessions.attempts_of(&name)))
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct RoomOverview {
+    pub room: String,
+    pub has_capacity_limit: bool,
+    /// Maximum number of viewers of the room, if it is limited
+    pub capacity: Option<u64>,
+    pub active_viewers: u64,
+    /// Number of streamers allowed to stream to the room
+    pub streamer_count: usize,
+    pub categories: Vec<String>,
+}
+
+/// Configured rooms, sorted by name
+pub fn room_overviews(state: &OvenCtrlState) -> Vec<RoomOverview> {
+    let config = state.config();
+
+    let mut rooms = config
+        .rooms
+        .keys()
+        .map(|room| {
+            let capacity = config
+                .room_config
+                .get(room)
+                .and_then(|room_config| room_config.max_viewers);
+
+            RoomOverview {
+                room: room.clone(),
+                has_capacity_limit: capacity.is_some(),
+                capacity,
+                active_viewers: state.viewers.get(room),
+                streamer_count: config
+                    .allowed_streams
+                    .values()
+                    .filter(|rooms| rooms.contains(room))
+                    .count(),
+                categories: vec![config.room_category(room).to_owned()],
+            }
+        })
+        .collect::<Vec<_>>();
+    rooms.sort_by(|a, b| a.room.cmp(&b.room));
+    rooms
+}
+
+#[tracing::instrument(skip(_permission, state))]
+async fn rooms(
+    _permission: RequiredPermission<scope::ReadConfig>,
+    State(state): State<Arc<OvenCtrlState>>,
+) -> Json<Vec<RoomOverview>> {
+    Json(room_overviews(&state))
+}
+
+/// Runtime state carried over restarts of the controller
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+struct RuntimeSnapshot {
+    viewers: HashMap<String, u64>,
+    ingest_sessions: Vec<IngestSession>,
+    metrics: AdmissionMetricsSnapshot,
+}
+
+#[tracing::instrument(skip(_permission, state))]
+async fn state_snapshot(
+    _permission: RequiredPermission<scope::ManageState>,
+    State(state): State<Arc<OvenCtrlState>>,
+) -> Json<RuntimeSnapshot> {
+    let _runtime = state.runtime_lock.read().unwrap();
+
+    Json(RuntimeSnapshot {
+        viewers: state.viewers.snapshot(),
+        ingest_sessions: state.ingest_sessions.all(),
+        metrics: state.metrics.snapshot(),
+    })
+}
+
+#[tracing::instrument(skip(_permission, state, snapshot))]
+async fn state_restore(
+    _permission: RequiredPermission<scope::ManageState>,
+    State(state): State<Arc<OvenCtrlState>>,
+    Json(snapshot): Json<RuntimeSnapshot>,
+) -> StatusCode {
+    let _runtime = state.runtime_lock.write().unwrap();
+
+    tracing::info!(
+        "Restoring {} ingest sessions and the viewers of {} rooms",
+        snapshot.ingest_sessions.len(),
+        snapshot.viewers.len()
+    );
+    state.viewers.restore(snapshot.viewers);
+    state.ingest_sessions.restore(snapshot.ingest_sessions);
+    state.metrics.restore(snapshot.metrics);
+
+    StatusCode::NO_CONTENT
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct EventsQuery {
+    /// Unix timestamp of the oldest event to return
+    since: Option<i64>,
+    limit: Option<usize>,
+}
+
+/// Number of events returned by `/api/events` when no limit is given
+const EVENTS_DEFAULT_LIMIT: usize = 100;
+const EVENTS_MAX_LIMIT: usize = 1000;
+
+#[derive(serde::Deserialize, Debug)]
+struct EventsPageQuery {
+    /// Sequence number of the last event already received
+    #[serde(default)]
+    after: u64,
+    limit: Option<usize>,
+    room: Option<String>,
+    /// Oldest event to return, in RFC 3339
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    since: Option<OffsetDateTime>,
+    outcome: Option<Outcome>,
+}
+
+/// Page of the admission events, read from the audit database when one is configured and from
+/// the in-memory event log otherwise
+#[tracing::instrument(skip(_permission, state))]
+async fn events(
+    _permission: RequiredPermission<scope::ReadMetrics>,
+    State(state): State<Arc<OvenCtrlState>>,
+    Query(query): Query<EventsPageQuery>,
+) -> Result<Json<EventPage>, StatusCode> {
+    let limit = query
+        .limit
+        .unwrap_or(EVENTS_DEFAULT_LIMIT)
+        .min(EVENTS_MAX_LIMIT);
+    let filter = EventFilter {
+        room: query.room,
+        since: query.since,
+        outcome: query.outcome,
+    };
+
+    match &state.audit_db {
+        Some(audit_db) => audit_db
+            .page(query.after, limit, &filter)
+            .await
+            .map(Json)
+            .map_err(|err| {
+                tracing::error!("Could not read the audit database: {err:#}");
+                StatusCode::INTERNAL_SERVER_ERROR
+            }),
+        None => Ok(Json(state.events.page(query.after, limit, &filter))),
+    }
+}
+
+/// Stream admission events as newline delimited JSON (sent with chunked encoding)
+#[tracing::instrument(skip(_permission, state))]
+async fn events_ndjson(
+    _permission: RequiredPermission<scope::ReadMetrics>,
+    State(state): State<Arc<OvenCtrlState>>,
+    Query(query): Query<EventsQuery>,
+) -> Response {
+    let events = state.events.query(query.since, query.limit);
+
+    let lines = futures_util::stream::iter(events).map(|event| {
+        let mut line = serde_json::to_vec(&event)?;
+        line.push(b'\n');
+        Ok::<_, serde_json::Error>(line)
+    });
+
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(lines),
+    )
+        .into_response()
+}
+
+/// Administration endpoints, only available when `admin_token` or `admin_tokens` is configured
+pub fn router() -> Router<Arc<OvenCtrlState>> {
+    let api = Router::new()
+        .route("/export", get(export))
+        .route("/import", post(import))
+        .route("/metrics/admission", get(admission_metrics))
+        .route("/metrics/analytics", get(analytics::summary))
+        .route("/events", get(events))
+        .route("/events/ndjson", get(events_ndjson))
+        .route("/logs/stream", get(log_tail::stream))
+        .route("/rooms", get(rooms))
+        .route("/rooms/:room/invite", post(invites::create))
+        .route("/rooms/:room/token-revoke", post(invites::revoke))
+        .route("/streamers/:name/sessions", get(streamer_sessions))
+        .route("/streamers/:name/attempts", get(streamer_attempts))
+        .route("/streamers/:name/quota", get(quota::status))
+        .route("/stream-events/subscribe", post(stream_events::subscribe))
+        .route("/test-webhook", post(stream_events::test))
+        .route(
+            "/stream-events/:subscription_id",
+            delete(stream_events::unsubscribe),
+        )
+        .route("/ws", get(admin_ws::upgrade))
+        .route("/state/snapshot", get(state_snapshot))
+        .route("/state/restore", post(state_restore));
+
+    Router::new()
+        .nest("/api", api)
+        .route("/diagnostics", get(diagnostics))
+}
@@ -0,0 +1,285 @@
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    sync::Arc,
+};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    http::StatusCode,
+    response::Response,
+};
+use serde_json::Value;
+
+use crate::{
+    admin::{
+        self, apply_import, authorize, has_admin_tokens, reject_env_keys, Import, MergeMode,
+        Permission,
+    },
+    OvenCtrlState, RoomPasswordList,
+};
+
+#[derive(serde::Deserialize, Debug)]
+pub(crate) struct WsQuery {
+    token: String,
+}
+
+/// Commands accepted on the administration WebSocket, one JSON object per text frame
+#[derive(serde::Deserialize, Debug)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+enum Command {
+    Export,
+    Import {
+        #[serde(default)]
+        merge_mode: MergeMode,
+        #[serde(flatten)]
+        import: Import,
+    },
+    AddStreamer {
+        name: String,
+        key: String,
+    },
+    RemoveStreamer {
+        name: String,
+    },
+    AddRoom {
+        name: String,
+        passwords: RoomPasswordList,
+    },
+    RemoveRoom {
+        name: String,
+    },
+    AllowStream {
+        streamer: String,
+        room: String,
+    },
+    DisallowStream {
+        streamer: String,
+        room: String,
+    },
+    ListRooms,
+    StreamerSessions {
+        name: String,
+    },
+    AdmissionMetrics,
+    Events {
+        since: Option<i64>,
+        limit: Option<usize>,
+    },
+}
+
+impl Command {
+    fn permission(&self) -> Permission {
+        match self {
+            Command::Export | Command::ListRooms => Permission::ReadConfig,
+            // Imports also require WriteRooms, checked when executing them
+            Command::Import { .. }
+            | Command::AddStreamer { .. }
+            | Command::RemoveStreamer { .. } => Permission::WriteStreamers,
+            Command::AddRoom { .. }
+            | Command::RemoveRoom { .. }
+            | Command::AllowStream { .. }
+            | Command::DisallowStream { .. } => Permission::WriteRooms,
+            Command::StreamerSessions { .. }
+            | Command::AdmissionMetrics
+            | Command::Events { .. } => Permission::ReadMetrics,
+        }
+    }
+}
+
+#[derive(serde::Serialize, Debug)]
+struct Reply {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Value>,
+}
+
+impl Reply {
+    fn error(error: impl Into<String>) -> Self {
+        Self {
+            ok: false,
+            error: Some(error.into()),
+            data: None,
+        }
+    }
+}
+
+/// Insert a new entry, failing when `name` already exists
+fn insert_new<V>(
+    entries: &mut HashMap<String, V>,
+    kind: &str,
+    name: String,
+    value: V,
+) -> anyhow::Result<()> {
+    match entries.entry(name) {
+        Entry::Vacant(entry) => {
+            entry.insert(value);
+            Ok(())
+        }
+        Entry::Occupied(entry) => anyhow::bail!("{kind} {} already exists", entry.key()),
+    }
+}
+
+fn execute(state: &OvenCtrlState, token: &str, command: Command) -> anyhow::Result<Option<Value>> {
+    let config = state.config();
+
+    let mut authorized = authorize(&config, token, Some(command.permission()));
+    if matches!(command, Command::Import { .. }) {
+        authorized =
+            authorized.and_then(|()| authorize(&config, token, Some(Permission::WriteRooms)));
+    }
+    match authorized {
+        Ok(()) => (),
+        Err(StatusCode::FORBIDDEN) => anyhow::bail!("missing permission"),
+        Err(_) => anyhow::bail!("invalid admin token"),
+    }
+
+    let data = match command {
+        Command::Export => Some(serde_json::to_value(config.redacted())?),
+        Command::Import { merge_mode, import } => {
+            let summary = state.update_config(|config| apply_import(config, merge_mode, import))?;
+            Some(serde_json::to_value(summary)?)
+        }
+        Command::AddStreamer { name, key } => {
+            state.update_config(|config| {
+                insert_new(&mut config.streamers, "streamer", name, key)?;
+                reject_env_keys(config)
+            })?;
+            None
+        }
+        Command::RemoveStreamer { name } => {
+            state.update_config(|config| {
+                if config.streamers.remove(&name).is_none() {
+                    anyhow::bail!("unknown streamer {name}");
+                }
+                config.allowed_streams.remove(&name);
+                Ok(())
+            })?;
+            None
+        }
+        Command::AddRoom { name, passwords } => {
+            state.update_config(|config| insert_new(&mut config.rooms, "room", name, passwords))?;
+            None
+        }
+        Command::RemoveRoom { name } => {
+            state.update_config(|config| {
+                if config.rooms.remove(&name).is_none() {
+                    anyhow::bail!("unknown room {name}");
+                }
+                for rooms in config.allowed_streams.values_mut() {
+                    rooms.remove(&name);
+                }
+                Ok(())
+            })?;
+            None
+        }
+        Command::AllowStream { streamer, room } => {
+            state.update_config(|config| {
+                if !config.streamers.contains_key(&streamer) {
+                    anyhow::bail!("unknown streamer {streamer}");
+                }
+                if !config.rooms.contains_key(&room) {
+                    anyhow::bail!("unknown room {room}");
+                }
+                config
+                    .allowed_streams
+                    .entry(streamer)
+                    .or_insert_with(HashSet::new)
+                    .insert(room);
+                Ok(())
+            })?;
+            None
+        }
+        Command::DisallowStream { streamer, room } => {
+            state.update_config(|config| {
+                let removed = config
+                    .allowed_streams
+                    .get_mut(&streamer)
+                    .is_some_and(|rooms| rooms.remove(&room));
+                if !removed {
+                    anyhow::bail!("streamer {streamer} is not allowed to stream to {room}");
+                }
+                Ok(())
+            })?;
+            None
+        }
+        Command::ListRooms => Some(serde_json::to_value(admin::room_overviews(state))?),
+        Command::StreamerSessions { name } => {
+            if !config.streamers.contains_key(&name) {
+                anyhow::bail!("unknown streamer {name}");
+            }
+            Some(serde_json::to_value(
+                state.ingest_sessions.of_streamer(&name),
+            )?)
+        }
+        Command::AdmissionMetrics => Some(serde_json::to_value(state.metrics.snapshot())?),
+        Command::Events { since, limit } => {
+            Some(serde_json::to_value(state.events.query(since, limit))?)
+        }
+    };
+
+    Ok(data)
+}
+
+fn reply(state: &OvenCtrlState, token: &str, message: &str) -> Reply {
+    let command = match serde_json::from_str::<Command>(message) {
+        Ok(command) => command,
+        Err(err) => return Reply::error(format!("invalid command: {err}")),
+    };
+
+    match execute(state, token, command) {
+        Ok(data) => Reply {
+            ok: true,
+            error: None,
+            data,
+        },
+        Err(err) => Reply::error(format!("{err:#}")),
+    }
+}
+
+async fn handle_socket(state: Arc<OvenCtrlState>, token: String, mut socket: WebSocket) {
+    while let Some(message) = socket.recv().await {
+        let reply = match message {
+            Ok(Message::Text(text)) => reply(&state, &token, &text),
+            Ok(Message::Binary(_)) => Reply::error("commands must be sent as text frames"),
+            // Pings are answered by axum
+            Ok(Message::Ping(_) | Message::Pong(_)) => continue,
+            Ok(Message::Close(_)) => break,
+            Err(err) => {
+                tracing::debug!("Administration WebSocket error: {err}");
+                break;
+            }
+        };
+
+        let reply = match serde_json::to_string(&reply) {
+            Ok(reply) => reply,
+            Err(err) => {
+                tracing::error!("Could not serialize administration reply: {err}");
+                break;
+            }
+        };
+        if socket.send(Message::Text(reply)).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Open the administration WebSocket, authenticated by the `token` query parameter
+#[tracing::instrument(skip_all)]
+pub(crate) async fn upgrade(
+    State(state): State<Arc<OvenCtrlState>>,
+    Query(query): Query<WsQuery>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, StatusCode> {
+    let config = state.config();
+    if !has_admin_tokens(&config) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    authorize(&config, &query.token, None)?;
+
+    Ok(ws.on_upgrade(move |socket| handle_socket(state, query.token, socket)))
+}
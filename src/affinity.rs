@@ -0,0 +1,65 @@
+use ovenctrl_macros::ConfigDoc;
+use sha2::{Digest, Sha256};
+
+/// Cookie binding LLHLS viewers to an OME origin, for load balancers routing on it
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, ConfigDoc)]
+pub struct AffinityCookieConfig {
+    /// Name of the cookie the load balancer routes on
+    pub cookie_name: String,
+    /// Lifetime of the cookie
+    pub ttl_secs: u64,
+    /// OME origins the viewers are spread across, as named in the load balancer
+    pub instances: Vec<String>,
+}
+
+/// Origin selected for a viewer, returned with the admission response
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct AffinityHint {
+    pub instance: String,
+    /// Value of the `Set-Cookie` header binding the viewer to `instance`
+    pub set_cookie: String,
+}
+
+fn weight(instance: &str, client_ip: &str) -> u64 {
+    let digest = Sha256::new()
+        .chain_update(instance)
+        .chain_update([0])
+        .chain_update(client_ip)
+        .finalize();
+
+    u64::from_be_bytes(
+        digest[..8]
+            .try_into()
+            .expect("digest is longer than 8 bytes"),
+    )
+}
+
+impl AffinityCookieConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.instances.is_empty() {
+            anyhow::bail!("llhls_affinity_cookie requires at least one instance");
+        }
+        Ok(())
+    }
+
+    /// Instance of a viewer, chosen by rendezvous hashing: adding or removing an instance only
+    /// moves the viewers of that instance
+    fn instance(&self, client_ip: &str) -> Option<&str> {
+        self.instances
+            .iter()
+            .max_by_key(|instance| weight(instance, client_ip))
+            .map(String::as_str)
+    }
+
+    pub fn hint(&self, client_ip: &str) -> Option<AffinityHint> {
+        let instance = self.instance(client_ip)?;
+
+        Some(AffinityHint {
+            instance: instance.to_owned(),
+            set_cookie: format!(
+                "{}={instance}; Max-Age={}; Path=/; HttpOnly",
+                self.cookie_name, self.ttl_secs
+            ),
+        })
+    }
+}
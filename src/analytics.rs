@@ -0,0 +1,171 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::StatusCode,
+    Json,
+};
+use dashmap::DashMap;
+use time::OffsetDateTime;
+
+use crate::{
+    admin::{scope, RequiredPermission},
+    rate_limit::{RateLimitConfig, TokenBucket},
+    OvenCtrlState,
+};
+
+/// Events a viewer page can send in a burst, then one every second
+const RATE_LIMIT: RateLimitConfig = RateLimitConfig {
+    capacity: 20.,
+    refill_rate: 1.,
+};
+/// Time after which the rate limit of an address is forgotten, its bucket being full again
+const RATE_LIMIT_IDLE: Duration = Duration::from_secs(60);
+const MAX_SESSION_ID_LENGTH: usize = 64;
+
+/// State changes of the player reported by the viewer pages
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum PlayerEvent {
+    Play,
+    Pause,
+    Error,
+    Complete,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+pub struct AnalyticsEvent {
+    pub event: PlayerEvent,
+    pub room: String,
+    /// Random identifier of the page that sent the event
+    pub session_id: String,
+    /// Time of the event, according to the viewer
+    #[serde(with = "time::serde::rfc3339")]
+    pub timestamp: OffsetDateTime,
+}
+
+/// Latest player events sent by the viewer pages
+#[derive(Debug)]
+pub struct PlayerAnalytics {
+    capacity: usize,
+    events: Mutex<VecDeque<AnalyticsEvent>>,
+    rate_limits: DashMap<IpAddr, TokenBucket>,
+}
+
+impl PlayerAnalytics {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            events: Mutex::new(VecDeque::with_capacity(capacity)),
+            rate_limits: DashMap::new(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.capacity != 0
+    }
+
+    fn push(&self, event: AnalyticsEvent) {
+        let mut events = self.events.lock().unwrap();
+        if events.len() == self.capacity {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    /// Forget the addresses that did not send events recently
+    pub fn cleanup(&self) {
+        self.rate_limits
+            .retain(|_, bucket| bucket.last_used().elapsed() < RATE_LIMIT_IDLE);
+    }
+
+    pub fn summary(&self) -> AnalyticsSummary {
+        let events = self.events.lock().unwrap();
+
+        let mut summary = AnalyticsSummary {
+            events: events.len(),
+            by_event: HashMap::new(),
+            by_room: HashMap::new(),
+        };
+        let mut sessions = HashMap::<&str, HashSet<&str>>::new();
+        for event in events.iter() {
+            *summary.by_event.entry(event.event).or_default() += 1;
+
+            let room = summary.by_room.entry(event.room.clone()).or_default();
+            *room.by_event.entry(event.event).or_default() += 1;
+            sessions
+                .entry(&event.room)
+                .or_default()
+                .insert(&event.session_id);
+        }
+        for (room, sessions) in sessions {
+            summary.by_room.get_mut(room).unwrap().sessions = sessions.len();
+        }
+
+        summary
+    }
+}
+
+#[derive(serde::Serialize, Debug, Default)]
+pub struct RoomAnalytics {
+    pub by_event: HashMap<PlayerEvent, u64>,
+    /// Number of distinct viewer pages
+    pub sessions: usize,
+}
+
+/// Aggregates of the events kept in memory
+#[derive(serde::Serialize, Debug)]
+pub struct AnalyticsSummary {
+    pub events: usize,
+    pub by_event: HashMap<PlayerEvent, u64>,
+    pub by_room: HashMap<String, RoomAnalytics>,
+}
+
+/// Record an event sent by the player of a viewer page
+#[tracing::instrument(skip(state, source, event))]
+pub(crate) async fn record(
+    State(state): State<Arc<OvenCtrlState>>,
+    source: Option<ConnectInfo<SocketAddr>>,
+    Json(event): Json<AnalyticsEvent>,
+) -> Result<StatusCode, (StatusCode, &'static str)> {
+    let analytics = &state.analytics;
+    if !analytics.enabled() {
+        return Err((StatusCode::NOT_FOUND, "analytics are disabled"));
+    }
+
+    let ip = source
+        .map(|ConnectInfo(source)| source.ip())
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    let allowed = analytics
+        .rate_limits
+        .entry(ip)
+        .or_insert_with(|| TokenBucket::new(RATE_LIMIT))
+        .try_consume();
+    if !allowed {
+        return Err((StatusCode::TOO_MANY_REQUESTS, "too many events"));
+    }
+
+    if event.session_id.len() > MAX_SESSION_ID_LENGTH {
+        return Err((StatusCode::BAD_REQUEST, "session_id is too long"));
+    }
+    if !state.config().rooms.contains_key(&event.room) {
+        return Err((StatusCode::NOT_FOUND, "unknown room"));
+    }
+
+    analytics.push(event);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[tracing::instrument(skip(_permission, state))]
+pub(crate) async fn summary(
+    _permission: RequiredPermission<scope::ReadMetrics>,
+    State(state): State<Arc<OvenCtrlState>>,
+) -> Json<AnalyticsSummary> {
+    Json(state.analytics.summary())
+}
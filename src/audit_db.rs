@@ -0,0 +1,168 @@
+use std::path::Path;
+
+use anyhow::Context;
+use serde::{de::DeserializeOwned, Serialize};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePool, SqliteRow},
+    QueryBuilder, Row, Sqlite,
+};
+use time::OffsetDateTime;
+use tokio::sync::mpsc;
+
+use crate::events::{AdmissionEvent, EventFilter, EventPage, Outcome};
+
+/// Persistent audit log of the admission events, stored in a SQLite database
+#[derive(Debug, Clone)]
+pub struct AuditDb {
+    pool: SqlitePool,
+    /// Events waiting to be written, in the order they were recorded
+    writer: mpsc::UnboundedSender<AdmissionEvent>,
+}
+
+/// Name of a unit enum variant, as serialized by serde
+fn variant_name<T: Serialize>(value: &T) -> anyhow::Result<String> {
+    match serde_json::to_value(value)? {
+        serde_json::Value::String(name) => Ok(name),
+        v => anyhow::bail!("{v} is not a unit variant"),
+    }
+}
+
+fn from_variant_name<T: DeserializeOwned>(name: String) -> anyhow::Result<T> {
+    Ok(serde_json::from_value(serde_json::Value::String(name))?)
+}
+
+fn unix_millis(timestamp: OffsetDateTime) -> i64 {
+    (timestamp.unix_timestamp_nanos() / 1_000_000) as i64
+}
+
+fn event_from_row(row: SqliteRow) -> anyhow::Result<AdmissionEvent> {
+    let timestamp: i64 = row.try_get("timestamp")?;
+    let outcome: Option<String> = row.try_get("outcome")?;
+
+    Ok(AdmissionEvent {
+        seq: row.try_get::<i64, _>("id")? as u64,
+        timestamp: OffsetDateTime::from_unix_timestamp_nanos(timestamp as i128 * 1_000_000)?,
+        direction: from_variant_name(row.try_get("direction")?)?,
+        protocol: from_variant_name(row.try_get("protocol")?)?,
+        status: from_variant_name(row.try_get("status")?)?,
+        app: row.try_get("app")?,
+        room: row.try_get("room")?,
+        streamer: row.try_get("streamer")?,
+        client_address: row.try_get("client_address")?,
+        allowed: outcome
+            .map(from_variant_name)
+            .transpose()?
+            .map(|outcome: Outcome| outcome == Outcome::Allowed),
+        reason: row.try_get("reason")?,
+        sampled: row.try_get("sampled")?,
+    })
+}
+
+async fn insert(pool: &SqlitePool, event: &AdmissionEvent) -> anyhow::Result<()> {
+    sqlx::query(
+        "INSERT INTO admission_events \
+         (timestamp, direction, protocol, status, app, room, streamer, client_address, outcome, reason, sampled) \
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(unix_millis(event.timestamp))
+    .bind(variant_name(&event.direction)?)
+    .bind(variant_name(&event.protocol)?)
+    .bind(variant_name(&event.status)?)
+    .bind(&event.app)
+    .bind(&event.room)
+    .bind(&event.streamer)
+    .bind(&event.client_address)
+    .bind(event.outcome().map(Outcome::as_str))
+    .bind(&event.reason)
+    .bind(event.sampled)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+impl AuditDb {
+    /// Open (or create) the database at `path`, bring its schema up to date, and spawn the task
+    /// writing the recorded events
+    pub async fn open(path: &Path) -> anyhow::Result<Self> {
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true);
+
+        let pool = SqlitePool::connect_with(options)
+            .await
+            .with_context(|| format!("could not open the audit database {}", path.display()))?;
+
+        sqlx::migrate!()
+            .run(&pool)
+            .await
+            .context("could not migrate the audit database")?;
+
+        let (writer, mut events) = mpsc::unbounded_channel::<AdmissionEvent>();
+        let db = Self { pool, writer };
+
+        let pool = db.pool.clone();
+        tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                if let Err(err) = insert(&pool, &event).await {
+                    tracing::error!(
+                        "Could not write the admission event to the audit database: {err:#}"
+                    );
+                }
+            }
+        });
+
+        Ok(db)
+    }
+
+    /// Queue an event to be written to the database
+    pub fn record(&self, event: AdmissionEvent) {
+        // The writer only stops once all the senders are dropped
+        let _ = self.writer.send(event);
+    }
+
+    /// At most `limit` events matching `filter` with an id greater than `after`, oldest first
+    pub async fn page(
+        &self,
+        after: u64,
+        limit: usize,
+        filter: &EventFilter,
+    ) -> anyhow::Result<EventPage> {
+        let mut query = QueryBuilder::<Sqlite>::new("SELECT * FROM admission_events WHERE id > ");
+        query.push_bind(after as i64);
+
+        if let Some(room) = &filter.room {
+            query.push(" AND room = ").push_bind(room);
+        }
+        if let Some(since) = filter.since {
+            query
+                .push(" AND timestamp >= ")
+                .push_bind(unix_millis(since));
+        }
+        if let Some(outcome) = filter.outcome {
+            query.push(" AND outcome = ").push_bind(outcome.as_str());
+        }
+
+        // Fetch one more event to know if there is a next page
+        query
+            .push(" ORDER BY id LIMIT ")
+            .push_bind(limit as i64 + 1);
+
+        let mut events = query
+            .build()
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(event_from_row)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let has_more = events.len() > limit;
+        events.truncate(limit);
+
+        Ok(EventPage {
+            next_cursor: events.last().map(|event| event.seq).unwrap_or(after),
+            events,
+            has_more,
+        })
+    }
+}
@@ -0,0 +1,121 @@
+use std::{
+    collections::HashSet,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use ipnet::IpNet;
+use ovenctrl_macros::ConfigDoc;
+
+use crate::OvenAdmission;
+
+/// Window over which the addresses sharing a user agent are counted
+const USER_AGENT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Heuristics denying the viewers that look automated
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Default, ConfigDoc)]
+pub struct BotDetectionConfig {
+    /// Deny the viewers connecting from `datacenter_networks`
+    #[serde(default)]
+    pub block_datacenter_ips: bool,
+    /// Networks of the hosting providers, e.g. exported from an ASN database
+    #[serde(default)]
+    pub datacenter_networks: Vec<IpNet>,
+    /// User agents denied access, `*` matching any sequence of characters
+    #[serde(default)]
+    pub blocked_user_agents: Vec<String>,
+    /// Number of addresses that can connect with the same user agent each minute
+    pub max_identical_ua_per_minute: Option<usize>,
+}
+
+/// Whether `text` matches `pattern`, where `*` matches any sequence of characters
+pub(crate) fn glob_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+
+    let mut parts = parts.collect::<Vec<_>>();
+    let Some(last) = parts.pop() else {
+        // No `*` in the pattern
+        return rest.is_empty();
+    };
+
+    for part in parts {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.ends_with(last)
+}
+
+#[derive(Debug)]
+struct UserAgentWindow {
+    start: Instant,
+    addresses: HashSet<String>,
+}
+
+/// Addresses seen recently with each user agent
+#[derive(Debug, Default)]
+pub struct BotDetector {
+    user_agents: DashMap<String, UserAgentWindow>,
+}
+
+impl BotDetector {
+    /// Reason to consider the viewer of `payload` to be a bot
+    pub fn detect(&self, config: &BotDetectionConfig, payload: &OvenAdmission) -> Option<String> {
+        let client = &payload.client;
+
+        if config.block_datacenter_ips {
+            if let Ok(address) = client.address.parse::<IpAddr>() {
+                if let Some(net) = config
+                    .datacenter_networks
+                    .iter()
+                    .find(|net| net.contains(&address))
+                {
+                    return Some(format!("address in datacenter network {net}"));
+                }
+            }
+        }
+
+        if let Some(pattern) = config
+            .blocked_user_agents
+            .iter()
+            .find(|pattern| glob_match(pattern, &client.user_agent))
+        {
+            return Some(format!("user agent matches {pattern:?}"));
+        }
+
+        let max = config.max_identical_ua_per_minute?;
+        let mut window = self
+            .user_agents
+            .entry(client.user_agent.clone())
+            .or_insert_with(|| UserAgentWindow {
+                start: Instant::now(),
+                addresses: HashSet::new(),
+            });
+        if window.start.elapsed() >= USER_AGENT_WINDOW {
+            window.start = Instant::now();
+            window.addresses.clear();
+        }
+
+        if !window.addresses.contains(&client.address) && window.addresses.len() >= max {
+            return Some(format!(
+                "user agent used by more than {max} addresses in the last minute"
+            ));
+        }
+        window.addresses.insert(client.address.clone());
+
+        None
+    }
+
+    /// Forget the user agents that were not seen during the last window
+    pub fn cleanup(&self) {
+        self.user_agents
+            .retain(|_, window| window.start.elapsed() < USER_AGENT_WINDOW);
+    }
+}
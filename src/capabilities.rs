@@ -0,0 +1,101 @@
+use std::{collections::HashSet, net::IpAddr};
+
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use dashmap::DashMap;
+
+/// Header of the admission responses advertising the features of the controller
+pub const OVENCTRL_CAPABILITIES: HeaderName = HeaderName::from_static("x-ovenctrl-capabilities");
+/// Header of the admission requests advertising the features of OME
+pub const OME_CAPABILITIES: HeaderName = HeaderName::from_static("x-ome-capabilities");
+
+const VERSION: u32 = 1;
+/// Features of the admission responses that OME can act on
+const FEATURES: &[&str] = &["ip-filter", "rate-limit", "new-url", "lifetime"];
+
+/// Value of the [OVENCTRL_CAPABILITIES] header
+pub fn advertised() -> HeaderValue {
+    HeaderValue::try_from(format!("version={VERSION},features={}", FEATURES.join(",")))
+        .expect("the capabilities are valid header characters")
+}
+
+/// Capabilities reported by an OME server, in the format of [OVENCTRL_CAPABILITIES]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OmeCapabilities {
+    pub version: Option<u32>,
+    pub features: HashSet<String>,
+}
+
+impl OmeCapabilities {
+    /// Parse `version=<n>,features=<a>,<b>`, the items without a key continuing the list of
+    /// features
+    pub fn parse(value: &str) -> Self {
+        let mut capabilities = Self {
+            version: None,
+            features: HashSet::new(),
+        };
+
+        let mut in_features = false;
+        for item in value.split(',').map(str::trim) {
+            let feature = match item.split_once('=') {
+                Some(("version", version)) => {
+                    in_features = false;
+                    capabilities.version = version.trim().parse().ok();
+                    continue;
+                }
+                Some(("features", feature)) => {
+                    in_features = true;
+                    feature
+                }
+                Some(_) => {
+                    in_features = false;
+                    continue;
+                }
+                None if in_features => item,
+                None => continue,
+            };
+
+            if !feature.is_empty() {
+                capabilities.features.insert(feature.to_owned());
+            }
+        }
+
+        capabilities
+    }
+
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.contains(feature)
+    }
+}
+
+/// Capabilities of each OME server, by the address the admission requests come from
+#[derive(Debug, Default)]
+pub struct NegotiatedCapabilities {
+    by_source: DashMap<IpAddr, OmeCapabilities>,
+}
+
+impl NegotiatedCapabilities {
+    /// Capabilities of the OME server sending an admission request, `None` for the servers that
+    /// never reported any
+    pub fn negotiate(
+        &self,
+        source: Option<IpAddr>,
+        headers: &HeaderMap,
+    ) -> Option<OmeCapabilities> {
+        let reported = headers
+            .get(OME_CAPABILITIES)
+            .and_then(|value| value.to_str().ok())
+            .map(OmeCapabilities::parse);
+
+        match (source, reported) {
+            (Some(source), Some(reported)) => {
+                if self.by_source.get(&source).as_deref() != Some(&reported) {
+                    tracing::info!("OME at {source} reported capabilities {reported:?}");
+                    self.by_source.insert(source, reported.clone());
+                }
+                Some(reported)
+            }
+            (None, reported) => reported,
+            (Some(source), None) => self.by_source.get(&source).map(|caps| caps.clone()),
+        }
+    }
+}
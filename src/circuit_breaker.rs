@@ -0,0 +1,116 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Closed {
+        failures: u32,
+    },
+    /// Requests are rejected until `until`
+    Open {
+        until: Instant,
+    },
+    /// The open period elapsed, a single trial request started at `since` decides whether the
+    /// circuit closes again
+    HalfOpen {
+        since: Instant,
+    },
+}
+
+/// Stop sending requests to a service after consecutive failures, instead of piling up requests
+/// that are bound to fail while it is down
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    name: &'static str,
+    failure_threshold: u32,
+    open_duration: Duration,
+    state: Mutex<State>,
+}
+
+impl CircuitBreaker {
+    pub fn new(name: &'static str, failure_threshold: u32, open_duration: Duration) -> Self {
+        Self {
+            name,
+            failure_threshold,
+            open_duration,
+            state: Mutex::new(State::Closed { failures: 0 }),
+        }
+    }
+
+    /// Whether the requests are currently rejected
+    pub fn is_open(&self) -> bool {
+        matches!(*self.state.lock().unwrap(), State::Open { until } if Instant::now() < until)
+    }
+
+    /// Check whether a request can be sent
+    pub fn check(&self) -> anyhow::Result<()> {
+        let mut state = self.state.lock().unwrap();
+
+        match *state {
+            State::Open { until } if Instant::now() < until => {
+                anyhow::bail!("circuit breaker of the {} is open", self.name)
+            }
+            // The trial request may have been cancelled before recording its result, in which
+            // case another one is tried after the open period
+            State::HalfOpen { since } if since.elapsed() < self.open_duration => {
+                anyhow::bail!(
+                    "circuit breaker of the {} is half-open, waiting for the trial request",
+                    self.name
+                )
+            }
+            State::Open { .. } | State::HalfOpen { .. } => {
+                tracing::warn!(
+                    "Circuit breaker of the {} is half-open, trying a request",
+                    self.name
+                );
+                *state = State::HalfOpen {
+                    since: Instant::now(),
+                };
+            }
+            State::Closed { .. } => (),
+        }
+
+        Ok(())
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+
+        if !matches!(*state, State::Closed { .. }) {
+            tracing::warn!("Circuit breaker of the {} is closed", self.name);
+        }
+        *state = State::Closed { failures: 0 };
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+
+        match *state {
+            State::Closed { failures } if failures + 1 < self.failure_threshold => {
+                *state = State::Closed {
+                    failures: failures + 1,
+                };
+                return;
+            }
+            State::Closed { failures } => tracing::warn!(
+                "Circuit breaker of the {} is open for {:?} after {} consecutive failures",
+                self.name,
+                self.open_duration,
+                failures + 1,
+            ),
+            State::HalfOpen { .. } => tracing::warn!(
+                "Circuit breaker of the {} is open again for {:?}, the trial request failed",
+                self.name,
+                self.open_duration,
+            ),
+            // Requests sent before the circuit opened
+            State::Open { .. } => return,
+        }
+
+        *state = State::Open {
+            until: Instant::now() + self.open_duration,
+        };
+    }
+}
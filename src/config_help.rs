@@ -0,0 +1,89 @@
+use std::{collections::HashMap, time::Duration};
+
+use config::{builder::DefaultState, ConfigBuilder, ConfigError};
+use serde::Serialize;
+
+/// Prefix of the environment variables setting the configuration
+pub const ENV_PREFIX: &str = "OVEN_CTRL";
+
+/// Documentation of a field of [`OvenCtrlConfig`](crate::OvenCtrlConfig) or of one of its
+/// tables, generated by `#[derive(ConfigDoc)]`
+#[derive(Debug, Clone)]
+pub struct ConfigField {
+    pub name: &'static str,
+    pub ty: &'static str,
+    pub default: String,
+    pub description: &'static str,
+    /// Fields of the tables of this field, empty for the other types
+    pub fields: Vec<ConfigField>,
+}
+
+/// Text of the default value of a field: `none` when it is unset, `empty` for the empty tables and
+/// arrays
+pub fn display_default<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::Null) => "none".into(),
+        Ok(serde_json::Value::String(value)) => value,
+        Ok(serde_json::Value::Array(values)) if values.is_empty() => "empty".into(),
+        Ok(serde_json::Value::Object(values)) if values.is_empty() => "empty".into(),
+        Ok(value) => value.to_string(),
+        Err(_) => "none".into(),
+    }
+}
+
+/// Sources reading the configuration from the `OVEN_CTRL_*` environment variables, or from `vars`
+/// when set, with their names.
+///
+/// The underscores are read both as separators of the nested keys, as they always were (e.g.
+/// `OVEN_CTRL_STREAMERS_alice` sets the key of the streamer `alice`), and as part of the top-level
+/// keys (e.g. `OVEN_CTRL_EXTERNAL_HOST`). The unknown keys of each interpretation are ignored.
+pub fn environment_sources(
+    vars: Option<HashMap<String, String>>,
+) -> [(config::Environment, String); 2] {
+    [
+        (
+            config::Environment::with_prefix(ENV_PREFIX)
+                .separator("_")
+                .source(vars.clone()),
+            format!("environment {ENV_PREFIX}_* (nested keys)"),
+        ),
+        (
+            config::Environment::with_prefix(ENV_PREFIX)
+                .prefix_separator("_")
+                .source(vars),
+            format!("environment {ENV_PREFIX}_* (top-level keys)"),
+        ),
+    ]
+}
+
+/// Build the configuration from the sources of `builder`, retrying up to `retries` times when one
+/// of them can't be read, e.g. a file on a network share that is not mounted yet. The delay before
+/// the first retry is `delay`, doubled after each attempt. The other errors, such as an invalid
+/// file, are returned at once.
+pub async fn build_with_retries(
+    builder: ConfigBuilder<DefaultState>,
+    retries: u32,
+    mut delay: Duration,
+) -> anyhow::Result<config::Config> {
+    for attempt in 0.. {
+        let err = match builder.clone().build() {
+            Ok(config) => return Ok(config),
+            Err(err) => err,
+        };
+
+        let io_error = matches!(&err, ConfigError::Foreign(cause) if cause.is::<std::io::Error>());
+        if !io_error || attempt == retries {
+            return Err(err.into());
+        }
+
+        tracing::warn!(
+            "Could not read the configuration (attempt {}/{}): {err}, retrying in {delay:?}",
+            attempt + 1,
+            retries + 1,
+        );
+        tokio::time::sleep(delay).await;
+        delay *= 2;
+    }
+
+    unreachable!()
+}
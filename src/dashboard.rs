@@ -0,0 +1,372 @@
+use std::{collections::HashMap, sync::Arc};
+
+use askama::Template;
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::{get, post},
+    Form, Router,
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+use time::OffsetDateTime;
+
+use crate::{
+    admin::{self, room_overviews, Import, MergeMode, Permission, RoomOverview},
+    events::AdmissionEvent,
+    sessions::IngestSession,
+    OvenCtrlConfig, OvenCtrlState, RoomPasswordList,
+};
+
+const SESSION_COOKIE: &str = "ovenctrl_admin";
+
+/// Number of admission events shown on the dashboard
+const DASHBOARD_EVENTS: usize = 50;
+
+#[derive(Template)]
+#[template(path = "admin_login.html")]
+struct LoginPage {
+    invalid: bool,
+}
+
+#[derive(Template)]
+#[template(path = "admin.html")]
+struct Dashboard {
+    sessions: Vec<IngestSession>,
+    streamers: Vec<String>,
+    rooms: Vec<RoomOverview>,
+    events: Vec<AdmissionEvent>,
+    /// Token of the forms changing the configuration
+    csrf: String,
+}
+
+/// Signature of `purpose` for the session expiring at `expires`, keyed by the admin token so that
+/// changing it logs everyone out
+fn session_signature(admin_token: &str, purpose: &str, expires: i64) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(admin_token.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(format!("{purpose}:{expires}").as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+fn session_cookie(admin_token: &str, expires: i64) -> String {
+    format!(
+        "{expires}.{}",
+        session_signature(admin_token, "admin-session", expires)
+    )
+}
+
+/// Token the forms of a session must send back. The session cookie is `SameSite=Strict`, which
+/// does not cover the requests forged from the other origins of the site, such as its subdomains.
+fn csrf_token(admin_token: &str, expires: i64) -> String {
+    session_signature(admin_token, "admin-csrf", expires)
+}
+
+/// Expiry of the valid session of the request, if any
+fn session_expiry(admin_token: &str, headers: &HeaderMap) -> Option<i64> {
+    let session = headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|cookie| {
+            cookie
+                .trim()
+                .strip_prefix(SESSION_COOKIE)?
+                .strip_prefix('=')
+        })?;
+
+    let expires = session
+        .split_once('.')
+        .and_then(|(expires, _)| expires.parse::<i64>().ok())?;
+
+    (expires > OffsetDateTime::now_utc().unix_timestamp()
+        && bool::from(
+            session
+                .as_bytes()
+                .ct_eq(session_cookie(admin_token, expires).as_bytes()),
+        ))
+    .then_some(expires)
+}
+
+fn render(page: impl Template) -> Result<Html<String>, StatusCode> {
+    page.render().map(Html).map_err(|err| {
+        tracing::error!("Could not render administration page: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+#[tracing::instrument(skip_all)]
+async fn dashboard(
+    State(state): State<Arc<OvenCtrlState>>,
+    headers: HeaderMap,
+) -> Result<Html<String>, StatusCode> {
+    let config = state.config();
+    let Some(admin_token) = &config.admin_token else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+
+    let Some(expires) = session_expiry(admin_token, &headers) else {
+        return render(LoginPage { invalid: false });
+    };
+
+    let mut streamers = config.streamers.keys().cloned().collect::<Vec<_>>();
+    streamers.sort();
+
+    render(Dashboard {
+        sessions: state.ingest_sessions.all(),
+        streamers,
+        rooms: room_overviews(&state),
+        events: state.events.latest(DASHBOARD_EVENTS),
+        csrf: csrf_token(admin_token, expires),
+    })
+}
+
+#[derive(serde::Deserialize)]
+struct LoginForm {
+    token: String,
+}
+
+#[tracing::instrument(skip_all)]
+async fn login(
+    State(state): State<Arc<OvenCtrlState>>,
+    Form(form): Form<LoginForm>,
+) -> Result<Response, StatusCode> {
+    let config = state.config();
+    let Some(admin_token) = &config.admin_token else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+
+    if !bool::from(admin_token.as_bytes().ct_eq(form.token.as_bytes())) {
+        tracing::warn!("Invalid admin token");
+        let page = render(LoginPage { invalid: true })?;
+        return Ok((StatusCode::UNAUTHORIZED, page).into_response());
+    }
+
+    let timeout = config.admin_session_timeout_secs;
+    let expires = OffsetDateTime::now_utc().unix_timestamp() + timeout as i64;
+    let cookie = format!(
+        "{SESSION_COOKIE}={}; Max-Age={timeout}; Path=/admin; HttpOnly; SameSite=Strict{}",
+        session_cookie(admin_token, expires),
+        if config.external_tls { "; Secure" } else { "" },
+    );
+
+    Ok(([(header::SET_COOKIE, cookie)], Redirect::to("/admin")).into_response())
+}
+
+type FormError = (StatusCode, String);
+
+/// Check that a form changing the configuration comes from a session of the dashboard, and that
+/// the session grants `permission`
+fn authorize_form(
+    config: &OvenCtrlConfig,
+    headers: &HeaderMap,
+    csrf: &str,
+    permission: Permission,
+) -> Result<(), FormError> {
+    let Some(admin_token) = &config.admin_token else {
+        return Err((StatusCode::NOT_FOUND, String::new()));
+    };
+    let Some(expires) = session_expiry(admin_token, headers) else {
+        return Err((StatusCode::UNAUTHORIZED, "the session expired".into()));
+    };
+
+    if !bool::from(
+        csrf.as_bytes()
+            .ct_eq(csrf_token(admin_token, expires).as_bytes()),
+    ) {
+        tracing::warn!("Invalid CSRF token in a dashboard form");
+        return Err((StatusCode::FORBIDDEN, "invalid CSRF token".into()));
+    }
+
+    // Sessions are opened with the admin_token
+    admin::authorize(config, admin_token, Some(permission))
+        .map_err(|status| (status, String::new()))
+}
+
+/// Apply a change to the configuration, and go back to the dashboard
+fn update(
+    state: &OvenCtrlState,
+    change: impl FnOnce(&mut OvenCtrlConfig) -> anyhow::Result<()>,
+) -> Result<Redirect, FormError> {
+    state
+        .update_config(change)
+        .map_err(|err| (StatusCode::BAD_REQUEST, format!("{err:#}")))?;
+
+    Ok(Redirect::to("/admin"))
+}
+
+#[derive(serde::Deserialize)]
+struct StreamerForm {
+    csrf: String,
+    name: String,
+    key: String,
+}
+
+#[tracing::instrument(skip_all, fields(name = form.name))]
+async fn add_streamer(
+    State(state): State<Arc<OvenCtrlState>>,
+    headers: HeaderMap,
+    Form(form): Form<StreamerForm>,
+) -> Result<Redirect, FormError> {
+    authorize_form(
+        &state.config(),
+        &headers,
+        &form.csrf,
+        Permission::WriteStreamers,
+    )?;
+    if form.name.is_empty() || form.key.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "missing name or key".into()));
+    }
+
+    let rsp = update(&state, |config| {
+        let import = Import {
+            streamers: Some(HashMap::from([(form.name.clone(), form.key)])),
+            ..Default::default()
+        };
+        let summary = admin::apply_import(config, MergeMode::Diff, import)?;
+        anyhow::ensure!(summary.added == 1, "streamer {} already exists", form.name);
+        Ok(())
+    })?;
+    tracing::info!("Added streamer {} from the dashboard", form.name);
+
+    Ok(rsp)
+}
+
+#[derive(serde::Deserialize)]
+struct RemoveForm {
+    csrf: String,
+    name: String,
+}
+
+#[tracing::instrument(skip_all, fields(name = form.name))]
+async fn remove_streamer(
+    State(state): State<Arc<OvenCtrlState>>,
+    headers: HeaderMap,
+    Form(form): Form<RemoveForm>,
+) -> Result<Redirect, FormError> {
+    authorize_form(
+        &state.config(),
+        &headers,
+        &form.csrf,
+        Permission::WriteStreamers,
+    )?;
+
+    let rsp = update(&state, |config| {
+        let mut streamers = config.streamers.clone();
+        anyhow::ensure!(
+            streamers.remove(&form.name).is_some(),
+            "unknown streamer {}",
+            form.name
+        );
+        let mut allowed_streams = config.allowed_streams.clone();
+        allowed_streams.remove(&form.name);
+
+        let import = Import {
+            streamers: Some(streamers),
+            allowed_streams: Some(allowed_streams),
+            ..Default::default()
+        };
+        admin::apply_import(config, MergeMode::Replace, import)?;
+        Ok(())
+    })?;
+    tracing::info!("Removed streamer {} from the dashboard", form.name);
+
+    Ok(rsp)
+}
+
+#[derive(serde::Deserialize)]
+struct RoomForm {
+    csrf: String,
+    room: String,
+    password: String,
+}
+
+#[tracing::instrument(skip_all, fields(room = form.room))]
+async fn add_room(
+    State(state): State<Arc<OvenCtrlState>>,
+    headers: HeaderMap,
+    Form(form): Form<RoomForm>,
+) -> Result<Redirect, FormError> {
+    authorize_form(
+        &state.config(),
+        &headers,
+        &form.csrf,
+        Permission::WriteRooms,
+    )?;
+    if !state.room_name_pattern.is_match(&form.room) {
+        return Err((StatusCode::BAD_REQUEST, "invalid room name".into()));
+    }
+    if form.password.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "missing password".into()));
+    }
+
+    let rsp = update(&state, |config| {
+        let import = Import {
+            rooms: Some(HashMap::from([(
+                form.room.clone(),
+                RoomPasswordList::Single(form.password),
+            )])),
+            ..Default::default()
+        };
+        let summary = admin::apply_import(config, MergeMode::Diff, import)?;
+        anyhow::ensure!(summary.added == 1, "room {} already exists", form.room);
+        Ok(())
+    })?;
+    tracing::info!("Added room {} from the dashboard", form.room);
+
+    Ok(rsp)
+}
+
+#[tracing::instrument(skip_all, fields(room = form.name))]
+async fn remove_room(
+    State(state): State<Arc<OvenCtrlState>>,
+    headers: HeaderMap,
+    Form(form): Form<RemoveForm>,
+) -> Result<Redirect, FormError> {
+    authorize_form(
+        &state.config(),
+        &headers,
+        &form.csrf,
+        Permission::WriteRooms,
+    )?;
+
+    let rsp = update(&state, |config| {
+        let mut rooms = config.rooms.clone();
+        anyhow::ensure!(
+            rooms.remove(&form.name).is_some(),
+            "unknown room {}",
+            form.name
+        );
+        let mut room_category = config.room_category.clone();
+        room_category.remove(&form.name);
+
+        let import = Import {
+            rooms: Some(rooms),
+            room_category: Some(room_category),
+            ..Default::default()
+        };
+        admin::apply_import(config, MergeMode::Replace, import)?;
+        Ok(())
+    })?;
+    tracing::info!("Removed room {} from the dashboard", form.name);
+
+    Ok(rsp)
+}
+
+/// Administration dashboard, only available when `admin_token` is configured
+pub fn router() -> Router<Arc<OvenCtrlState>> {
+    Router::new()
+        .route("/admin", get(dashboard))
+        .route("/admin/login", post(login))
+        .route("/admin/streamers", post(add_streamer))
+        .route("/admin/streamers/remove", post(remove_streamer))
+        .route("/admin/rooms", post(add_room))
+        .route("/admin/rooms/remove", post(remove_room))
+}
@@ -0,0 +1,196 @@
+use std::{collections::VecDeque, sync::Mutex};
+
+use time::OffsetDateTime;
+
+use crate::{OvenAdmission, OvenDirection, OvenProtocol, OvenStatus};
+
+/// Record of an admission request and of its outcome
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct AdmissionEvent {
+    /// Position of the event in the log, increasing by one for each event
+    pub seq: u64,
+    #[serde(with = "time::serde::rfc3339")]
+    pub timestamp: OffsetDateTime,
+    pub direction: OvenDirection,
+    pub protocol: OvenProtocol,
+    pub status: OvenStatus,
+    pub app: String,
+    pub room: String,
+    pub streamer: Option<String>,
+    pub client_address: String,
+    /// Decision for opening requests
+    pub allowed: Option<bool>,
+    pub reason: Option<String>,
+    /// Whether the event was kept by the `event_sample_rate` sampling, and stands for
+    /// `1 / event_sample_rate` events
+    pub sampled: bool,
+}
+
+/// Decision of an opening request
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Allowed,
+    Denied,
+}
+
+impl Outcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Outcome::Allowed => "allowed",
+            Outcome::Denied => "denied",
+        }
+    }
+}
+
+/// Criteria of the events returned by `/api/events`
+#[derive(Debug, Default, Clone)]
+pub struct EventFilter {
+    pub room: Option<String>,
+    pub since: Option<OffsetDateTime>,
+    pub outcome: Option<Outcome>,
+}
+
+impl EventFilter {
+    fn matches(&self, event: &AdmissionEvent) -> bool {
+        self.room.as_ref().is_none_or(|room| *room == event.room)
+            && self.since.is_none_or(|since| event.timestamp >= since)
+            && self
+                .outcome
+                .is_none_or(|outcome| event.outcome() == Some(outcome))
+    }
+}
+
+impl AdmissionEvent {
+    pub fn outcome(&self) -> Option<Outcome> {
+        self.allowed.map(|allowed| match allowed {
+            true => Outcome::Allowed,
+            false => Outcome::Denied,
+        })
+    }
+
+    pub fn new(payload: &OvenAdmission) -> Self {
+        let streamer = match payload.request.direction {
+            OvenDirection::Incoming => payload
+                .request
+                .url
+                .url
+                .query_pairs()
+                .find(|(key, _)| key == "name")
+                .map(|(_, name)| name.into_owned()),
+            OvenDirection::Outgoing => None,
+        };
+
+        Self {
+            seq: 0,
+            timestamp: OffsetDateTime::now_utc(),
+            direction: payload.request.direction,
+            protocol: payload.request.protocol,
+            status: payload.request.status,
+            app: payload.request.url.app.clone(),
+            room: payload.request.url.room.clone(),
+            streamer,
+            client_address: payload.client.address.clone(),
+            allowed: None,
+            reason: None,
+            sampled: false,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct EventBuffer {
+    /// Sequence number of the next event
+    next_seq: u64,
+    /// Events with consecutive sequence numbers, oldest first
+    events: VecDeque<AdmissionEvent>,
+}
+
+impl EventBuffer {
+    /// Events with a sequence number greater than `after`
+    fn after(&self, after: u64) -> impl Iterator<Item = &AdmissionEvent> + '_ {
+        let first = self.events.front().map(|event| event.seq).unwrap_or(0);
+        let skip = after.saturating_add(1).saturating_sub(first);
+
+        self.events
+            .iter()
+            .skip(usize::try_from(skip).unwrap_or(usize::MAX))
+    }
+}
+
+/// Events following a cursor
+#[derive(serde::Serialize, Debug)]
+pub struct EventPage {
+    pub events: Vec<AdmissionEvent>,
+    /// Cursor of the next page, to be passed as `after`
+    pub next_cursor: u64,
+    pub has_more: bool,
+}
+
+/// Ring buffer of the latest admission events
+#[derive(Debug)]
+pub struct EventLog {
+    capacity: usize,
+    buffer: Mutex<EventBuffer>,
+}
+
+impl EventLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            buffer: Mutex::new(EventBuffer {
+                next_seq: 1,
+                events: VecDeque::with_capacity(capacity),
+            }),
+        }
+    }
+
+    pub fn push(&self, mut event: AdmissionEvent) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut buffer = self.buffer.lock().unwrap();
+        event.seq = buffer.next_seq;
+        buffer.next_seq += 1;
+
+        if buffer.events.len() == self.capacity {
+            buffer.events.pop_front();
+        }
+        buffer.events.push_back(event);
+    }
+
+    /// Oldest events first, starting at the `since` unix timestamp
+    pub fn query(&self, since: Option<i64>, limit: Option<usize>) -> Vec<AdmissionEvent> {
+        let buffer = self.buffer.lock().unwrap();
+        buffer
+            .events
+            .iter()
+            .filter(|event| since.is_none_or(|since| event.timestamp.unix_timestamp() >= since))
+            .take(limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect()
+    }
+
+    /// At most `limit` events matching `filter` with a sequence number greater than `after`,
+    /// oldest first
+    pub fn page(&self, after: u64, limit: usize, filter: &EventFilter) -> EventPage {
+        let buffer = self.buffer.lock().unwrap();
+
+        let mut events = buffer.after(after).filter(|event| filter.matches(event));
+        let page = events.by_ref().take(limit).cloned().collect::<Vec<_>>();
+        let has_more = events.next().is_some();
+
+        EventPage {
+            next_cursor: page.last().map(|event| event.seq).unwrap_or(after),
+            events: page,
+            has_more,
+        }
+    }
+
+    /// Newest events first
+    pub fn latest(&self, count: usize) -> Vec<AdmissionEvent> {
+        let buffer = self.buffer.lock().unwrap();
+        buffer.events.iter().rev().take(count).cloned().collect()
+    }
+}
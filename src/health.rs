@@ -0,0 +1,34 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::StatusCode, routing::get, Router};
+
+use crate::OvenCtrlState;
+
+/// The process is running
+async fn livez() -> &'static str {
+    "alive"
+}
+
+/// The controller can decide admissions, and reach the services it depends on. The server only
+/// starts listening once the configuration is loaded.
+async fn readyz(State(state): State<Arc<OvenCtrlState>>) -> (StatusCode, &'static str) {
+    if state
+        .ome_api
+        .as_ref()
+        .is_some_and(|api| !api.is_available())
+    {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "OME API circuit breaker is open",
+        );
+    }
+
+    (StatusCode::OK, "ready")
+}
+
+/// Probes for orchestrators, e.g. Kubernetes
+pub fn router() -> Router<Arc<OvenCtrlState>> {
+    Router::new()
+        .route("/livez", get(livez))
+        .route("/readyz", get(readyz))
+}
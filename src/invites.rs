@@ -0,0 +1,434 @@
+use std::{
+    collections::HashMap,
+    path::{Path as FsPath, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Context;
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use dashmap::{mapref::entry::Entry, DashMap};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use time::OffsetDateTime;
+
+use crate::{
+    admin::{scope, RequiredPermission},
+    player_page, OvenCtrlConfig, OvenCtrlState,
+};
+
+/// Tokens signed by the controller
+#[derive(Debug, Clone, Copy)]
+enum TokenKind {
+    /// Link to the viewer page of a room
+    Invite,
+    /// Credential of the player of a page opened with an invite
+    Pass,
+}
+
+impl TokenKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            TokenKind::Invite => "invite",
+            TokenKind::Pass => "pass",
+        }
+    }
+}
+
+/// Content of a token, signed with HMAC-SHA256
+#[derive(Debug, Clone)]
+struct Claims {
+    room: String,
+    /// Unix timestamp at which the token expires
+    expires: i64,
+    /// Number of times the link can be opened
+    max_uses: u32,
+    /// Revocation counter of the room when the token was created
+    rev: u64,
+    /// Identifier of the token, keying its use counter
+    id: String,
+}
+
+fn mac(key: &[u8], epoch: &str, kind: TokenKind, payload: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(epoch.as_bytes());
+    mac.update(b".");
+    mac.update(kind.as_str().as_bytes());
+    mac.update(b".");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+impl Claims {
+    /// `<room>.<expires>.<max uses>.<rev>.<id>.<signature>`, with the room and the signature
+    /// encoded in URL-safe base64. The signature also covers the `epoch` of the counters the
+    /// revision and the uses refer to.
+    fn sign(&self, key: &[u8], epoch: &str, kind: TokenKind) -> String {
+        let payload = format!(
+            "{}.{}.{}.{}.{}",
+            URL_SAFE_NO_PAD.encode(&self.room),
+            self.expires,
+            self.max_uses,
+            self.rev,
+            self.id
+        );
+        let signature =
+            URL_SAFE_NO_PAD.encode(mac(key, epoch, kind, &payload).finalize().into_bytes());
+
+        format!("{payload}.{signature}")
+    }
+
+    /// Claims of a token signed with `key`, which may be expired or revoked
+    fn verify(token: &str, key: &[u8], epoch: &str, kind: TokenKind) -> Option<Self> {
+        let (payload, signature) = token.rsplit_once('.')?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        mac(key, epoch, kind, payload)
+            .verify_slice(&signature)
+            .ok()?;
+
+        let mut parts = payload.split('.');
+        let room = String::from_utf8(URL_SAFE_NO_PAD.decode(parts.next()?).ok()?).ok()?;
+        let claims = Claims {
+            room,
+            expires: parts.next()?.parse().ok()?,
+            max_uses: parts.next()?.parse().ok()?,
+            rev: parts.next()?.parse().ok()?,
+            id: parts.next()?.to_owned(),
+        };
+
+        parts.next().is_none().then_some(claims)
+    }
+}
+
+/// Counters of the invites saved in `invite_state_path`
+#[derive(serde::Deserialize, serde::Serialize, Debug, Default)]
+struct SavedInvites {
+    epoch: String,
+    revocation_counter: HashMap<String, u64>,
+    uses: HashMap<String, (i64, u32)>,
+    passes: HashMap<String, (i64, String)>,
+}
+
+/// Links letting viewers watch a room without its password.
+///
+/// The links are signed rather than stored. Their revocation and use counters are kept in
+/// memory, and saved in `invite_state_path` when it is configured. The tokens are signed for an
+/// epoch of these counters, drawn when they are created, so the links created before a restart
+/// are refused unless both `invite_secret` and `invite_state_path` are configured: otherwise their
+/// revocation and their uses would be forgotten.
+#[derive(Debug)]
+pub struct Invites {
+    /// Key signing the tokens when `invite_secret` is not configured
+    random_key: [u8; 32],
+    epoch: String,
+    /// Expiry of each opened invite, and the number of times it was opened
+    uses: DashMap<String, (i64, u32)>,
+    /// Expiry of each used pass, and the address of the client it is bound to
+    passes: DashMap<String, (i64, String)>,
+    /// Incremented to invalidate all the invites to a room
+    revocation_counter: DashMap<String, u64>,
+    /// File the counters are saved to, with a lock ordering the writes
+    state_path: Option<(PathBuf, Mutex<()>)>,
+}
+
+impl Default for Invites {
+    fn default() -> Self {
+        Self {
+            random_key: rand::random(),
+            epoch: uuid::Uuid::new_v4().simple().to_string(),
+            uses: Default::default(),
+            passes: Default::default(),
+            revocation_counter: Default::default(),
+            state_path: None,
+        }
+    }
+}
+
+fn collect<V: Clone>(map: &DashMap<String, V>) -> HashMap<String, V> {
+    map.iter()
+        .map(|entry| (entry.key().clone(), entry.value().clone()))
+        .collect()
+}
+
+fn now() -> i64 {
+    OffsetDateTime::now_utc().unix_timestamp()
+}
+
+impl Invites {
+    /// Restore the counters saved in `path`, which is created if it doesn't exist
+    pub fn load(path: &FsPath) -> anyhow::Result<Self> {
+        let saved = match std::fs::read(path) {
+            Ok(saved) => serde_json::from_slice::<SavedInvites>(&saved)
+                .with_context(|| format!("invalid invite state {}", path.display()))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => SavedInvites {
+                epoch: uuid::Uuid::new_v4().simple().to_string(),
+                ..Default::default()
+            },
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("could not read the invite state {}", path.display()))
+            }
+        };
+
+        let invites = Self {
+            epoch: saved.epoch,
+            uses: saved.uses.into_iter().collect(),
+            passes: saved.passes.into_iter().collect(),
+            revocation_counter: saved.revocation_counter.into_iter().collect(),
+            state_path: Some((path.to_owned(), Mutex::new(()))),
+            ..Default::default()
+        };
+        // The epoch must be saved before signing any token with it
+        invites.save()?;
+
+        Ok(invites)
+    }
+
+    /// Write the counters to `invite_state_path`, replacing the previous file at once
+    fn save(&self) -> anyhow::Result<()> {
+        let Some((path, lock)) = &self.state_path else {
+            return Ok(());
+        };
+        let _lock = lock.lock().unwrap();
+
+        let saved = serde_json::to_vec(&SavedInvites {
+            epoch: self.epoch.clone(),
+            revocation_counter: collect(&self.revocation_counter),
+            uses: collect(&self.uses),
+            passes: collect(&self.passes),
+        })?;
+
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, saved)
+            .and_then(|()| std::fs::rename(&tmp, path))
+            .with_context(|| format!("could not save the invite state {}", path.display()))
+    }
+
+    /// Save the counters, only logging the errors for the operations that can't fail
+    fn save_or_log(&self) {
+        if let Err(err) = self.save() {
+            tracing::error!("{err:#}");
+        }
+    }
+
+    fn key<'a>(&'a self, config: &'a OvenCtrlConfig) -> &'a [u8] {
+        match &config.invite_secret {
+            Some(secret) => secret.as_bytes(),
+            None => &self.random_key,
+        }
+    }
+
+    pub(crate) fn create(
+        &self,
+        config: &OvenCtrlConfig,
+        room: String,
+        expires_in: Duration,
+        max_uses: u32,
+    ) -> String {
+        let expires_in = i64::try_from(expires_in.as_secs()).unwrap_or(i64::MAX);
+        let claims = Claims {
+            rev: self.revision(&room),
+            room,
+            expires: now().saturating_add(expires_in),
+            max_uses,
+            id: uuid::Uuid::new_v4().simple().to_string(),
+        };
+
+        claims.sign(self.key(config), &self.epoch, TokenKind::Invite)
+    }
+
+    fn revision(&self, room: &str) -> u64 {
+        self.revocation_counter
+            .get(room)
+            .map(|rev| *rev)
+            .unwrap_or(0)
+    }
+
+    /// Invalidate the invites to `room` created until now, returning the new revocation counter
+    pub(crate) fn revoke(&self, room: &str) -> anyhow::Result<u64> {
+        let rev = {
+            let mut rev = self.revocation_counter.entry(room.to_owned()).or_default();
+            *rev += 1;
+            *rev
+        };
+        self.save()?;
+
+        Ok(rev)
+    }
+
+    /// Claims of a token that is neither expired nor revoked
+    fn verify(&self, config: &OvenCtrlConfig, token: &str, kind: TokenKind) -> Option<Claims> {
+        Claims::verify(token, self.key(config), &self.epoch, kind)
+            .filter(|claims| claims.expires > now() && claims.rev >= self.revision(&claims.room))
+    }
+
+    /// Use an invite, returning the room it grants access to and the pass of the player of the
+    /// page
+    pub(crate) fn open(&self, config: &OvenCtrlConfig, token: &str) -> Option<(String, String)> {
+        let invite = self.verify(config, token, TokenKind::Invite)?;
+        let mut uses = self
+            .uses
+            .entry(invite.id.clone())
+            .or_insert((invite.expires, 0));
+        if uses.1 >= invite.max_uses {
+            return None;
+        }
+        uses.1 += 1;
+
+        let pass = Claims {
+            max_uses: 1,
+            id: format!("{}-{}", invite.id, uses.1),
+            ..invite
+        };
+        drop(uses);
+        self.save_or_log();
+
+        Some((
+            pass.room.clone(),
+            pass.sign(self.key(config), &self.epoch, TokenKind::Pass),
+        ))
+    }
+
+    /// Whether the pass of a page opened with an invite lets `client` connect to `room`. A pass
+    /// is bound to the first client using it: its player can reconnect, or fall back to another
+    /// source, until the invite expires or is revoked, but other clients are denied.
+    pub fn admits(&self, config: &OvenCtrlConfig, pass: &str, room: &str, client: &str) -> bool {
+        let Some(pass) = self
+            .verify(config, pass, TokenKind::Pass)
+            .filter(|pass| pass.room == room)
+        else {
+            return false;
+        };
+
+        let (admitted, bound) = match self.passes.entry(pass.id) {
+            Entry::Occupied(owner) => (owner.get().1 == client, false),
+            Entry::Vacant(entry) => {
+                entry.insert((pass.expires, client.to_owned()));
+                (true, true)
+            }
+        };
+        if bound {
+            self.save_or_log();
+        }
+
+        admitted
+    }
+
+    /// Forget the use counters of the expired invites
+    pub fn cleanup(&self) {
+        let now = now();
+        let before = self.uses.len() + self.passes.len();
+        self.uses.retain(|_, (expires, _)| *expires > now);
+        self.passes.retain(|_, (expires, _)| *expires > now);
+        if self.uses.len() + self.passes.len() != before {
+            self.save_or_log();
+        }
+    }
+}
+
+fn default_expires_in() -> u64 {
+    3600
+}
+
+fn default_max_uses() -> u32 {
+    1
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub(crate) struct InviteRequest {
+    #[serde(default = "default_expires_in")]
+    expires_in_secs: u64,
+    #[serde(default = "default_max_uses")]
+    max_uses: u32,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub(crate) struct InviteResponse {
+    invite_url: String,
+}
+
+/// Create an invite link to a room, on the host the request was sent to
+#[tracing::instrument(skip(_permission, state, headers))]
+pub(crate) async fn create(
+    _permission: RequiredPermission<scope::WriteRooms>,
+    State(state): State<Arc<OvenCtrlState>>,
+    Path(room): Path<String>,
+    headers: HeaderMap,
+    Json(request): Json<InviteRequest>,
+) -> Result<Json<InviteResponse>, (StatusCode, String)> {
+    let config = state.config();
+    let room = config.canonical_room(&room).unwrap_or(&room).to_owned();
+    if !config.rooms.contains_key(&room) && !config.public_rooms.contains(&room) {
+        return Err((StatusCode::NOT_FOUND, format!("unknown room {room}")));
+    }
+
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .ok_or((StatusCode::BAD_REQUEST, "missing Host header".into()))?;
+
+    let token = state.invites.create(
+        &config,
+        room,
+        Duration::from_secs(request.expires_in_secs),
+        request.max_uses,
+    );
+
+    Ok(Json(InviteResponse {
+        invite_url: format!(
+            "http{}://{host}/join-invite/{token}",
+            if config.external_tls { "s" } else { "" }
+        ),
+    }))
+}
+
+#[derive(serde::Serialize, Debug)]
+pub(crate) struct RevokeResponse {
+    rev: u64,
+}
+
+/// Invalidate the existing invites to a room, without changing its password
+#[tracing::instrument(skip(_permission, state))]
+pub(crate) async fn revoke(
+    _permission: RequiredPermission<scope::WriteRooms>,
+    State(state): State<Arc<OvenCtrlState>>,
+    Path(room): Path<String>,
+) -> Result<Json<RevokeResponse>, (StatusCode, String)> {
+    let config = state.config();
+    let room = config.canonical_room(&room).unwrap_or(&room);
+    if !config.rooms.contains_key(room) && !config.public_rooms.contains(room) {
+        return Err((StatusCode::NOT_FOUND, format!("unknown room {room}")));
+    }
+
+    let rev = state.invites.revoke(room).map_err(|err| {
+        tracing::error!("{err:#}");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "could not save the revocation".to_owned(),
+        )
+    })?;
+    tracing::info!("Revoked the invites to room {room} (revision {rev})");
+
+    Ok(Json(RevokeResponse { rev }))
+}
+
+/// Viewer page of the room of an invite
+#[tracing::instrument(skip_all)]
+pub(crate) async fn join(
+    State(state): State<Arc<OvenCtrlState>>,
+    Path(token): Path<String>,
+) -> Response {
+    let config = state.config();
+    let Some((room, pass)) = state.invites.open(&config, &token) else {
+        tracing::warn!("Invalid, expired or used up invite");
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    player_page(&config, &room, &room, ("invite", &pass), "../").into_response()
+}
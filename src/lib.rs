@@ -0,0 +1,3924 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use admin::AdminToken;
+use affinity::{AffinityCookieConfig, AffinityHint};
+use anyhow::Context;
+use askama::Template;
+use axum::{
+    extract::{rejection::JsonRejection, ConnectInfo, Path, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    middleware,
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::{get, post},
+    Form, Json, Router,
+};
+use bot_detection::BotDetectionConfig;
+use dashmap::DashMap;
+use events::{AdmissionEvent, EventLog};
+use hmac::{Hmac, Mac};
+use ipnet::IpNet;
+use metrics::AdmissionMetrics;
+use ome_api::{OmeApi, OmeAuthScheme, PushTarget};
+use ovenctrl_macros::ConfigDoc;
+use plugin::{HttpPlugin, PluginChain, PluginDecision};
+use poster::PosterCache;
+use rate_limit::{RateLimitConfig, TokenBucket};
+use regex::Regex;
+use sessions::{IngestSessions, ViewerCounts};
+use sha2::Sha256;
+use stats::StatsCache;
+use subtle::ConstantTimeEq;
+use time::OffsetDateTime;
+use tower_http::{
+    compression::CompressionLayer, decompression::RequestDecompressionLayer,
+    set_header::SetResponseHeaderLayer, trace::TraceLayer,
+};
+use trace::{LatencyLog, RouteSpan};
+use tracing::Instrument;
+use ui_auth::BasicAuthConfig;
+use url::Url;
+use vault::VaultConfig;
+
+mod admin;
+mod admin_ws;
+mod affinity;
+mod analytics;
+mod audit_db;
+mod bot_detection;
+mod capabilities;
+mod circuit_breaker;
+pub mod config_help;
+mod dashboard;
+mod events;
+mod health;
+mod invites;
+pub mod log_tail;
+mod metrics;
+mod ome_api;
+mod plugin;
+mod poster;
+mod quota;
+mod rate_limit;
+pub mod replay;
+pub mod room_import;
+mod self_test;
+mod serve;
+mod sessions;
+mod stats;
+mod stream_events;
+mod trace;
+mod ui_auth;
+pub mod vault;
+
+fn deserialize_client_port<'de, D>(deserializer: D) -> Result<u16, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::Deserialize;
+
+    match u16::deserialize(deserializer)? {
+        0 => Err(serde::de::Error::custom("client port can't be 0")),
+        port => Ok(port),
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+pub struct OvenClient {
+    pub address: String,
+    #[serde(deserialize_with = "deserialize_client_port")]
+    pub port: u16,
+    pub user_agent: String,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum OvenDirection {
+    Incoming,
+    Outgoing,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OvenProtocol {
+    WebRTC,
+    RTMP,
+    SRT,
+    LLHLS,
+    Thumbnail,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum OvenStatus {
+    Closing,
+    Opening,
+}
+
+/// Stream URL sent by OME, of the form `<scheme>://<host>/<app>/<room>` unless configured
+/// otherwise by `app_url_path_index` and `room_url_path_index`
+///
+/// Segments after the room are ignored, as they are used by some protocols to address parts of
+/// the stream (e.g. `/app/room/llhls.m3u8` or `/app/room/chunk-0001.ts` for LLHLS).
+#[derive(Debug)]
+pub struct OvenStreamUrl {
+    pub url: Url,
+    pub app: String,
+    pub room: String,
+}
+
+impl OvenStreamUrl {
+    /// Parse a URL whose application and room are the path segments `app_index` and
+    /// `room_index`
+    pub fn parse(url: Url, app_index: usize, room_index: usize) -> anyhow::Result<Self> {
+        let segments = url
+            .path_segments()
+            .with_context(|| format!("url '{url}' has no segments"))?
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+        let segment = |index: usize| segments.get(index).filter(|s| !s.is_empty()).cloned();
+
+        let app = segment(app_index)
+            .with_context(|| format!("url '{url}' is lacking an application segment"))?;
+        let room = segment(room_index)
+            .with_context(|| format!("url '{url}' is lacking a room segment"))?;
+
+        Ok(Self { url, app, room })
+    }
+}
+
+impl TryFrom<Url> for OvenStreamUrl {
+    type Error = anyhow::Error;
+
+    fn try_from(url: Url) -> Result<Self, Self::Error> {
+        Self::parse(url, 0, 1)
+    }
+}
+
+impl serde::Serialize for OvenStreamUrl {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.url.serialize(serializer)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for OvenStreamUrl {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let url = Url::deserialize(deserializer)?;
+        Self::try_from(url).map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+pub struct OvenRequest {
+    pub direction: OvenDirection,
+    pub protocol: OvenProtocol,
+    pub status: OvenStatus,
+    pub url: OvenStreamUrl,
+    pub new_url: Option<Url>,
+    #[serde(
+        deserialize_with = "time::serde::iso8601::deserialize",
+        serialize_with = "time::serde::rfc3339::serialize"
+    )]
+    pub time: OffsetDateTime,
+    /// Set by OME for its internal connections (e.g. health checks), admitted without validation
+    /// when `allow_bypass` is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bypass: Option<bool>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+pub struct OvenAdmission {
+    pub client: OvenClient,
+    pub request: OvenRequest,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct OvenClosingResponse {}
+
+#[derive(serde::Serialize, Debug)]
+pub struct OvenOpeningResponse {
+    pub allowed: bool,
+    pub new_url: Option<Url>,
+    pub lifetime: Option<u64>,
+    pub reason: Option<String>,
+    /// Fallbacks of `new_url`, for the OME versions that support several redirect URLs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alternative_urls: Option<Vec<Url>>,
+    /// Origin of LLHLS viewers, ignored by OME but available to the load balancer tooling
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub affinity: Option<AffinityHint>,
+}
+
+#[derive(serde::Serialize, Debug)]
+#[serde(untagged)]
+pub enum OvenResponse {
+    Closing(OvenClosingResponse),
+    Opening(Box<OvenOpeningResponse>),
+}
+
+impl From<OvenOpeningResponse> for Json<OvenResponse> {
+    fn from(value: OvenOpeningResponse) -> Self {
+        Json(OvenResponse::Opening(Box::new(value)))
+    }
+}
+
+impl From<OvenClosingResponse> for Json<OvenResponse> {
+    fn from(value: OvenClosingResponse) -> Self {
+        Json(OvenResponse::Closing(value))
+    }
+}
+
+/// Category of an admission denial
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum DenialReason {
+    MalformedRequest,
+    UnknownApplication,
+    UnknownVirtualHost,
+    ClientNotAllowed,
+    UnknownStreamer,
+    RateLimited,
+    InvalidKey,
+    QuotaExceeded,
+    InvalidRoomName,
+    RoomNotAllowed,
+    UnknownRoom,
+    InvalidPassword,
+    SessionExpired,
+    RoomFull,
+    BotDetected,
+    Plugin,
+    Timeout,
+    Other,
+}
+
+impl std::fmt::Display for DenialReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+impl std::error::Error for DenialReason {}
+
+impl DenialReason {
+    /// Find the denial reason of an error returned by [handle_opening_admission]
+    pub fn of(err: &anyhow::Error) -> Self {
+        err.downcast_ref().copied().unwrap_or(DenialReason::Other)
+    }
+}
+
+fn denied<M>(reason: DenialReason, message: M) -> anyhow::Error
+where
+    M: std::fmt::Display + Send + Sync + 'static,
+{
+    anyhow::Error::new(reason).context(message)
+}
+
+/// Return an error with the [DenialReason] and a formatted message
+macro_rules! deny {
+    ($reason:expr, $($arg:tt)*) => {
+        return Err(denied($reason, format!($($arg)*)))
+    };
+}
+
+/// Whether OME asked for `payload` to be admitted without validation, and it is allowed to
+fn is_ome_bypass(config: &OvenCtrlConfig, payload: &OvenAdmission) -> bool {
+    config.allow_bypass && payload.request.bypass == Some(true)
+}
+
+/// Whether the client of `payload` is admitted without any validation
+fn is_bypassed(config: &OvenCtrlConfig, payload: &OvenAdmission) -> bool {
+    is_ome_bypass(config, payload)
+        || payload
+            .client
+            .address
+            .parse::<IpAddr>()
+            .is_ok_and(|address| config.bypass_ips.contains(&address))
+}
+
+fn handle_opening_admission(
+    state: &OvenCtrlState,
+    payload: &OvenAdmission,
+) -> anyhow::Result<OvenOpeningResponse> {
+    let config = state.config();
+
+    if is_bypassed(&config, payload) {
+        if is_ome_bypass(&config, payload) {
+            tracing::info!(
+                "OME requested a bypass for client {}, skipping validation",
+                payload.client.address
+            );
+        } else {
+            tracing::debug!(
+                "Client {} is in bypass_ips, skipping validation",
+                payload.client.address
+            );
+        }
+
+        return Ok(OvenOpeningResponse {
+            allowed: true,
+            lifetime: None,
+            new_url: None,
+            reason: None,
+            alternative_urls: None,
+            affinity: None,
+        });
+    }
+
+    if let Some((start, end)) = config.client_port_range {
+        if !(start..=end).contains(&payload.client.port) {
+            deny!(
+                DenialReason::ClientNotAllowed,
+                "client port {} is outside of the allowed range",
+                payload.client.port
+            );
+        }
+    }
+
+    if let Some(vhost) = &config.vhost {
+        let host = payload.request.url.url.host_str().unwrap_or_default();
+        if !host.eq_ignore_ascii_case(vhost) {
+            deny!(
+                DenialReason::UnknownVirtualHost,
+                "unknown virtual host: {host}"
+            );
+        }
+    }
+
+    if let Some(app) = &config.ome_app_name {
+        if payload.request.url.app != *app {
+            deny!(
+                DenialReason::UnknownApplication,
+                "unknown application: {}",
+                payload.request.url.app
+            );
+        }
+    }
+
+    let mut lifetime = None;
+    let mut alternative_urls = Vec::new();
+
+    let new_url = match payload.request.direction {
+        OvenDirection::Incoming => {
+            #[derive(serde::Deserialize)]
+            struct IngestQuery {
+                name: String,
+                key: String,
+            }
+
+            let query = payload
+                .request
+                .url
+                .url
+                .query()
+                .ok_or(DenialReason::MalformedRequest)
+                .context("no query parameters present")?;
+
+            let query = serde_urlencoded::from_str::<IngestQuery>(query)
+                .map_err(|err| denied(DenialReason::MalformedRequest, err))?;
+            let expected_key = config
+                .streamers
+                .get(&query.name)
+                .ok_or(DenialReason::UnknownStreamer)
+                .with_context(|| format!("unknown streamer: {}", query.name))?;
+
+            if !rate_limit::consume(&state.rate_limits, &config, &query.name) {
+                deny!(DenialReason::RateLimited, "rate limit exceeded");
+            }
+
+            if let Some(pattern) = &state.stream_key_pattern {
+                if !pattern.is_match(&query.key) {
+                    deny!(
+                        DenialReason::InvalidKey,
+                        "malformed key for streamer {}",
+                        query.name
+                    )
+                }
+            }
+
+            // The usual string equality returns at the first differing byte, so the latency of the
+            // answer would tell an attacker how much of the key prefix they guessed right. `ct_eq`
+            // inspects every byte regardless of where the keys differ.
+            if !bool::from(expected_key.as_bytes().ct_eq(query.key.as_bytes())) {
+                deny!(
+                    DenialReason::InvalidKey,
+                    "invalid key for streamer {}",
+                    query.name
+                )
+            }
+
+            if state.quota_usage.exhausted(&config, &query.name) {
+                deny!(
+                    DenialReason::QuotaExceeded,
+                    "streaming quota of streamer {} is exhausted",
+                    query.name
+                )
+            }
+
+            let room = &payload.request.url.room;
+
+            if !state.room_name_pattern.is_match(room) {
+                deny!(DenialReason::InvalidRoomName, "invalid room name format");
+            }
+
+            let auto_created = config.auto_create_rooms && !config.rooms.contains_key(room);
+            let any_room = config
+                .streamer_config
+                .get(&query.name)
+                .is_some_and(|streamer| streamer.allow_any_room);
+
+            // With the allow policy, only the rooms assigned to streamers are restricted
+            let restricted = !(auto_created && any_room)
+                && (config.default_incoming_policy == Policy::Deny
+                    || config
+                        .allowed_streams
+                        .values()
+                        .any(|rooms| rooms.contains(room)));
+
+            if restricted {
+                let allowed_streams = config
+                    .allowed_streams
+                    .get(&query.name)
+                    .ok_or(DenialReason::RoomNotAllowed)
+                    .with_context(|| {
+                        format!(
+                            "streamer '{}' does not have access to any rooms",
+                            query.name
+                        )
+                    })?;
+
+                if !allowed_streams.contains(room) {
+                    deny!(
+                        DenialReason::RoomNotAllowed,
+                        "streamer {} does not have access to room {room}",
+                        query.name
+                    )
+                }
+            }
+
+            config
+                .room_config
+                .get(room)
+                .and_then(|room_config| room_config.max_ingest_bitrate_kbps)
+                .map(|max_bitrate| {
+                    let mut url = payload.request.url.url.clone();
+                    url.query_pairs_mut()
+                        .append_pair("maxBitrate", &max_bitrate.to_string());
+                    url
+                })
+        }
+        // Thumbnails are pulled by monitoring systems that don't know the room password. Without
+        // thumbnail_allowed_ips, they are admitted like the other viewers.
+        OvenDirection::Outgoing
+            if matches!(payload.request.protocol, OvenProtocol::Thumbnail)
+                && !config.thumbnail_allowed_ips.is_empty() =>
+        {
+            let address = payload.client.address.parse::<IpAddr>().map_err(|_| {
+                denied(
+                    DenialReason::MalformedRequest,
+                    format!("invalid client address: {}", payload.client.address),
+                )
+            })?;
+
+            if !config
+                .thumbnail_allowed_ips
+                .iter()
+                .any(|net| net.contains(&address))
+            {
+                deny!(
+                    DenialReason::ClientNotAllowed,
+                    "client {address} is not allowed to access thumbnails"
+                );
+            }
+
+            None
+        }
+        OvenDirection::Outgoing => {
+            #[derive(serde::Deserialize)]
+            struct ViewerQuery {
+                password: Option<String>,
+                invite: Option<String>,
+            }
+
+            let room = &payload.request.url.room;
+
+            if !config.is_open_room(room) {
+                let query = payload
+                    .request
+                    .url
+                    .url
+                    .query()
+                    .ok_or(DenialReason::MalformedRequest)
+                    .context("no query parameters present")?;
+
+                let query = serde_urlencoded::from_str::<ViewerQuery>(query)
+                    .map_err(|err| denied(DenialReason::MalformedRequest, err))?;
+                let invited = query.invite.is_some_and(|pass| {
+                    state
+                        .invites
+                        .admits(&config, &pass, room, &payload.client.address)
+                });
+
+                if !invited {
+                    let password = query
+                        .password
+                        .ok_or(DenialReason::MalformedRequest)
+                        .context("missing password")?;
+                    let expected_password = config
+                        .rooms
+                        .get(room)
+                        .ok_or(DenialReason::UnknownRoom)
+                        .with_context(|| format!("room does not exist: {room}"))?;
+
+                    if !expected_password.contains(&password) {
+                        deny!(
+                            DenialReason::InvalidPassword,
+                            "Password mismatch for room: {room}"
+                        );
+                    }
+                }
+            }
+
+            let capacity = config
+                .room_config
+                .get(room)
+                .and_then(|room_config| room_config.max_viewers);
+            if capacity.is_some_and(|capacity| state.viewers.get(room) >= capacity) {
+                deny!(DenialReason::RoomFull, "room {room} is full");
+            }
+
+            if let Some(reason) = state.bots.detect(&config.bot_detection, payload) {
+                // Logged under their own target to be analyzed apart from the other denials
+                tracing::warn!(
+                    target: "oven_ctrl::bots",
+                    client = payload.client.address,
+                    user_agent = payload.client.user_agent,
+                    "Bot detected: {reason}"
+                );
+                deny!(
+                    DenialReason::BotDetected,
+                    "viewer looks automated: {reason}"
+                );
+            }
+
+            if let Some(max_duration) = config.max_session_duration_secs {
+                let max_duration = Duration::from_secs(max_duration);
+                let started = *state
+                    .viewer_sessions
+                    .entry((payload.client.address.clone(), room.clone()))
+                    .or_insert_with(Instant::now);
+
+                // Reconnecting does not reset the session, the viewer only gets the time left
+                let remaining = max_duration.saturating_sub(started.elapsed());
+                if remaining.is_zero() {
+                    deny!(
+                        DenialReason::SessionExpired,
+                        "maximum session duration exceeded for room: {room}"
+                    );
+                }
+
+                lifetime = Some(u64::try_from(remaining.as_millis())?);
+            }
+
+            match config.room_config.get(room) {
+                Some(room_config) => {
+                    alternative_urls = room_config.alternative_outgoing_urls(payload)?;
+                    room_config.outgoing_url(payload)?
+                }
+                None => None,
+            }
+        }
+    };
+
+    let affinity = match (payload.request.direction, payload.request.protocol) {
+        (OvenDirection::Outgoing, OvenProtocol::LLHLS) => config
+            .llhls_affinity_cookie
+            .as_ref()
+            .and_then(|cookie| cookie.hint(&payload.client.address)),
+        _ => None,
+    };
+
+    Ok(OvenOpeningResponse {
+        allowed: true,
+        lifetime,
+        new_url,
+        reason: None,
+        alternative_urls: (!alternative_urls.is_empty()).then_some(alternative_urls),
+        affinity,
+    })
+}
+
+/// Run the built-in checks, then the plugins
+async fn opening_admission(
+    state: &OvenCtrlState,
+    payload: &OvenAdmission,
+) -> anyhow::Result<OvenOpeningResponse> {
+    let rsp = handle_opening_admission(state, payload)?;
+
+    match state.plugins.decide(payload, &state.config()).await {
+        PluginDecision::Allow | PluginDecision::Defer => Ok(rsp),
+        PluginDecision::Deny(reason) => deny!(DenialReason::Plugin, "{reason}"),
+    }
+}
+
+#[tracing::instrument(skip(state, headers), fields(room, streamer))]
+async fn admission(
+    state: State<Arc<OvenCtrlState>>,
+    source: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    payload: Result<Json<OvenAdmission>, JsonRejection>,
+) -> Result<Json<OvenResponse>, (StatusCode, String)> {
+    tracing::trace!("Received admission request");
+
+    let mut payload = payload.map_err(|rejection| {
+        tracing::warn!("Malformed admission request: {rejection}");
+        let status = match rejection {
+            JsonRejection::JsonDataError(_) => StatusCode::BAD_REQUEST,
+            _ => rejection.status(),
+        };
+        (status, rejection.body_text())
+    })?;
+
+    let config = state.config();
+    let url = payload.request.url.url.clone();
+    payload.request.url =
+        OvenStreamUrl::parse(url, config.app_url_path_index(), config.room_url_path_index)
+            .map_err(|err| {
+                tracing::warn!("Malformed admission request: {err:#}");
+                (StatusCode::BAD_REQUEST, format!("{err:#}"))
+            })?;
+
+    if let Some(address) = config
+        .use_ome_client_header
+        .as_deref()
+        .and_then(|header| headers.get(header))
+        .and_then(|value| value.to_str().ok())
+    {
+        tracing::debug!(
+            "Using client address {address} instead of {}",
+            payload.client.address
+        );
+        payload.client.address = address.trim().to_owned();
+    }
+
+    // Name of the stream in OME, which differs from the room when an alias is used
+    let stream = payload.request.url.room.clone();
+    if let Some(room) = config.canonical_room(&stream) {
+        tracing::debug!("Room alias {stream} resolves to {room}");
+        payload.request.url.room = room.to_owned();
+    }
+
+    let record = matches!(payload.request.direction, OvenDirection::Incoming)
+        && config.record_rooms.contains(&payload.request.url.room);
+    let push_relay_targets = match payload.request.direction {
+        OvenDirection::Incoming => config
+            .room_config
+            .get(&payload.request.url.room)
+            .map(|room_config| room_config.push_relay_targets.clone())
+            .unwrap_or_default(),
+        OvenDirection::Outgoing => Vec::new(),
+    };
+    let app = payload.request.url.app.clone();
+    let room = payload.request.url.room.clone();
+    let protocol = payload.request.protocol;
+    let category = config.room_category(&room).to_owned();
+    let mut event = AdmissionEvent::new(&payload);
+
+    // The admission logic runs for every request, only its reporting is sampled
+    let sampled =
+        config.event_sample_rate >= 1. || rand::random::<f64>() < config.event_sample_rate;
+    event.sampled = config.event_sample_rate < 1.;
+
+    let span = tracing::Span::current();
+    span.record("room", room.as_str());
+    if let Some(streamer) = &event.streamer {
+        span.record("streamer", streamer.as_str());
+    }
+
+    let rsp = match payload.request.status {
+        OvenStatus::Closing => {
+            let runtime = state.runtime_lock.write().unwrap();
+            if sampled {
+                state.metrics.record(protocol, &category, None);
+            }
+
+            let was_open = match (payload.request.direction, protocol) {
+                (OvenDirection::Incoming, _) => match state.ingest_sessions.close(&payload) {
+                    Some(session) => {
+                        state.quota_usage.record(&config, &session);
+                        true
+                    }
+                    // The streams of the bypassed clients are not registered
+                    None => is_bypassed(&config, &payload),
+                },
+                (OvenDirection::Outgoing, OvenProtocol::Thumbnail) => true,
+                (OvenDirection::Outgoing, _) => state.viewers.leave(&room),
+            };
+            drop(runtime);
+            if !was_open {
+                tracing::debug!("Closing a connection that is not open, ignoring it");
+                state.metrics.record_spurious_closing();
+            }
+
+            // A closing for a connection that was never admitted must not stop the relays or
+            // the recording of the live stream of the room
+            if was_open && !push_relay_targets.is_empty() {
+                spawn_push_relays(
+                    state.0.clone(),
+                    app.clone(),
+                    stream.clone(),
+                    push_relay_targets,
+                    false,
+                );
+            }
+            if was_open && record {
+                spawn_recording(state.0.clone(), app, stream, false);
+            }
+
+            if sampled {
+                state.stream_events.relay(&state.http_client, &payload);
+            }
+
+            OvenClosingResponse {}.into()
+        }
+        OvenStatus::Opening => {
+            let timeout = Duration::from_millis(config.admission_handler_timeout_ms);
+            let outcome = tokio::time::timeout(timeout, opening_admission(&state, &payload))
+                .await
+                .unwrap_or_else(|_| Err(denied(DenialReason::Timeout, "admission timeout")));
+
+            let runtime = state.runtime_lock.write().unwrap();
+            let mut rsp = match outcome {
+                Err(err) => {
+                    tracing::debug!("Denied admission: {err:#}");
+                    if sampled {
+                        state.metrics.record(
+                            protocol,
+                            &category,
+                            Some(Err(DenialReason::of(&err))),
+                        );
+                    }
+
+                    OvenOpeningResponse {
+                        allowed: false,
+                        new_url: None,
+                        lifetime: None,
+                        reason: Some(err.to_string()),
+                        alternative_urls: None,
+                        affinity: None,
+                    }
+                }
+                Ok(rsp) => {
+                    tracing::debug!("Allowed admission");
+                    if sampled {
+                        state.metrics.record(protocol, &category, Some(Ok(())));
+                    }
+                    rsp
+                }
+            };
+
+            let capabilities = state
+                .ome_capabilities
+                .negotiate(source.map(|ConnectInfo(source)| source.ip()), &headers);
+            if capabilities.is_some_and(|capabilities| !capabilities.supports("new-url")) {
+                rsp.new_url = None;
+                rsp.alternative_urls = None;
+            }
+
+            match (payload.request.direction, protocol, &event.streamer) {
+                _ if !rsp.allowed => (),
+                (OvenDirection::Incoming, _, Some(streamer)) => {
+                    state.ingest_sessions.open(&payload, streamer.clone())
+                }
+                (OvenDirection::Incoming, _, None) => (),
+                (OvenDirection::Outgoing, OvenProtocol::Thumbnail, _) => (),
+                (OvenDirection::Outgoing, _, _) => state.viewers.join(&room),
+            }
+            drop(runtime);
+
+            if let (true, OvenDirection::Incoming, Some(streamer)) =
+                (rsp.allowed, payload.request.direction, &event.streamer)
+            {
+                // The streamers were validated, unless the client is bypassed
+                if config.auto_create_rooms
+                    && !config.rooms.contains_key(&room)
+                    && !is_bypassed(&config, &payload)
+                {
+                    if let Err(err) = state.auto_create_room(&room, streamer) {
+                        tracing::error!("Could not create room {room}: {err:#}");
+                    }
+                }
+            }
+
+            if rsp.allowed && !push_relay_targets.is_empty() {
+                spawn_push_relays(
+                    state.0.clone(),
+                    app.clone(),
+                    stream.clone(),
+                    push_relay_targets,
+                    true,
+                );
+            }
+            if rsp.allowed && record {
+                spawn_recording(state.0.clone(), app, stream, true);
+            }
+            if rsp.allowed && sampled {
+                state.stream_events.relay(&state.http_client, &payload);
+            }
+
+            // Attempts of unknown streamers are not kept, anyone could fill the memory with them
+            if let (OvenDirection::Incoming, Some(streamer)) =
+                (payload.request.direction, &event.streamer)
+            {
+                if config.streamers.contains_key(streamer) {
+                    state.ingest_sessions.record_attempt(
+                        &payload,
+                        streamer,
+                        rsp.allowed,
+                        rsp.reason.clone(),
+                    );
+                }
+            }
+
+            event.allowed = Some(rsp.allowed);
+            event.reason.clone_from(&rsp.reason);
+
+            rsp.into()
+        }
+    };
+
+    if sampled {
+        if let Some(audit_db) = &state.audit_db {
+            audit_db.record(event.clone());
+        }
+        state.events.push(event);
+    }
+
+    Ok(rsp)
+}
+
+fn spawn_recording(state: Arc<OvenCtrlState>, app: String, room: String, start: bool) {
+    let task = async move {
+        let Some(api) = &state.ome_api else {
+            tracing::error!("Can't record room {room}: OME API is not configured");
+            return;
+        };
+
+        let result = if start {
+            api.start_record(&app, &room).await
+        } else {
+            api.stop_record(&app, &room).await
+        };
+
+        match result {
+            Ok(()) if start => tracing::info!("Started recording room {room}"),
+            Ok(()) => tracing::info!("Stopped recording room {room}"),
+            Err(err) => tracing::error!("Could not toggle recording of room {room}: {err:#}"),
+        }
+    };
+    tokio::spawn(task.in_current_span());
+}
+
+/// Start or stop the push relays of a stream. Each target is handled independently, so that an
+/// unreachable target does not prevent relaying to the others.
+fn spawn_push_relays(
+    state: Arc<OvenCtrlState>,
+    app: String,
+    stream: String,
+    targets: Vec<Url>,
+    start: bool,
+) {
+    let task = async move {
+        let Some(api) = &state.ome_api else {
+            tracing::error!("Can't relay stream {stream}: OME API is not configured");
+            return;
+        };
+
+        let mut failures = 0;
+        for (index, target) in targets.iter().enumerate() {
+            // Derived from the configuration, so that the relays can be stopped without
+            // remembering them
+            let id = format!("ovenctrl-{stream}-{index}");
+            let result = match PushTarget::from_url(target) {
+                Ok(_) if !start => api.stop_push(&app, &id).await,
+                Ok(target) => api.start_push(&app, &stream, &id, &target).await,
+                Err(err) => Err(err),
+            };
+
+            if let Err(err) = result {
+                failures += 1;
+                tracing::error!(
+                    "Could not {} the push relay of stream {stream} to {target}: {err:#}",
+                    if start { "start" } else { "stop" },
+                );
+            }
+        }
+
+        tracing::info!(
+            "{} {}/{} push relays of stream {stream}",
+            if start { "Started" } else { "Stopped" },
+            targets.len() - failures,
+            targets.len(),
+        );
+    };
+    tokio::spawn(task.in_current_span());
+}
+
+/// Time during which a second join with the same nonce is considered a double submission
+const JOIN_NONCE_REUSE: Duration = Duration::from_secs(10);
+/// Age after which join nonces are forgotten
+const JOIN_NONCE_LIFETIME: Duration = Duration::from_secs(60);
+
+/// Periodically forget viewing sessions, as they are kept after closing to survive reconnections
+fn spawn_session_cleanup(state: Arc<OvenCtrlState>) {
+    tokio::spawn(async move {
+        let max_age = Duration::from_secs(state.config().session_max_age_secs);
+        let mut interval = tokio::time::interval(Duration::from_secs(
+            state.config().session_cleanup_interval_secs,
+        ));
+
+        loop {
+            interval.tick().await;
+
+            let before = state.viewer_sessions.len();
+            state
+                .viewer_sessions
+                .retain(|_, started| started.elapsed() < max_age);
+            let removed = before - state.viewer_sessions.len();
+
+            tracing::debug!("Removed {removed} expired viewing sessions");
+
+            state
+                .join_nonces
+                .retain(|_, seen| seen.elapsed() < JOIN_NONCE_LIFETIME);
+            state.invites.cleanup();
+            state.bots.cleanup();
+            state.analytics.cleanup();
+        }
+    });
+}
+
+#[derive(Template)]
+#[template(path = "login.html")]
+struct LoginPage<'a> {
+    join_path: &'a str,
+    /// Identifies a submission of the form, to detect double submissions
+    nonce: String,
+}
+
+#[tracing::instrument(skip(state))]
+async fn login(state: State<Arc<OvenCtrlState>>) -> Result<Html<String>, StatusCode> {
+    let config = state.config();
+    let page = LoginPage {
+        join_path: &config.join_path,
+        nonce: uuid::Uuid::new_v4().to_string(),
+    };
+
+    page.render().map(Html).map_err(|err| {
+        tracing::error!("Could not render login page: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+#[derive(Template)]
+#[template(path = "join_denied.html")]
+struct JoinDeniedPage<'a> {
+    reason: &'a str,
+}
+
+const DEFAULT_PAGE_TITLE_TEMPLATE: &str = "Room: {room}";
+
+const DEFAULT_PRE_STREAM_HTML: &str =
+    r#"<p>The stream starts in <span class="pre_stream_countdown"></span> seconds</p>"#;
+
+/// Reason shown to viewers for invalid logins, identical for the room and the password so that it
+/// does not tell which rooms exist
+const JOIN_DENIAL_REASON: &str = "Invalid room or password";
+
+async fn join_denied(config: &OvenCtrlConfig, reason: &str) -> Response {
+    let page_path = match &config.join_denial_mode {
+        JoinDenialMode::Redirect { url } => return Redirect::to(url).into_response(),
+        JoinDenialMode::InlineHtml { page_path } => page_path,
+    };
+
+    let custom = match page_path {
+        None => None,
+        Some(path) => match tokio::fs::read_to_string(path).await {
+            Ok(page) => Some(page.replace("{reason}", &escape_html(reason))),
+            Err(err) => {
+                tracing::error!("Could not read join denial page {}: {err}", path.display());
+                None
+            }
+        },
+    };
+
+    let page = match custom {
+        Some(page) => page,
+        None => match (JoinDeniedPage { reason }).render() {
+            Ok(page) => page,
+            Err(err) => {
+                tracing::error!("Could not render join denial page: {err}");
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        },
+    };
+
+    (StatusCode::UNPROCESSABLE_ENTITY, Html(page)).into_response()
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct JoinForm {
+    room: String,
+    password: String,
+    nonce: String,
+}
+
+#[tracing::instrument(skip(state))]
+async fn join(
+    state: State<Arc<OvenCtrlState>>,
+    form: Form<JoinForm>,
+) -> Result<Html<String>, Response> {
+    let config = state.config();
+    // Also checked for the open rooms, which are not looked up
+    if !state.room_name_pattern.is_match(&form.room) {
+        tracing::warn!("Invalid room name");
+        return Err(join_denied(&config, JOIN_DENIAL_REASON).await);
+    }
+
+    let room = config.canonical_room(&form.room).unwrap_or(&form.room);
+    if !config.is_open_room(room) {
+        let Some(room_password) = config.rooms.get(room) else {
+            tracing::warn!("Invalid room");
+            return Err(join_denied(&config, JOIN_DENIAL_REASON).await);
+        };
+
+        if !room_password.contains(&form.password) {
+            tracing::warn!("Invalid password");
+            return Err(join_denied(&config, JOIN_DENIAL_REASON).await);
+        }
+    }
+
+    let previous = state.join_nonces.insert(form.nonce.clone(), Instant::now());
+    if previous.is_some_and(|seen| seen.elapsed() < JOIN_NONCE_REUSE) {
+        tracing::warn!("Join form submitted twice");
+        return Err(StatusCode::CONFLICT.into_response());
+    }
+
+    Ok(player_page(
+        &config,
+        room,
+        &form.room,
+        ("password", &form.password),
+        "",
+    ))
+}
+
+/// `value` as a JavaScript literal, which can't close the `<script>` element it is written in
+fn script_value<T: serde::Serialize + ?Sized>(value: &T) -> anyhow::Result<String> {
+    Ok(serde_json::to_string(value)?
+        .replace('<', "\\u003c")
+        .replace('>', "\\u003e")
+        .replace('&', "\\u0026"))
+}
+
+/// WebSocket URL of the OME stream `stream`, with the `credential` query parameter
+fn webrtc_url(
+    config: &OvenCtrlConfig,
+    stream: &str,
+    credential: (&str, &str),
+) -> anyhow::Result<Url> {
+    let tls = if config.external_tls { "s" } else { "" };
+    let mut url = Url::parse(&format!("ws{tls}://{}/", config.external_host))
+        .with_context(|| format!("invalid external_host: {}", config.external_host))?;
+    url.path_segments_mut()
+        .map_err(|()| anyhow::anyhow!("the WebRTC URL has no path"))?
+        .pop_if_empty()
+        .push(config.ome_app())
+        .push(stream);
+    url.query_pairs_mut()
+        .append_pair(credential.0, credential.1);
+
+    Ok(url)
+}
+
+/// Viewer page of `room`, connecting to the OME stream `stream` with the `credential` query
+/// parameter. `base` is the path of the controller root relative to the page.
+fn player_page(
+    config: &OvenCtrlConfig,
+    room: &str,
+    stream: &str,
+    credential: (&str, &str),
+    base: &str,
+) -> Html<String> {
+    let (meta_tags, meta_block) = match config.room_meta.get(room) {
+        None => (String::new(), String::new()),
+        Some(meta) => (meta.html_tags(), meta.html_block()),
+    };
+
+    let poster = match config.room_meta.get(room) {
+        Some(RoomMeta {
+            poster_url: Some(_),
+            ..
+        }) => match script_value(&format!("{base}rooms/{room}/poster")) {
+            Ok(poster) => format!("image: {poster},"),
+            Err(err) => {
+                tracing::error!("Could not add the poster: {err}");
+                String::new()
+            }
+        },
+        _ => String::new(),
+    };
+
+    let llhls = match config
+        .room_config
+        .get(room)
+        .and_then(|room_config| room_config.llhls_source.as_ref())
+    {
+        None => String::new(),
+        Some(source) => source
+            .player_source(&config.external_host, config.ome_app(), stream, credential)
+            .unwrap_or_else(|err| {
+                tracing::error!("Could not add the LLHLS source: {err:#}");
+                String::new()
+            }),
+    };
+
+    let room_config = config.room_config.get(room);
+
+    let display_name = config
+        .room_meta
+        .get(room)
+        .map(|meta| meta.display_name.as_str())
+        .unwrap_or(room);
+    let title = room_config
+        .and_then(|c| c.page_title_template.as_deref())
+        .unwrap_or(DEFAULT_PAGE_TITLE_TEMPLATE)
+        .replace("{room}", room)
+        .replace("{display_name}", display_name);
+    let favicon = match room_config.and_then(|c| c.favicon_url.as_ref()) {
+        None => String::new(),
+        Some(url) => format!(
+            r#"<link rel="icon" href="{}" />"#,
+            escape_html(url.as_str())
+        ),
+    };
+
+    let (pre_stream, start_player) = match room_config.and_then(|c| c.pre_stream_countdown_secs) {
+        None => (String::new(), "startPlayer();".to_owned()),
+        Some(countdown) => (
+            format!(
+                r#"<div id="pre_stream">{}</div>"#,
+                room_config
+                    .and_then(|c| c.pre_stream_html.as_deref())
+                    .unwrap_or(DEFAULT_PRE_STREAM_HTML)
+            ),
+            format!(
+                r#"
+            let remaining = {countdown};
+            const countdown = () => {{
+                for (const element of document.querySelectorAll(".pre_stream_countdown")) {{
+                    element.textContent = remaining;
+                }}
+                if (remaining <= 0) {{
+                    document.getElementById("pre_stream").remove();
+                    startPlayer();
+                }} else {{
+                    remaining -= 1;
+                    setTimeout(countdown, 1000);
+                }}
+            }};
+            countdown();"#
+            ),
+        ),
+    };
+
+    let ice_servers = match room_config.map(|c| &c.webrtc_ice_servers) {
+        Some(servers) if !servers.is_empty() => servers,
+        _ => &config.webrtc_ice_servers,
+    };
+    // OvenPlayer uses its own ICE servers when none are given
+    let webrtc_config = if ice_servers.is_empty() {
+        String::new()
+    } else {
+        match script_value(ice_servers) {
+            Ok(servers) => format!("webrtcConfig: {{ iceServers: {servers} }},"),
+            Err(err) => {
+                tracing::error!("Could not add the ICE servers: {err}");
+                String::new()
+            }
+        }
+    };
+
+    let features = room_config
+        .map(|c| c.player_features.clone())
+        .unwrap_or_default();
+
+    let player_script = match &config.ovenplayer_cdn_url {
+        None => format!(r#"<script src="{base}dist/ovenplayer.js"></script>"#),
+        Some(url) => match &config.ovenplayer_integrity {
+            None => format!(r#"<script src="{}"></script>"#, escape_html(url.as_str())),
+            Some(integrity) => format!(
+                r#"<script src="{}" integrity="{}" crossorigin="anonymous"></script>"#,
+                escape_html(url.as_str()),
+                escape_html(integrity)
+            ),
+        },
+    };
+
+    let webrtc_url = webrtc_url(config, stream, credential)
+        .and_then(|url| script_value(url.as_str()))
+        .unwrap_or_else(|err| {
+            tracing::error!("Could not add the WebRTC source: {err:#}");
+            r#""""#.to_owned()
+        });
+
+    let analytics = match script_value(room) {
+        Err(err) => {
+            tracing::error!("Could not add the player analytics: {err}");
+            String::new()
+        }
+        Ok(_) if config.player_analytics_capacity == 0 => String::new(),
+        Ok(room) => {
+            format!(
+                r#"
+                const sessionId = crypto.randomUUID
+                    ? crypto.randomUUID()
+                    : Math.random().toString(36).slice(2);
+                const playerEvents = {{
+                    playing: "play",
+                    paused: "pause",
+                    error: "error",
+                    complete: "complete",
+                }};
+                player.on("stateChanged", ({{ newstate }}) => {{
+                    const event = playerEvents[newstate];
+                    if (!event) {{
+                        return;
+                    }}
+                    fetch("{base}api/analytics", {{
+                        method: "POST",
+                        headers: {{ "Content-Type": "application/json" }},
+                        body: JSON.stringify({{
+                            event,
+                            room: {room},
+                            session_id: sessionId,
+                            timestamp: new Date().toISOString(),
+                        }}),
+                        keepalive: true,
+                    }}).catch(() => {{}});
+                }});"#,
+            )
+        }
+    };
+
+    Html(format!(
+        r#"
+<!DOCTYPE html>
+<html>
+    <head>
+        <meta charset="utf-8" />
+        <meta name="viewport" content="width=device-width" />
+        {meta_tags}
+        {favicon}
+        <title>{title}</title>
+    </head>
+    <body>
+        {meta_block}
+        {pre_stream}
+        <div id="player_id"></div>
+        {feature_html}
+        {player_script}
+        <script>
+            function startPlayer() {{
+                const player = OvenPlayer.create('player_id', {{
+                    {poster}
+                    {webrtc_config}
+                    {player_options}
+                    sources: [
+                        {{
+                            label: "label_for_webrtc",
+                            type: "webrtc",
+                            file: {webrtc_url}
+                        }},
+                        {llhls}
+                    ]
+                }});
+                {analytics}
+            }}
+            {start_player}
+        </script>
+    </body>
+</html>
+        "#,
+        title = escape_html(&title),
+        feature_html = features.html(),
+        player_options = features.player_options(),
+    ))
+}
+
+#[tracing::instrument(skip(state))]
+async fn room_meta(
+    state: State<Arc<OvenCtrlState>>,
+    Path(room): Path<String>,
+) -> Result<Json<RoomMeta>, StatusCode> {
+    state
+        .config()
+        .room_meta
+        .get(&room)
+        .cloned()
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+const DEFAULT_ROOM_NAME_PATTERN: &str = "^[a-zA-Z0-9_-]{1,64}$";
+
+fn default_port() -> u16 {
+    3000
+}
+
+fn default_admission_path() -> String {
+    "/oven/admission".into()
+}
+
+fn default_join_path() -> String {
+    "/join".into()
+}
+
+/// Response to a login with an invalid room or password
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum JoinDenialMode {
+    /// Redirect the viewer to `url`
+    Redirect { url: String },
+    /// Answer with `422 Unprocessable Entity` and an HTML page, where `{reason}` is replaced by
+    /// the reason of the denial. A default page is used when `page_path` is absent.
+    InlineHtml { page_path: Option<PathBuf> },
+}
+
+impl Default for JoinDenialMode {
+    fn default() -> Self {
+        JoinDenialMode::Redirect {
+            url: "/not_found.html".into(),
+        }
+    }
+}
+
+/// Decision for the admissions that no rule of the configuration covers
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Policy {
+    Allow,
+    #[default]
+    Deny,
+}
+
+fn default_http_client_user_agent() -> String {
+    concat!("ovenctrl/", env!("CARGO_PKG_VERSION")).into()
+}
+
+fn default_slow_request_threshold() -> u64 {
+    100
+}
+
+fn default_stats_cache_ttl() -> u64 {
+    5
+}
+
+fn default_http_keep_alive_timeout() -> u64 {
+    60
+}
+
+fn default_http_max_idle_connections() -> usize {
+    32
+}
+
+fn default_event_log_capacity() -> usize {
+    1000
+}
+
+fn default_room_url_path_index() -> usize {
+    1
+}
+
+fn default_event_sample_rate() -> f64 {
+    1.
+}
+
+fn default_response_compression() -> bool {
+    true
+}
+
+fn default_quota_estimated_bitrate() -> u32 {
+    6000
+}
+
+fn default_quota_reset_day() -> u8 {
+    1
+}
+
+fn default_max_webhook_subscribers() -> usize {
+    10
+}
+
+fn default_ome_vhost() -> String {
+    "default".into()
+}
+
+fn default_ome_api_failure_threshold() -> u32 {
+    5
+}
+
+fn default_ome_api_circuit_open() -> u64 {
+    30
+}
+
+/// Information displayed to the viewers of a room
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, ConfigDoc)]
+struct RoomMeta {
+    /// Name of the room on the viewer page and in its link previews
+    display_name: String,
+    /// Description of the room in the link previews
+    description: Option<String>,
+    /// Image of the room in the link previews
+    thumbnail_url: Option<Url>,
+    /// Image shown by the player before playback starts
+    poster_url: Option<Url>,
+    /// Keywords of the room
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+impl RoomMeta {
+    fn html_tags(&self) -> String {
+        let mut tags = format!(
+            r#"<meta property="og:title" content="{}" />"#,
+            escape_html(&self.display_name)
+        );
+
+        if let Some(description) = &self.description {
+            let description = escape_html(description);
+            tags += &format!(r#"<meta name="description" content="{description}" />"#);
+            tags += &format!(r#"<meta property="og:description" content="{description}" />"#);
+        }
+
+        if let Some(thumbnail) = &self.thumbnail_url {
+            tags += &format!(
+                r#"<meta property="og:image" content="{}" />"#,
+                escape_html(thumbnail.as_str())
+            );
+        }
+
+        if !self.tags.is_empty() {
+            tags += &format!(
+                r#"<meta name="keywords" content="{}" />"#,
+                escape_html(&self.tags.join(","))
+            );
+        }
+
+        tags
+    }
+
+    fn html_block(&self) -> String {
+        let description = match &self.description {
+            Some(description) => format!("<p>{}</p>", escape_html(description)),
+            None => String::new(),
+        };
+
+        format!(
+            r#"<div id="room_meta"><h1>{}</h1>{description}</div>"#,
+            escape_html(&self.display_name)
+        )
+    }
+}
+
+/// Passwords giving access to a room, either a single password or a list of passwords
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+#[serde(untagged)]
+enum RoomPasswordList {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl RoomPasswordList {
+    fn iter(&self) -> impl Iterator<Item = &str> {
+        match self {
+            RoomPasswordList::Single(password) => std::slice::from_ref(password).iter(),
+            RoomPasswordList::Multiple(passwords) => passwords.iter(),
+        }
+        .map(String::as_str)
+    }
+
+    fn contains(&self, password: &str) -> bool {
+        self.iter().any(|p| p == password)
+    }
+
+    /// Add the passwords of `other` that are missing, returns whether any was added
+    fn merge(&mut self, other: RoomPasswordList) -> bool {
+        let mut passwords = self.iter().map(String::from).collect::<Vec<_>>();
+        let before = passwords.len();
+
+        for password in other.iter() {
+            if !passwords.iter().any(|p| p == password) {
+                passwords.push(password.into());
+            }
+        }
+
+        let added = passwords.len() != before;
+        if added {
+            *self = RoomPasswordList::Multiple(passwords);
+        }
+        added
+    }
+}
+
+fn default_outgoing_url_validity() -> u64 {
+    3600
+}
+
+fn default_admin_session_timeout() -> u64 {
+    3600
+}
+
+fn default_admission_handler_timeout() -> u64 {
+    5000
+}
+
+fn default_session_cleanup_interval() -> u64 {
+    300
+}
+
+fn default_session_max_age() -> u64 {
+    86400
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, ConfigDoc)]
+struct RoomConfig {
+    /// Template of the URL viewers are redirected to.
+    ///
+    /// Supports the `{app}`, `{room}`, `{ip}` and `{expires}` placeholders
+    outgoing_signed_url_template: Option<String>,
+    /// Key used to sign the redirect URL with HMAC-SHA256
+    outgoing_url_secret: Option<String>,
+    /// Validity of the redirect URL, in seconds
+    #[serde(default = "default_outgoing_url_validity")]
+    outgoing_url_validity_secs: u64,
+    /// Templates of the URLs of secondary edges, offered along with the redirect URL and signed
+    /// in the same way
+    #[serde(default)]
+    outgoing_alternative_url_templates: Vec<String>,
+    /// LLHLS source offered by the player in addition to WebRTC
+    #[config_doc(nested)]
+    llhls_source: Option<LlhlsSourceConfig>,
+    /// Delay before the player connects to the stream, in seconds
+    pre_stream_countdown_secs: Option<u64>,
+    /// Title of the viewer page, supports the `{room}` and `{display_name}` placeholders
+    page_title_template: Option<String>,
+    /// Icon of the viewer page
+    favicon_url: Option<Url>,
+    /// HTML shown in place of the player during the countdown.
+    ///
+    /// Elements with the `pre_stream_countdown` class are filled with the remaining seconds
+    pre_stream_html: Option<String>,
+    /// Bitrate limit of the streams, passed to OME as the `maxBitrate` parameter of the ingest URL
+    max_ingest_bitrate_kbps: Option<u32>,
+    /// STUN and TURN servers of the player, replacing the global `webrtc_ice_servers`
+    #[serde(default)]
+    #[config_doc(nested)]
+    webrtc_ice_servers: Vec<IceServer>,
+    /// Destinations OME pushes the streams of the room to while they are live
+    #[serde(default)]
+    push_relay_targets: Vec<Url>,
+    /// Viewers watching the room at the same time, beyond which new viewers are denied
+    max_viewers: Option<u64>,
+    /// Elements of the player shown on the viewer page
+    #[serde(default)]
+    #[config_doc(nested)]
+    player_features: PlayerFeatureFlags,
+}
+
+/// Settings of a streamer, in addition to its key
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Default, ConfigDoc)]
+struct StreamerConfig {
+    /// Stream to any room created by `auto_create_rooms`, without it being in `allowed_streams`
+    #[serde(default)]
+    allow_any_room: bool,
+}
+
+fn default_player_feature() -> bool {
+    true
+}
+
+/// Elements of the player that can be hidden, e.g. for rooms embedded in another page
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, ConfigDoc)]
+struct PlayerFeatureFlags {
+    /// Button switching the player to fullscreen
+    #[serde(default = "default_player_feature")]
+    show_fullscreen_button: bool,
+    /// Button opening the settings menu
+    #[serde(default = "default_player_feature")]
+    show_settings_button: bool,
+    /// Speed entry of the settings menu
+    #[serde(default = "default_player_feature")]
+    show_playback_rate_control: bool,
+    /// Space to pause, arrows to seek and change the volume
+    #[serde(default = "default_player_feature")]
+    enable_keyboard_shortcuts: bool,
+}
+
+impl Default for PlayerFeatureFlags {
+    fn default() -> Self {
+        Self {
+            show_fullscreen_button: true,
+            show_settings_button: true,
+            show_playback_rate_control: true,
+            enable_keyboard_shortcuts: true,
+        }
+    }
+}
+
+impl PlayerFeatureFlags {
+    /// `controls` of the OvenPlayer configuration.
+    ///
+    /// The bundled OvenPlayer only checks that `controls` is set, the flags are applied by
+    /// [Self::player_options] and [Self::html] instead.
+    fn controls(&self) -> serde_json::Value {
+        serde_json::json!({
+            "fullscreenButton": self.show_fullscreen_button,
+            "settingsButton": self.show_settings_button,
+            "playbackRateControl": self.show_playback_rate_control,
+            "keyboardShortcuts": self.enable_keyboard_shortcuts,
+        })
+    }
+
+    /// Options of the OvenPlayer configuration disabling the hidden features
+    fn player_options(&self) -> String {
+        let mut options = format!("controls: {},", self.controls());
+        if !self.show_fullscreen_button {
+            // Double clicking the video toggles the fullscreen otherwise
+            options.push_str(" expandFullScreenUI: false,");
+        }
+        if !self.show_playback_rate_control {
+            options.push_str(" playbackRates: [1],");
+        }
+        options
+    }
+
+    /// Style hiding the elements of the player, and script ignoring its keyboard shortcuts
+    fn html(&self) -> String {
+        let mut hidden = Vec::new();
+        if !self.show_fullscreen_button {
+            hidden.push(".op-fullscreen-button");
+        }
+        if !self.show_settings_button {
+            hidden.push(".op-setting-button");
+        }
+        if !self.show_playback_rate_control {
+            hidden.push(r#".op-setting-item[op-panel-type="speed"]"#);
+        }
+
+        let mut html = String::new();
+        if !hidden.is_empty() {
+            html.push_str(&format!(
+                "<style>{} {{ display: none !important; }}</style>",
+                hidden.join(", ")
+            ));
+        }
+        if !self.enable_keyboard_shortcuts {
+            // Stopped while capturing, before reaching the listener of the player
+            html.push_str(
+                r#"<script>
+            document.addEventListener("keydown", (event) => {
+                if (event.target.closest(".ovenplayer")) {
+                    event.stopPropagation();
+                }
+            }, true);
+        </script>"#,
+            );
+        }
+        html
+    }
+}
+
+/// STUN or TURN server used by the WebRTC player
+#[derive(serde::Deserialize, serde::Serialize, Clone, ConfigDoc)]
+struct IceServer {
+    /// `stun:` or `turn:` URLs of the server
+    urls: Vec<String>,
+    /// User name of the TURN server
+    #[serde(skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    /// Password of the TURN server
+    #[serde(skip_serializing_if = "Option::is_none")]
+    credential: Option<String>,
+}
+
+impl std::fmt::Debug for IceServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IceServer")
+            .field("urls", &self.urls)
+            .field("username", &self.username)
+            .field(
+                "credential",
+                &self.credential.as_ref().map(|_| "<redacted>"),
+            )
+            .finish()
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, ConfigDoc)]
+struct LlhlsSourceConfig {
+    /// Template of the playlist URL.
+    ///
+    /// Supports the `{host}`, `{app}` and `{room}` placeholders
+    url_template: String,
+    /// Name of the source in the quality menu of the player
+    label: String,
+}
+
+impl LlhlsSourceConfig {
+    /// OvenPlayer source object for the playlist of a room
+    fn player_source(
+        &self,
+        host: &str,
+        app: &str,
+        room: &str,
+        credential: (&str, &str),
+    ) -> anyhow::Result<String> {
+        let rendered = self
+            .url_template
+            .replace("{host}", host)
+            .replace("{app}", app)
+            .replace("{room}", room);
+
+        let mut url = Url::parse(&rendered)
+            .with_context(|| format!("invalid LLHLS url for room {room}: {rendered}"))?;
+        url.query_pairs_mut()
+            .append_pair(credential.0, credential.1);
+
+        Ok(format!(
+            r#"{{ label: {}, type: "llhls", file: {} }},"#,
+            script_value(&self.label)?,
+            script_value(url.as_str())?,
+        ))
+    }
+}
+
+impl RoomConfig {
+    /// Render the viewer specific redirect URL, appending a `signature` query parameter if a secret
+    /// is configured
+    fn outgoing_url(&self, payload: &OvenAdmission) -> anyhow::Result<Option<Url>> {
+        self.outgoing_signed_url_template
+            .as_deref()
+            .map(|template| self.render_outgoing_url(template, payload))
+            .transpose()
+    }
+
+    /// Render the fallbacks of [RoomConfig::outgoing_url], only used along with it
+    fn alternative_outgoing_urls(&self, payload: &OvenAdmission) -> anyhow::Result<Vec<Url>> {
+        if self.outgoing_signed_url_template.is_none() {
+            return Ok(Vec::new());
+        }
+
+        self.outgoing_alternative_url_templates
+            .iter()
+            .map(|template| self.render_outgoing_url(template, payload))
+            .collect()
+    }
+
+    fn render_outgoing_url(&self, template: &str, payload: &OvenAdmission) -> anyhow::Result<Url> {
+        let expires = OffsetDateTime::now_utc().unix_timestamp()
+            + i64::try_from(self.outgoing_url_validity_secs)?;
+
+        let rendered = template
+            .replace("{app}", &payload.request.url.app)
+            .replace("{room}", &payload.request.url.room)
+            .replace("{ip}", &payload.client.address)
+            .replace("{expires}", &expires.to_string());
+
+        let mut url = Url::parse(&rendered)
+            .with_context(|| format!("invalid outgoing url for room: {rendered}"))?;
+
+        if let Some(secret) = &self.outgoing_url_secret {
+            let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())?;
+            mac.update(url.as_str().as_bytes());
+
+            let signature = mac
+                .finalize()
+                .into_bytes()
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect::<String>();
+
+            url.query_pairs_mut().append_pair("signature", &signature);
+        }
+
+        Ok(url)
+    }
+}
+
+/// Configuration of the controller, usually loaded from a file
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, ConfigDoc)]
+pub struct OvenCtrlConfig {
+    /// Port the controller listens on
+    #[serde(default = "default_port")]
+    port: u16,
+    /// Host of the OME server the viewers connect to
+    external_host: String,
+    /// Connect the viewers to the OME server with TLS
+    #[serde(default)]
+    external_tls: bool,
+    /// Streamer name to token
+    #[serde(default)]
+    streamers: HashMap<String, String>,
+    /// Stream room to room passwords
+    #[serde(default)]
+    rooms: HashMap<String, RoomPasswordList>,
+    /// Streamer name to allowed streams
+    #[serde(default)]
+    allowed_streams: HashMap<String, HashSet<String>>,
+    /// Streamer name to additional streamer settings
+    #[serde(default)]
+    #[config_doc(nested)]
+    streamer_config: HashMap<String, StreamerConfig>,
+    /// Streams to rooms no streamer is allowed to stream to
+    #[serde(default)]
+    #[config_doc(ty = "allow or deny")]
+    default_incoming_policy: Policy,
+    /// Viewers of rooms without passwords
+    #[serde(default)]
+    #[config_doc(ty = "allow or deny")]
+    default_outgoing_policy: Policy,
+    /// Rooms viewers can watch without a password
+    #[serde(default)]
+    public_rooms: HashSet<String>,
+    /// Create the unknown rooms streamers stream to, with a random password
+    #[serde(default)]
+    auto_create_rooms: bool,
+    /// Endpoints consulted in order after the built-in checks admitted a client
+    #[serde(default)]
+    admission_plugin_urls: Vec<Url>,
+    /// Path OME sends the admission requests to
+    #[serde(default = "default_admission_path")]
+    admission_path: String,
+    /// Path the login form is submitted to
+    #[serde(default = "default_join_path")]
+    join_path: String,
+    /// Response to a login with an invalid room or password
+    #[serde(default)]
+    #[config_doc(default = "redirect to /not_found.html")]
+    join_denial_mode: JoinDenialMode,
+    /// Credentials required to access the viewer pages
+    #[config_doc(nested)]
+    ui_basic_auth: Option<BasicAuthConfig>,
+    /// OvenPlayer script loaded by the viewer pages instead of the bundled one
+    ovenplayer_cdn_url: Option<Url>,
+    /// Subresource integrity hash of the `ovenplayer_cdn_url` script (e.g. `sha384-...`)
+    ovenplayer_integrity: Option<String>,
+    /// STUN and TURN servers of the player, for viewers behind restrictive NATs
+    #[serde(default)]
+    #[config_doc(nested)]
+    webrtc_ice_servers: Vec<IceServer>,
+    /// Headers added to every response, unless the handler already set them
+    #[serde(default)]
+    security_headers: HashMap<String, String>,
+    /// Max age of the `Strict-Transport-Security` header, only sent when `external_tls` is set
+    hsts_max_age_secs: Option<u64>,
+    /// Add `includeSubDomains` to the `Strict-Transport-Security` header
+    #[serde(default)]
+    hsts_include_subdomains: bool,
+    /// Compress the responses with brotli or gzip, for the clients that accept it
+    #[serde(default = "default_response_compression")]
+    enable_response_compression: bool,
+    /// Header of the admission requests holding the client address, when OME does not report it
+    use_ome_client_header: Option<String>,
+    /// Secrets loaded at startup, overriding the other configuration sources
+    #[config_doc(nested)]
+    vault: Option<VaultConfig>,
+    /// Cookie binding LLHLS viewers to an OME origin behind a load balancer
+    #[config_doc(nested)]
+    llhls_affinity_cookie: Option<AffinityCookieConfig>,
+    /// Expect a PROXY protocol (v1 or v2) header at the start of each connection
+    #[serde(default)]
+    proxy_protocol: bool,
+    /// Concurrent connections a client address can open, counted after the PROXY protocol header
+    max_connections_per_ip: Option<usize>,
+    /// Time an idle connection is kept open for the next request, 0 closing the HTTP/1
+    /// connections after each request
+    #[serde(default = "default_http_keep_alive_timeout")]
+    http_keep_alive_timeout_secs: u64,
+    /// Idle connections kept open to each host the controller sends requests to (OME API,
+    /// webhooks)
+    #[serde(default = "default_http_max_idle_connections")]
+    http_max_idle_connections_per_host: usize,
+    /// Accept cleartext HTTP/2 connections (h2c with prior knowledge) in addition to HTTP/1, so
+    /// that the concurrent admission requests share a connection
+    #[serde(default)]
+    enable_http2: bool,
+    /// Clients that are always admitted, without any validation
+    #[serde(default)]
+    bypass_ips: Vec<IpAddr>,
+    /// Admit without validation the requests that OME marks with `bypass`
+    #[serde(default)]
+    allow_bypass: bool,
+    /// Inclusive range of ports clients must connect from
+    #[config_doc(ty = "array of 2 integers")]
+    client_port_range: Option<(u16, u16)>,
+    /// Pattern room names must match to be streamed to
+    #[config_doc(default = "^[a-zA-Z0-9_-]{1,64}$")]
+    room_name_pattern: Option<String>,
+    /// Pattern stream keys must match, checked before comparing them to the expected key
+    stream_key_pattern: Option<String>,
+    /// Networks allowed to pull thumbnails without the password of the room. When empty, the
+    /// thumbnails require the password like the other viewers
+    #[serde(default)]
+    thumbnail_allowed_ips: Vec<IpNet>,
+    /// Stream room to information displayed to viewers
+    #[serde(default)]
+    #[config_doc(nested)]
+    room_meta: HashMap<String, RoomMeta>,
+    /// Stream room to additional room settings
+    #[serde(default)]
+    #[config_doc(nested)]
+    room_config: HashMap<String, RoomConfig>,
+    /// Token required to access the `/api` endpoints, disabled when absent
+    admin_token: Option<String>,
+    /// Validity of the sessions of the administration dashboard
+    #[serde(default = "default_admin_session_timeout")]
+    admin_session_timeout_secs: u64,
+    /// Tokens only granted some of the administration operations
+    #[serde(default)]
+    #[config_doc(nested)]
+    admin_tokens: Vec<AdminToken>,
+    /// Key signing the invite links with HMAC-SHA256. A random key is generated at startup when
+    /// absent, invalidating the links on restarts
+    invite_secret: Option<String>,
+    /// File in which the revocations and the uses of the invite links are saved. When absent they
+    /// are only kept in memory, and the links created before a restart are refused
+    #[serde(default)]
+    invite_state_path: Option<PathBuf>,
+    /// Rooms that are recorded by OME while a stream is live
+    #[serde(default)]
+    record_rooms: HashSet<String>,
+    /// User agent of the requests made by oven-ctrl
+    #[serde(default = "default_http_client_user_agent")]
+    http_client_user_agent: String,
+    /// Base URL of the OME Manager API (e.g. `http://localhost:8081`)
+    ome_api_url: Option<Url>,
+    /// Value of `<AccessToken>` in the OME `Server.xml`
+    ome_api_access_token: Option<String>,
+    /// Authentication of the API requests, replacing `ome_api_access_token`
+    ome_auth_scheme: Option<OmeAuthScheme>,
+    /// OME application of the streams, any application is accepted when absent
+    ome_app_name: Option<String>,
+    /// Host name of the OME virtual host of the streams, any host is accepted when absent
+    vhost: Option<String>,
+    /// Path segment of the stream URLs containing the room, starting at 0
+    #[serde(default = "default_room_url_path_index")]
+    room_url_path_index: usize,
+    /// Path segment of the stream URLs containing the application
+    #[config_doc(default = "the segment before the room")]
+    app_url_path_index: Option<usize>,
+    /// Virtual host the streams are published on
+    #[serde(default = "default_ome_vhost")]
+    ome_vhost: String,
+    /// Consecutive failures of the OME API after which its requests are no longer sent
+    #[serde(default = "default_ome_api_failure_threshold")]
+    ome_api_failure_threshold: u32,
+    /// Time during which the OME API requests are not sent after too many failures
+    #[serde(default = "default_ome_api_circuit_open")]
+    ome_api_circuit_open_secs: u64,
+    /// Maximum duration a viewer can watch a room, across reconnections
+    max_session_duration_secs: Option<u64>,
+    /// Interval between two removals of old viewing sessions
+    #[serde(default = "default_session_cleanup_interval")]
+    session_cleanup_interval_secs: u64,
+    /// Age after which a viewing session is forgotten
+    #[serde(default = "default_session_max_age")]
+    session_max_age_secs: u64,
+    /// Responses slower than this are logged at INFO instead of DEBUG
+    #[serde(default = "default_slow_request_threshold")]
+    slow_request_threshold_ms: u64,
+    /// Time after which an opening admission is denied, so that OME does not wait on slow plugins
+    #[serde(default = "default_admission_handler_timeout")]
+    admission_handler_timeout_ms: u64,
+    /// Alternative name of a room to the name of the room
+    #[serde(default)]
+    room_aliases: HashMap<String, String>,
+    /// Room to the category it is counted in by the metrics
+    #[serde(default)]
+    room_category: HashMap<String, String>,
+    /// Time during which the statistics of a room are served without querying the OME API again
+    #[serde(default = "default_stats_cache_ttl")]
+    stats_cache_ttl_secs: u64,
+    /// Number of admission events kept in memory
+    #[serde(default = "default_event_log_capacity")]
+    event_log_capacity: usize,
+    /// SQLite database in which the admission events are persisted, created if it doesn't exist.
+    /// When unset the events are only kept in memory
+    #[serde(default)]
+    audit_db_path: Option<PathBuf>,
+    /// Fraction of the admissions recorded in the event log and the metrics, and relayed to the
+    /// webhook subscribers
+    #[serde(default = "default_event_sample_rate")]
+    event_sample_rate: f64,
+    /// Number of player events of the viewer pages kept in memory, 0 disabling the analytics
+    #[serde(default)]
+    player_analytics_capacity: usize,
+    /// Rate limit of admissions for each streamer
+    #[config_doc(nested)]
+    rate_limit: Option<RateLimitConfig>,
+    /// Streamer name to rate limit, overriding `rate_limit`
+    #[serde(default)]
+    #[config_doc(nested)]
+    streamer_rate_limits: HashMap<String, RateLimitConfig>,
+    /// Streamer name to number of bytes they can stream during each billing period
+    #[serde(default)]
+    streamer_monthly_quota_bytes: HashMap<String, u64>,
+    /// Bitrate counted against the quotas for the rooms without `max_ingest_bitrate_kbps`
+    #[serde(default = "default_quota_estimated_bitrate")]
+    quota_estimated_bitrate_kbps: u32,
+    /// Day of the month the billing periods start on, between 1 and 28
+    #[serde(default = "default_quota_reset_day")]
+    quota_reset_day: u8,
+    /// Maximum number of subscriptions to the admissions with `/api/stream-events/subscribe`
+    #[serde(default = "default_max_webhook_subscribers")]
+    max_webhook_subscribers: usize,
+    /// Heuristics denying the viewers that look automated
+    #[serde(default)]
+    #[config_doc(default = "disabled", nested)]
+    bot_detection: BotDetectionConfig,
+}
+
+impl OvenCtrlConfig {
+    /// OME application the viewer pages connect to
+    fn ome_app(&self) -> &str {
+        self.ome_app_name.as_deref().unwrap_or("app")
+    }
+
+    /// Room designated by `alias`, `None` when it is not an alias
+    fn canonical_room(&self, alias: &str) -> Option<&str> {
+        self.room_aliases.get(alias).map(String::as_str)
+    }
+
+    /// Whether viewers can watch `room` without a password. With the allow policy, only the rooms
+    /// with a password are protected.
+    fn is_open_room(&self, room: &str) -> bool {
+        self.public_rooms.contains(room)
+            || (self.default_outgoing_policy == Policy::Allow && !self.rooms.contains_key(room))
+    }
+
+    /// Path segment of the stream URLs containing the application
+    fn app_url_path_index(&self) -> usize {
+        self.app_url_path_index
+            .unwrap_or(self.room_url_path_index.saturating_sub(1))
+    }
+
+    /// Category of a room in the metrics
+    fn room_category(&self, room: &str) -> &str {
+        self.room_category
+            .get(room)
+            .map(String::as_str)
+            .unwrap_or("uncategorized")
+    }
+
+    /// Copy of the configuration without its secrets, to be exported. The room passwords are
+    /// kept, as they are shared with the viewers.
+    pub fn redacted(&self) -> Self {
+        let mut config = self.clone();
+
+        config.streamers.values_mut().for_each(redact);
+        config.admin_token.iter_mut().for_each(redact);
+        for scoped in &mut config.admin_tokens {
+            redact(&mut scoped.token);
+        }
+        config.invite_secret.iter_mut().for_each(redact);
+        config.ome_api_access_token.iter_mut().for_each(redact);
+        match &mut config.ome_auth_scheme {
+            Some(OmeAuthScheme::Bearer { token }) => redact(token),
+            Some(OmeAuthScheme::Basic { password, .. }) => redact(password),
+            Some(OmeAuthScheme::Header { value, .. }) => redact(value),
+            None => (),
+        }
+        if let Some(vault) = &mut config.vault {
+            vault.redact();
+        }
+        if let Some(basic_auth) = &mut config.ui_basic_auth {
+            redact(&mut basic_auth.password_hash);
+        }
+        for room_config in config.room_config.values_mut() {
+            room_config.outgoing_url_secret.iter_mut().for_each(redact);
+        }
+
+        config
+    }
+
+    /// Replace `env:<VAR>` streamer keys by the value of the `<VAR>` environment variable
+    pub fn resolve_env_keys(&mut self) -> anyhow::Result<()> {
+        for (name, key) in &mut self.streamers {
+            if let Some(var) = key.strip_prefix("env:") {
+                *key = std::env::var(var).with_context(|| {
+                    format!("key of streamer {name} references missing environment variable {var}")
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Placeholder of the secrets removed from the exported configuration
+const REDACTED: &str = "<redacted>";
+
+fn redact(secret: &mut String) {
+    *secret = REDACTED.to_owned();
+}
+
+/// Shared state of the controller handlers
+#[derive(Debug)]
+pub struct OvenCtrlState {
+    /// Replaced as a whole when the configuration is modified at runtime
+    config: RwLock<Arc<OvenCtrlConfig>>,
+    rate_limits: Arc<DashMap<String, TokenBucket>>,
+    ome_api: Option<OmeApi>,
+    /// Version advertised by OME the last time its API was reached
+    ome_version: RwLock<Option<String>>,
+    room_name_pattern: Regex,
+    stream_key_pattern: Option<Regex>,
+    /// Start of the viewing session of each (client address, room)
+    viewer_sessions: DashMap<(String, String), Instant>,
+    ingest_sessions: IngestSessions,
+    viewers: ViewerCounts,
+    /// Held exclusively while updating the ingest sessions, viewer counts and metrics, so that
+    /// the snapshots of the runtime state are consistent
+    runtime_lock: RwLock<()>,
+    /// Nonces of the submitted join forms, with the time they were last seen
+    join_nonces: Arc<DashMap<String, Instant>>,
+    metrics: AdmissionMetrics,
+    events: EventLog,
+    /// Persistent copy of the event log, opened by [run] when `audit_db_path` is set
+    audit_db: Option<audit_db::AuditDb>,
+    plugins: PluginChain,
+    http_client: reqwest::Client,
+    posters: PosterCache,
+    /// Links to rooms that don't require their password
+    invites: invites::Invites,
+    quota_usage: quota::QuotaUsage,
+    stream_events: stream_events::StreamEventRelay,
+    bots: bot_detection::BotDetector,
+    analytics: analytics::PlayerAnalytics,
+    /// Capabilities reported by the OME servers
+    ome_capabilities: capabilities::NegotiatedCapabilities,
+    stats: StatsCache,
+    security_headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl OvenCtrlState {
+    /// Current configuration, unaffected by later modifications
+    pub fn config(&self) -> Arc<OvenCtrlConfig> {
+        self.config.read().unwrap().clone()
+    }
+
+    /// Modify a copy of the configuration, replacing the current one if `update` succeeds
+    fn update_config<T>(
+        &self,
+        update: impl FnOnce(&mut OvenCtrlConfig) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        let mut config = self.config.write().unwrap();
+
+        let mut updated = (**config).clone();
+        let result = update(&mut updated)?;
+        *config = Arc::new(updated);
+
+        Ok(result)
+    }
+
+    /// Add an unknown room streamed to by `streamer`, who is allowed to stream to it again. Only
+    /// called once the admission is allowed, so that the denied streams leave no room behind.
+    fn auto_create_room(&self, room: &str, streamer: &str) -> anyhow::Result<()> {
+        let password = uuid::Uuid::new_v4().to_string();
+
+        let created = self.update_config(|config| {
+            // Another admission may have created it since the configuration was read
+            if config.rooms.contains_key(room) {
+                return Ok(false);
+            }
+
+            config
+                .rooms
+                .insert(room.to_owned(), RoomPasswordList::Single(password.clone()));
+            config
+                .allowed_streams
+                .entry(streamer.to_owned())
+                .or_default()
+                .insert(room.to_owned());
+            Ok(true)
+        })?;
+
+        if created {
+            // The password is only available through the administration API, not in the logs
+            tracing::info!(
+                room,
+                streamer,
+                "Created room {room} streamed to by {streamer}"
+            );
+        }
+
+        Ok(())
+    }
+
+    fn new(
+        config: OvenCtrlConfig,
+        http_client: reqwest::Client,
+        ome_api: Option<OmeApi>,
+    ) -> anyhow::Result<Self> {
+        let pattern = config
+            .room_name_pattern
+            .as_deref()
+            .unwrap_or(DEFAULT_ROOM_NAME_PATTERN);
+        let room_name_pattern =
+            Regex::new(pattern).with_context(|| format!("invalid room_name_pattern: {pattern}"))?;
+        let stream_key_pattern = config
+            .stream_key_pattern
+            .as_deref()
+            .map(|pattern| {
+                Regex::new(pattern)
+                    .with_context(|| format!("invalid stream_key_pattern: {pattern}"))
+            })
+            .transpose()?;
+
+        let invites = match &config.invite_state_path {
+            Some(path) => invites::Invites::load(path)?,
+            None => Default::default(),
+        };
+
+        Ok(Self {
+            events: EventLog::new(config.event_log_capacity),
+            audit_db: None,
+            analytics: analytics::PlayerAnalytics::new(config.player_analytics_capacity),
+            config: RwLock::new(Arc::new(config)),
+            rate_limits: Default::default(),
+            ome_api,
+            ome_version: Default::default(),
+            room_name_pattern,
+            stream_key_pattern,
+            viewer_sessions: Default::default(),
+            ingest_sessions: Default::default(),
+            viewers: Default::default(),
+            runtime_lock: Default::default(),
+            join_nonces: Default::default(),
+            metrics: Default::default(),
+            plugins: Default::default(),
+            http_client,
+            posters: Default::default(),
+            invites,
+            quota_usage: Default::default(),
+            stream_events: Default::default(),
+            bots: Default::default(),
+            ome_capabilities: Default::default(),
+            stats: Default::default(),
+            security_headers: Vec::new(),
+        })
+    }
+}
+
+fn css_header() -> HeaderMap {
+    let mut css_header = HeaderMap::new();
+    css_header.insert("Content-Type", HeaderValue::from_static("text/css"));
+    css_header
+}
+
+impl OvenCtrlState {
+    /// Check that the OME API is reachable, if it is configured, and store the version of OME
+    pub async fn check_ome_api(&self) -> anyhow::Result<()> {
+        let Some(api) = &self.ome_api else {
+            return Ok(());
+        };
+
+        let version = api.probe().await.context("OME API is unreachable")?;
+        match &version {
+            Some(version) => tracing::info!("Connected to the OME API of {version}"),
+            None => tracing::info!("Connected to the OME API, it does not advertise its version"),
+        }
+        *self.ome_version.write().unwrap() = version;
+
+        Ok(())
+    }
+
+    /// Version of OME, as of the last successful request to its API
+    pub fn ome_version(&self) -> Option<String> {
+        self.ome_version.read().unwrap().clone()
+    }
+
+    /// Validate the configuration and build the clients of the services used by the controller
+    pub fn from_config(config: OvenCtrlConfig) -> anyhow::Result<Self> {
+        let http_client = reqwest::Client::builder()
+            .user_agent(&config.http_client_user_agent)
+            .pool_max_idle_per_host(config.http_max_idle_connections_per_host)
+            .build()?;
+
+        let ome_api = OmeApi::from_config(&config, http_client.clone())?;
+        if !config.record_rooms.is_empty() && ome_api.is_none() {
+            anyhow::bail!(
+                "record_rooms requires ome_api_url, and ome_api_access_token or ome_auth_scheme"
+            );
+        }
+        for (room, room_config) in &config.room_config {
+            if room_config.push_relay_targets.is_empty() {
+                continue;
+            }
+            if ome_api.is_none() {
+                anyhow::bail!(
+                    "push_relay_targets of room {room} requires ome_api_url, and \
+                     ome_api_access_token or ome_auth_scheme"
+                );
+            }
+            for target in &room_config.push_relay_targets {
+                PushTarget::from_url(target)?;
+            }
+        }
+        if config.session_cleanup_interval_secs == 0 {
+            anyhow::bail!("session_cleanup_interval_secs can't be 0");
+        }
+        if config.app_url_path_index() == config.room_url_path_index {
+            anyhow::bail!(
+                "the application and the room must be different segments of the stream URLs, \
+                 set app_url_path_index"
+            );
+        }
+
+        if !(0. ..=1.).contains(&config.event_sample_rate) {
+            anyhow::bail!("event_sample_rate must be between 0 and 1");
+        }
+        if !(1..=28).contains(&config.quota_reset_day) {
+            anyhow::bail!("quota_reset_day must be between 1 and 28");
+        }
+
+        for path in [&config.admission_path, &config.join_path] {
+            if !path.starts_with('/') {
+                anyhow::bail!("invalid path {path}: it must start with a /");
+            }
+        }
+
+        if let Some(auth) = &config.ui_basic_auth {
+            auth.validate()?;
+        }
+        if let Some(cookie) = &config.llhls_affinity_cookie {
+            cookie.validate()?;
+        }
+        if config.ome_app_name.is_none() {
+            tracing::warn!(
+                "ome_app_name is not configured, streams of any OME application are admitted"
+            );
+        }
+        if config.ovenplayer_integrity.is_some() && config.ovenplayer_cdn_url.is_none() {
+            tracing::warn!("ovenplayer_integrity is ignored without ovenplayer_cdn_url");
+        }
+
+        let mut security_headers = config
+            .security_headers
+            .iter()
+            .map(|(name, value)| {
+                let name = HeaderName::try_from(name)
+                    .with_context(|| format!("invalid security header name: {name}"))?;
+                let value = HeaderValue::try_from(value)
+                    .with_context(|| format!("invalid value for security header {name}"))?;
+                Ok((name, value))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        if let Some(max_age) = config.hsts_max_age_secs {
+            if config.external_tls {
+                let mut hsts = format!("max-age={max_age}");
+                if config.hsts_include_subdomains {
+                    hsts += "; includeSubDomains";
+                }
+                security_headers.push((
+                    header::STRICT_TRANSPORT_SECURITY,
+                    HeaderValue::try_from(hsts)?,
+                ));
+            } else {
+                tracing::warn!("Ignoring hsts_max_age_secs, HSTS requires external_tls");
+            }
+        }
+
+        let mut state = OvenCtrlState::new(config, http_client.clone(), ome_api)?;
+        state.security_headers = security_headers;
+        for url in &state.config().admission_plugin_urls {
+            state
+                .plugins
+                .push(Box::new(HttpPlugin::new(http_client.clone(), url.clone())));
+        }
+
+        Ok(state)
+    }
+}
+
+/// Routes of the controller, to be served or nested in a larger application
+pub fn build_router(state: Arc<OvenCtrlState>) -> Router {
+    let config = state.config();
+
+    let viewer = Router::new()
+        .route(&config.join_path, post(join))
+        .route("/rooms/:room/poster", get(poster::room_poster))
+        .route("/join-invite/:token", get(invites::join))
+        .route("/", get(login))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            ui_auth::require_ui_auth,
+        ));
+
+    let mut app = Router::new()
+        .merge(admin::router())
+        .merge(dashboard::router())
+        .merge(health::router())
+        .merge(viewer)
+        .route("/api/rooms/:room/meta", get(room_meta))
+        .route("/api/rooms/:room/stats", get(stats::room_stats))
+        .route("/api/analytics", post(analytics::record))
+        .route(
+            "/not_found.html",
+            get(|| async { (StatusCode::NOT_FOUND, Html(include_str!("not_found.html"))) }),
+        )
+        .route(
+            "/dist/normalize.css",
+            get(|| async { (css_header(), include_str!("dist/normalize.css")) }),
+        )
+        .route(
+            "/dist/milligram.min.css",
+            get(|| async { (css_header(), include_str!("dist/milligram.min.css")) }),
+        )
+        .route(
+            "/dist/milligram.min.css.map",
+            get(|| async { include_str!("dist/milligram.min.css.map") }),
+        )
+        .route(
+            "/dist/ovenplayer.js",
+            get(|| async {
+                let mut js_header = HeaderMap::new();
+                js_header.insert("Content-Type", HeaderValue::from_static("text/javascript"));
+
+                (js_header, include_str!("dist/ovenplayer.js"))
+            }),
+        )
+        .route(
+            "/dist/ovenplayer.js.map",
+            get(|| async { include_str!("dist/ovenplayer.js.map") }),
+        )
+        .with_state(state.clone());
+
+    // Only routes added before the layer are compressed: the admission responses are too small
+    // to be worth it
+    if config.enable_response_compression {
+        app = app.layer(CompressionLayer::new());
+    }
+    app = app.route(
+        &config.admission_path,
+        post(admission)
+            .layer(SetResponseHeaderLayer::overriding(
+                capabilities::OVENCTRL_CAPABILITIES,
+                capabilities::advertised(),
+            ))
+            .with_state(state.clone()),
+    );
+
+    for (name, value) in &state.security_headers {
+        app = app.layer(SetResponseHeaderLayer::if_not_present(
+            name.clone(),
+            value.clone(),
+        ));
+    }
+
+    app
+        // The default body limit is enforced while reading the decompressed body
+        .layer(RequestDecompressionLayer::new())
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(RouteSpan {
+                    admission_path: config.admission_path.as_str().into(),
+                    join_path: config.join_path.as_str().into(),
+                })
+                .on_response(LatencyLog {
+                    threshold: Duration::from_millis(config.slow_request_threshold_ms),
+                }),
+        )
+}
+
+/// Self-contained router of the controller, to be merged in a larger application.
+///
+/// The periodic cleanup of the sessions is spawned, so this must be called within a Tokio runtime.
+pub fn ovenctrl_router(config: Arc<OvenCtrlConfig>) -> anyhow::Result<Router> {
+    let state = Arc::new(OvenCtrlState::from_config(Arc::unwrap_or_clone(config))?);
+    spawn_session_cleanup(state.clone());
+
+    Ok(build_router(state))
+}
+
+/// Run the self-test of the configuration, then serve the controller until an error occurs. When
+/// `require_ome_api` is set, it does not start if the configured OME API can't be reached.
+pub async fn run(config: OvenCtrlConfig, require_ome_api: bool) -> anyhow::Result<()> {
+    let port = config.port;
+    let proxy_protocol = config.proxy_protocol;
+    let max_connections_per_ip = config.max_connections_per_ip;
+    let http2 = config.enable_http2;
+    let keep_alive_timeout = match config.http_keep_alive_timeout_secs {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    };
+
+    let mut state = OvenCtrlState::from_config(config)?;
+    if let Some(path) = &state.config().audit_db_path {
+        state.audit_db = Some(audit_db::AuditDb::open(path).await?);
+    }
+    let state = Arc::new(state);
+    if let Err(err) = state.check_ome_api().await {
+        if require_ome_api {
+            return Err(err);
+        }
+        tracing::warn!("{err:#}, starting anyway");
+    }
+    spawn_session_cleanup(state.clone());
+
+    let self_test = self_test::self_test(&state);
+    let failures = self_test.iter().filter(|r| !r.passed()).collect::<Vec<_>>();
+    for failure in &failures {
+        tracing::error!(
+            "Self-test failed: {} (expected allowed: {}, reason: {:?})",
+            failure.description,
+            failure.expected_allowed,
+            failure.reason,
+        );
+    }
+    tracing::info!(
+        "Self-test: {}/{} checks passed",
+        self_test.len() - failures.len(),
+        self_test.len()
+    );
+    if !failures.is_empty() {
+        anyhow::bail!("admission self-test failed");
+    }
+
+    let app = build_router(state);
+
+    let listener = tokio::net::TcpListener::bind(("0.0.0.0", port)).await?;
+
+    tracing::info!("Starting oven-ctrl");
+
+    serve::serve(
+        listener,
+        app,
+        proxy_protocol,
+        max_connections_per_ip,
+        keep_alive_timeout,
+        http2,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        external_host = "localhost:3333"
+
+        [streamers]
+        alice = "alice-key"
+        bob = "bob-key"
+
+        [allowed_streams]
+        alice = ["main"]
+
+        [rooms]
+        main = "hunter2"
+    "#;
+
+    fn state() -> OvenCtrlState {
+        OvenCtrlState::new(
+            toml::from_str(CONFIG).unwrap(),
+            reqwest::Client::new(),
+            None,
+        )
+        .unwrap()
+    }
+
+    fn payload(direction: &str, status: &str, url: &str) -> OvenAdmission {
+        serde_json::from_value(serde_json::json!({
+            "client": {
+                "address": "192.168.0.10",
+                "port": 40000,
+                "user_agent": "test"
+            },
+            "request": {
+                "direction": direction,
+                "protocol": "WebRTC",
+                "status": status,
+                "url": url,
+                "time": "2021-05-12T13:45:00.000Z"
+            }
+        }))
+        .unwrap()
+    }
+
+    fn incoming(url: &str) -> OvenAdmission {
+        payload("incoming", "opening", url)
+    }
+
+    fn denial(payload: OvenAdmission) -> DenialReason {
+        let err = handle_opening_admission(&state(), &payload).unwrap_err();
+        DenialReason::of(&err)
+    }
+
+    #[test]
+    fn valid_incoming() {
+        let rsp = handle_opening_admission(
+            &state(),
+            &incoming("rtmp://ome/app/main?name=alice&key=alice-key"),
+        )
+        .unwrap();
+
+        assert!(rsp.allowed);
+        assert!(rsp.reason.is_none());
+    }
+
+    #[test]
+    fn max_ingest_bitrate() {
+        let state = state_with("room_config.main.max_ingest_bitrate_kbps = 6000");
+
+        let rsp = handle_opening_admission(
+            &state,
+            &incoming("rtmp://ome/app/main?name=alice&key=alice-key"),
+        )
+        .unwrap();
+
+        assert!(rsp.allowed);
+        assert_eq!(
+            rsp.new_url.unwrap().as_str(),
+            "rtmp://ome/app/main?name=alice&key=alice-key&maxBitrate=6000"
+        );
+    }
+
+    fn state_with(extra: &str) -> OvenCtrlState {
+        let config = format!("{extra}\n{CONFIG}");
+        OvenCtrlState::new(
+            toml::from_str(&config).unwrap(),
+            reqwest::Client::new(),
+            None,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn default_policies() {
+        let state = state_with(
+            r#"
+            default_incoming_policy = "allow"
+            default_outgoing_policy = "allow"
+            public_rooms = ["main"]
+            "#,
+        );
+
+        // Unassigned rooms are open to streamers, and rooms without a password to viewers
+        let open = [
+            incoming("rtmp://ome/app/other?name=bob&key=bob-key"),
+            payload("outgoing", "opening", "wss://ome/app/other"),
+            payload("outgoing", "opening", "wss://ome/app/main"),
+        ];
+        for admission in open {
+            assert!(
+                handle_opening_admission(&state, &admission)
+                    .unwrap()
+                    .allowed
+            );
+        }
+
+        let err = handle_opening_admission(
+            &state,
+            &incoming("rtmp://ome/app/main?name=bob&key=bob-key"),
+        )
+        .unwrap_err();
+        assert_eq!(DenialReason::of(&err), DenialReason::RoomNotAllowed);
+
+        assert_eq!(
+            denial(payload("outgoing", "opening", "wss://ome/app/other")),
+            DenialReason::MalformedRequest
+        );
+    }
+
+    #[test]
+    fn vhost() {
+        let state = state_with(r#"vhost = "live.example.com""#);
+
+        let admission = incoming("rtmp://live.example.com/app/main?name=alice&key=alice-key");
+        assert!(
+            handle_opening_admission(&state, &admission)
+                .unwrap()
+                .allowed
+        );
+
+        let err = handle_opening_admission(
+            &state,
+            &incoming("rtmp://other.example.com/app/main?name=alice&key=alice-key"),
+        )
+        .unwrap_err();
+        assert_eq!(DenialReason::of(&err), DenialReason::UnknownVirtualHost);
+    }
+
+    #[test]
+    fn missing_query() {
+        assert_eq!(
+            denial(incoming("rtmp://ome/app/main")),
+            DenialReason::MalformedRequest
+        );
+    }
+
+    #[test]
+    fn unknown_streamer() {
+        assert_eq!(
+            denial(incoming("rtmp://ome/app/main?name=eve&key=alice-key")),
+            DenialReason::UnknownStreamer
+        );
+    }
+
+    #[test]
+    fn wrong_key() {
+        assert_eq!(
+            denial(incoming("rtmp://ome/app/main?name=alice&key=bob-key")),
+            DenialReason::InvalidKey
+        );
+    }
+
+    #[test]
+    fn no_allowed_streams() {
+        assert_eq!(
+            denial(incoming("rtmp://ome/app/main?name=bob&key=bob-key")),
+            DenialReason::RoomNotAllowed
+        );
+    }
+
+    #[test]
+    fn room_not_allowed() {
+        assert_eq!(
+            denial(incoming("rtmp://ome/app/other?name=alice&key=alice-key")),
+            DenialReason::RoomNotAllowed
+        );
+    }
+
+    #[tokio::test]
+    async fn auto_create_rooms() {
+        let state = Arc::new(state_with(
+            "auto_create_rooms = true\nstreamer_config.bob.allow_any_room = true",
+        ));
+        let send = |payload: OvenAdmission| {
+            admission(
+                State(state.clone()),
+                None,
+                HeaderMap::new(),
+                Ok(Json(payload)),
+            )
+        };
+        let allowed = |rsp: Result<Json<OvenResponse>, (StatusCode, String)>| matches!(rsp.unwrap().0, OvenResponse::Opening(rsp) if rsp.allowed);
+
+        let rsp = send(incoming("rtmp://ome/app/other?name=alice&key=alice-key")).await;
+        assert!(!allowed(rsp));
+        assert!(!state.config().rooms.contains_key("other"));
+
+        let bob = "rtmp://ome/app/new?name=bob&key=bob-key";
+        assert!(allowed(send(incoming(bob)).await));
+        let config = state.config();
+        let password = config.rooms["new"].iter().next().unwrap().to_owned();
+        assert!(config.allowed_streams["bob"].contains("new"));
+
+        // The room now exists, only bob is allowed to stream to it
+        assert!(allowed(send(incoming(bob)).await));
+        assert_eq!(state.config().rooms["new"].iter().next(), Some(&*password));
+        let rsp = send(incoming("rtmp://ome/app/new?name=alice&key=alice-key")).await;
+        assert!(!allowed(rsp));
+
+        let viewer = payload(
+            "outgoing",
+            "opening",
+            &format!("wss://ome/app/new?password={password}"),
+        );
+        assert!(allowed(send(viewer).await));
+
+        // Streams denied by a plugin, after the built-in checks, don't create rooms
+        let mut state =
+            state_with("auto_create_rooms = true\nstreamer_config.bob.allow_any_room = true");
+        let unreachable = "http://127.0.0.1:1/".parse().unwrap();
+        state.plugins.push(Box::new(HttpPlugin::new(
+            reqwest::Client::new(),
+            unreachable,
+        )));
+        let state = Arc::new(state);
+        let rsp = admission(
+            State(state.clone()),
+            None,
+            HeaderMap::new(),
+            Ok(Json(incoming(bob))),
+        )
+        .await;
+        assert!(!allowed(rsp));
+        assert!(!state.config().rooms.contains_key("new"));
+    }
+
+    #[test]
+    fn outgoing() {
+        let rsp = handle_opening_admission(
+            &state(),
+            &payload("outgoing", "opening", "wss://ome/app/main?password=hunter2"),
+        )
+        .unwrap();
+        assert!(rsp.allowed);
+
+        assert_eq!(
+            denial(payload(
+                "outgoing",
+                "opening",
+                "wss://ome/app/main?password=wrong"
+            )),
+            DenialReason::InvalidPassword
+        );
+    }
+
+    #[test]
+    fn alternative_urls() {
+        let state = state_with(
+            "room_config.main.outgoing_signed_url_template = \"https://edge1/{room}\"\n\
+             room_config.main.outgoing_alternative_url_templates = [\"https://edge2/{room}\"]",
+        );
+
+        let rsp = handle_opening_admission(
+            &state,
+            &payload("outgoing", "opening", "wss://ome/app/main?password=hunter2"),
+        )
+        .unwrap();
+        assert_eq!(rsp.new_url.unwrap().as_str(), "https://edge1/main");
+        assert_eq!(
+            rsp.alternative_urls.unwrap(),
+            vec![Url::parse("https://edge2/main").unwrap()]
+        );
+    }
+
+    #[test]
+    fn bot_detection() {
+        assert!(bot_detection::glob_match("curl/*", "curl/8.0"));
+        assert!(bot_detection::glob_match(
+            "*Headless*",
+            "Mozilla HeadlessChrome"
+        ));
+        assert!(!bot_detection::glob_match("curl/*", "Mozilla curl/8.0"));
+        assert!(!bot_detection::glob_match("test", "tests"));
+
+        let state = state_with("bot_detection = { max_identical_ua_per_minute = 1 }");
+
+        let url = "wss://ome/app/main?password=hunter2";
+        let viewer = payload("outgoing", "opening", url);
+        assert!(handle_opening_admission(&state, &viewer).is_ok());
+        assert!(handle_opening_admission(&state, &viewer).is_ok());
+
+        let mut other = payload("outgoing", "opening", url);
+        other.client.address = "192.168.0.11".into();
+        let err = handle_opening_admission(&state, &other).unwrap_err();
+        assert_eq!(DenialReason::of(&err), DenialReason::BotDetected);
+    }
+
+    #[test]
+    fn ome_capabilities() {
+        let parsed = capabilities::OmeCapabilities::parse("version=2,features=new-url, lifetime");
+        assert_eq!(parsed.version, Some(2));
+        assert!(parsed.supports("new-url"));
+        assert!(parsed.supports("lifetime"));
+        assert!(!parsed.supports("rate-limit"));
+
+        let negotiated = capabilities::NegotiatedCapabilities::default();
+        let source = Some(IpAddr::from([10, 0, 0, 1]));
+        assert_eq!(negotiated.negotiate(source, &HeaderMap::new()), None);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            capabilities::OME_CAPABILITIES,
+            HeaderValue::from_static("version=1,features=lifetime"),
+        );
+        negotiated.negotiate(source, &headers);
+        let stored = negotiated.negotiate(source, &HeaderMap::new()).unwrap();
+        assert!(!stored.supports("new-url"));
+    }
+
+    #[test]
+    fn push_relay_targets() {
+        let target = PushTarget::from_url(&Url::parse("rtmp://edge/app/key").unwrap()).unwrap();
+        assert_eq!(target.protocol, "rtmp");
+        assert_eq!(target.url.as_str(), "rtmp://edge/app");
+        assert_eq!(target.stream_key.as_deref(), Some("key"));
+
+        let target = PushTarget::from_url(&Url::parse("srt://edge:9999").unwrap()).unwrap();
+        assert_eq!(target.protocol, "srt");
+        assert!(target.stream_key.is_none());
+
+        assert!(PushTarget::from_url(&Url::parse("rtmp://edge").unwrap()).is_err());
+        assert!(PushTarget::from_url(&Url::parse("https://edge/app").unwrap()).is_err());
+    }
+
+    #[test]
+    fn ome_bypass() {
+        let url = "rtmp://ome/app/main?name=eve&key=wrong";
+        let mut request = incoming(url);
+        request.request.bypass = Some(true);
+        assert!(handle_opening_admission(&state(), &request).is_err());
+
+        let state = state_with("allow_bypass = true");
+        assert!(handle_opening_admission(&state, &request).unwrap().allowed);
+        assert!(handle_opening_admission(&state, &incoming(url)).is_err());
+    }
+
+    #[tokio::test]
+    async fn room_url_path_index() {
+        let state = Arc::new(state_with(
+            "room_url_path_index = 2\nome_app_name = \"app\"",
+        ));
+
+        let rsp = admission(
+            State(state.clone()),
+            None,
+            HeaderMap::new(),
+            Ok(Json(incoming(
+                "rtmp://ome/default/app/main?name=alice&key=alice-key",
+            ))),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(&rsp.0, OvenResponse::Opening(rsp) if rsp.allowed));
+        assert_eq!(state.ingest_sessions.of_streamer("alice")[0].room, "main");
+
+        let err = admission(
+            State(state),
+            None,
+            HeaderMap::new(),
+            Ok(Json(incoming(
+                "rtmp://ome/app/main?name=alice&key=alice-key",
+            ))),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.0, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn closing() {
+        let rsp = admission(
+            State(Arc::new(state())),
+            None,
+            HeaderMap::new(),
+            Ok(Json(payload(
+                "incoming",
+                "closing",
+                "rtmp://ome/app/main?name=eve&key=wrong",
+            ))),
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(rsp.0, OvenResponse::Closing(_)));
+    }
+
+    #[tokio::test]
+    async fn duplicate_closing() {
+        let state = Arc::new(state());
+        let url = "rtmp://ome/app/main?name=alice&key=alice-key";
+        for (direction, status) in [
+            ("incoming", "opening"),
+            ("incoming", "closing"),
+            ("incoming", "closing"),
+            ("outgoing", "closing"),
+        ] {
+            let _ = admission(
+                State(state.clone()),
+                None,
+                HeaderMap::new(),
+                Ok(Json(payload(direction, status, url))),
+            )
+            .await
+            .unwrap();
+        }
+
+        assert!(state.ingest_sessions.of_streamer("alice").is_empty());
+        assert_eq!(state.viewers.get("main"), 0);
+        assert_eq!(state.metrics.snapshot().spurious_closings, 2);
+    }
+
+    #[tokio::test]
+    async fn unsampled_admissions() {
+        let state = Arc::new(state_with("event_sample_rate = 0.0"));
+
+        let rsp = admission(
+            State(state.clone()),
+            None,
+            HeaderMap::new(),
+            Ok(Json(incoming(
+                "rtmp://ome/app/main?name=alice&key=alice-key",
+            ))),
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(&rsp.0, OvenResponse::Opening(rsp) if rsp.allowed));
+        assert_eq!(state.ingest_sessions.of_streamer("alice").len(), 1);
+        assert!(state.events.query(None, None).is_empty());
+        assert_eq!(state.metrics.snapshot().total_requests, 0);
+    }
+
+    #[test]
+    fn config_fields_are_documented() {
+        let config: OvenCtrlConfig = toml::from_str(CONFIG).unwrap();
+        let serde_json::Value::Object(fields) = serde_json::to_value(config).unwrap() else {
+            panic!("the configuration is not serialized as an object");
+        };
+
+        let config_doc = OvenCtrlConfig::config_doc();
+        let documented = config_doc
+            .iter()
+            .map(|field| field.name)
+            .collect::<HashSet<_>>();
+        let actual = fields.keys().map(String::as_str).collect::<HashSet<_>>();
+        assert_eq!(documented, actual);
+
+        for field in &config_doc {
+            let default = match &fields[field.name] {
+                serde_json::Value::String(value) => value.clone(),
+                serde_json::Value::Number(_) | serde_json::Value::Bool(_) => {
+                    fields[field.name].to_string()
+                }
+                _ => continue,
+            };
+            if field.name != "external_host" {
+                assert_eq!(field.default, default, "default of {}", field.name);
+            }
+        }
+
+        let room_config = config_doc
+            .iter()
+            .find(|field| field.name == "room_config")
+            .unwrap();
+        let player_features = room_config
+            .fields
+            .iter()
+            .find(|field| field.name == "player_features")
+            .unwrap();
+        assert_eq!(player_features.fields.len(), 4);
+        assert!(player_features
+            .fields
+            .iter()
+            .all(|field| field.ty == "boolean" && field.default == "true"));
+    }
+
+    #[test]
+    fn connection_attempts() {
+        let sessions = IngestSessions::default();
+        for i in 0..25 {
+            let payload = incoming(&format!("rtmp://ome/app/main?name=alice&key=key-{i}"));
+            sessions.record_attempt(&payload, "alice", i == 24, None);
+        }
+
+        let attempts = sessions.attempts_of("alice");
+        assert_eq!(attempts.len(), 20);
+        assert_eq!(attempts[0].key_prefix.as_deref(), Some("key-"));
+        assert_eq!(attempts[0].outcome, sessions::AttemptOutcome::Denied);
+        assert_eq!(attempts[19].outcome, sessions::AttemptOutcome::Allowed);
+        assert!(sessions.attempts_of("bob").is_empty());
+    }
+
+    #[test]
+    fn quota_billing_period() {
+        let date = |year, month, day| {
+            time::Date::from_calendar_date(year, time::Month::try_from(month).unwrap(), day)
+                .unwrap()
+        };
+
+        assert_eq!(quota::period_start(date(2024, 3, 15), 1), date(2024, 3, 1));
+        assert_eq!(
+            quota::period_start(date(2024, 3, 15), 15),
+            date(2024, 3, 15)
+        );
+        assert_eq!(
+            quota::period_start(date(2024, 3, 14), 15),
+            date(2024, 2, 15)
+        );
+        assert_eq!(
+            quota::period_start(date(2024, 1, 10), 28),
+            date(2023, 12, 28)
+        );
+    }
+
+    #[test]
+    fn import_rooms_csv() {
+        let csv = "room_name,password,allowed_streamers,category\n\
+                   main,pw,alice;bob,talks\n\
+                   \"side\",\"a,\"\"b\",\"alice, carol\"\n\
+                   bad room,pw,alice\n\
+                   main,other,bob\n\
+                   empty,,alice\n";
+        let (import, errors) = room_import::RoomImport::parse(csv);
+
+        assert_eq!(
+            import.rooms,
+            [("main", "pw"), ("side", "a,\"b")]
+                .map(|(room, password)| (room.to_owned(), password.to_owned()))
+                .into()
+        );
+        assert_eq!(
+            import.allowed_streams["alice"],
+            ["main".to_owned(), "side".to_owned()].into()
+        );
+        assert_eq!(import.allowed_streams["bob"], ["main".to_owned()].into());
+        assert_eq!(import.allowed_streams["carol"], ["side".to_owned()].into());
+        assert_eq!(
+            import.room_category,
+            [("main".to_owned(), "talks".to_owned())].into()
+        );
+        assert_eq!(errors.len(), 3);
+        assert!(errors[0].starts_with("line 4:"), "{errors:?}");
+        assert!(errors[1].contains("duplicate room main"), "{errors:?}");
+        assert!(errors[2].contains("missing password"), "{errors:?}");
+    }
+
+    #[test]
+    fn import_rooms_csv_quoted_newline() {
+        let csv = "lobby,\"first\nsecond\",alice\n\
+                   bad room,pw,alice\n";
+        let (import, errors) = room_import::RoomImport::parse(csv);
+
+        // Without a header, the first line is a room
+        assert_eq!(import.rooms["lobby"], "first\nsecond");
+        assert_eq!(import.allowed_streams["alice"], ["lobby".to_owned()].into());
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("line 3:"), "{errors:?}");
+    }
+
+    #[test]
+    fn player_feature_flags() {
+        let config: OvenCtrlConfig = toml::from_str(CONFIG).unwrap();
+        let page = player_page(&config, "main", "main", ("password", "hunter2"), "../").0;
+        assert!(!page.contains("<style>"));
+        assert!(page.contains(r#""settingsButton":true"#));
+
+        let config = format!(
+            "{CONFIG}\n[room_config.main.player_features]\n\
+             show_settings_button = false\nenable_keyboard_shortcuts = false\n"
+        );
+        let config: OvenCtrlConfig = toml::from_str(&config).unwrap();
+        let page = player_page(&config, "main", "main", ("password", "hunter2"), "../").0;
+        assert!(page.contains(r#""settingsButton":false"#));
+        assert!(page.contains(r#""fullscreenButton":true"#));
+        assert!(page.contains("<style>.op-setting-button { display: none !important; }</style>"));
+        assert!(page.contains("event.stopPropagation()"));
+        assert!(!page.contains("playbackRates"));
+    }
+
+    #[tokio::test]
+    async fn player_analytics() {
+        let state = Arc::new(state_with("player_analytics_capacity = 2"));
+        let send = |event: &str, room: &str, session_id: &str| {
+            let event = serde_json::from_value(serde_json::json!({
+                "event": event,
+                "room": room,
+                "session_id": session_id,
+                "timestamp": "2024-05-01T12:00:00Z",
+            }))
+            .unwrap();
+            analytics::record(State(state.clone()), None, Json(event))
+        };
+
+        for (event, session_id) in [("play", "a"), ("pause", "a"), ("play", "b")] {
+            assert_eq!(
+                send(event, "main", session_id).await,
+                Ok(StatusCode::NO_CONTENT)
+            );
+        }
+        assert_eq!(
+            send("play", "unknown", "a").await.unwrap_err().0,
+            StatusCode::NOT_FOUND
+        );
+
+        // Only the latest events are kept
+        let summary = state.analytics.summary();
+        assert_eq!(summary.events, 2);
+        let main = &summary.by_room["main"];
+        assert_eq!(main.sessions, 2);
+        assert_eq!(main.by_event[&analytics::PlayerEvent::Play], 1);
+        assert_eq!(main.by_event[&analytics::PlayerEvent::Pause], 1);
+
+        let mut limited = false;
+        for _ in 0..20 {
+            limited |= send("play", "main", "a")
+                .await
+                .is_err_and(|(status, _)| status == StatusCode::TOO_MANY_REQUESTS);
+        }
+        assert!(limited);
+    }
+
+    #[test]
+    fn revoked_invites() {
+        let state = state();
+        let config = state.config();
+        let invites = &state.invites;
+        let token = invites.create(&config, "main".to_owned(), Duration::from_secs(60), 2);
+        let (_, pass) = invites.open(&config, &token).unwrap();
+        assert!(invites.admits(&config, &pass, "main", "192.168.0.10"));
+
+        assert_eq!(invites.revoke("main").unwrap(), 1);
+        assert!(!invites.admits(&config, &pass, "main", "192.168.0.10"));
+        assert!(invites.open(&config, &token).is_none());
+
+        let token = invites.create(&config, "main".to_owned(), Duration::from_secs(60), 1);
+        let (_, pass) = invites.open(&config, &token).unwrap();
+        assert!(invites.admits(&config, &pass, "main", "192.168.0.10"));
+    }
+
+    #[test]
+    fn invite_uses() {
+        let state = state_with("invite_secret = \"invite-secret\"\n");
+        let config = state.config();
+        let invites = &state.invites;
+        let token = invites.create(&config, "main".to_owned(), Duration::from_secs(60), 1);
+
+        // The invite can not be used as a pass, nor tampered with
+        assert!(!invites.admits(&config, &token, "main", "192.168.0.10"));
+        let (claims, signature) = token.rsplit_once('.').unwrap();
+        let tampered = format!("{}.{signature}", claims.replacen(".1.", ".9.", 1));
+        assert!(invites.open(&config, &tampered).is_none());
+
+        let (room, pass) = invites.open(&config, &token).unwrap();
+        assert_eq!(room, "main");
+        assert!(invites.open(&config, &token).is_none());
+
+        // The pass is bound to its first client, and to the room of the invite
+        assert!(invites.admits(&config, &pass, "main", "192.168.0.10"));
+        assert!(invites.admits(&config, &pass, "main", "192.168.0.10"));
+        assert!(!invites.admits(&config, &pass, "main", "192.168.0.11"));
+        assert!(!invites.admits(&config, &pass, "other", "192.168.0.10"));
+    }
+
+    #[test]
+    fn invite_restarts() {
+        let path = std::env::temp_dir().join(format!("ovenctrl-{}.json", uuid::Uuid::new_v4()));
+        let restart = || {
+            state_with(&format!(
+                "invite_secret = \"invite-secret\"\ninvite_state_path = {:?}\n",
+                path.display().to_string()
+            ))
+        };
+        let state = restart();
+        let config = state.config();
+        let invites = &state.invites;
+
+        let revoked = invites.create(&config, "main".to_owned(), Duration::from_secs(60), 1);
+        invites.revoke("main").unwrap();
+        let used = invites.create(&config, "main".to_owned(), Duration::from_secs(60), 1);
+        let (_, pass) = invites.open(&config, &used).unwrap();
+        assert!(invites.admits(&config, &pass, "main", "192.168.0.10"));
+        let valid = invites.create(&config, "main".to_owned(), Duration::from_secs(60), 1);
+
+        // The counters are restored from invite_state_path
+        let restarted = restart();
+        let invites = &restarted.invites;
+        assert!(invites.open(&config, &revoked).is_none());
+        assert!(invites.open(&config, &used).is_none());
+        assert!(!invites.admits(&config, &pass, "main", "192.168.0.11"));
+        assert!(invites.admits(&config, &pass, "main", "192.168.0.10"));
+        assert!(invites.open(&config, &valid).is_some());
+
+        // Without it or without invite_secret, the counters are unknown and the links are refused
+        let valid = invites.create(&config, "main".to_owned(), Duration::from_secs(60), 1);
+        for config in ["invite_secret = \"invite-secret\"\n", ""] {
+            let unconfigured = state_with(config);
+            assert!(unconfigured
+                .invites
+                .open(&unconfigured.config(), &valid)
+                .is_none());
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// Status of an administration API request
+    async fn admin_status(
+        state: &Arc<OvenCtrlState>,
+        path: &str,
+        token: Option<&str>,
+    ) -> StatusCode {
+        use tower::ServiceExt;
+
+        let mut request = axum::http::Request::get(path);
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+        }
+
+        admin::router()
+            .with_state(state.clone())
+            .oneshot(request.body(axum::body::Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn admin_permissions() {
+        let state = Arc::new(state());
+        assert_eq!(
+            admin_status(&state, "/api/rooms", None).await,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            admin_status(&state, "/api/rooms", Some("admin-token")).await,
+            StatusCode::NOT_FOUND
+        );
+
+        let state = Arc::new(state_with(
+            "admin_token = \"admin-token\"\n\
+             admin_tokens = [{ token = \"config-token\", permissions = [\"read_config\"] }]\n",
+        ));
+        for path in ["/api/rooms", "/api/state/snapshot"] {
+            assert_eq!(
+                admin_status(&state, path, None).await,
+                StatusCode::UNAUTHORIZED
+            );
+            assert_eq!(
+                admin_status(&state, path, Some("wrong-token")).await,
+                StatusCode::UNAUTHORIZED
+            );
+            assert_eq!(
+                admin_status(&state, path, Some("admin-token")).await,
+                StatusCode::OK
+            );
+        }
+
+        // A scoped token is only accepted by the endpoints of its permissions
+        assert_eq!(
+            admin_status(&state, "/api/rooms", Some("config-token")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            admin_status(&state, "/api/state/snapshot", Some("config-token")).await,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[test]
+    fn circuit_breaker() {
+        let breaker = circuit_breaker::CircuitBreaker::new("test", 2, Duration::from_millis(50));
+        breaker.check().unwrap();
+        breaker.record_failure();
+        breaker.check().unwrap();
+        breaker.record_success();
+
+        // Closed -> Open after the failure threshold
+        breaker.record_failure();
+        breaker.record_failure();
+        assert!(breaker.is_open());
+        assert!(breaker.check().is_err());
+
+        // Open -> HalfOpen after the cooldown, letting a single trial request through
+        std::thread::sleep(Duration::from_millis(60));
+        breaker.check().unwrap();
+        assert!(breaker.check().is_err());
+
+        // HalfOpen -> Open when the trial fails
+        breaker.record_failure();
+        assert!(breaker.is_open());
+        assert!(breaker.check().is_err());
+
+        // HalfOpen -> Closed when the trial succeeds
+        std::thread::sleep(Duration::from_millis(60));
+        breaker.check().unwrap();
+        breaker.record_success();
+        assert!(!breaker.is_open());
+        breaker.check().unwrap();
+        breaker.check().unwrap();
+    }
+
+    #[test]
+    fn room_capacity() {
+        let state = state_with("room_config.main.max_viewers = 1\n");
+        let [overview] = &admin::room_overviews(&state)[..] else {
+            panic!("expected a single room");
+        };
+        assert!(overview.has_capacity_limit);
+        assert_eq!(overview.capacity, Some(1));
+        assert_eq!(overview.categories, ["uncategorized"]);
+
+        let viewer = payload("outgoing", "opening", "wss://ome/app/main?password=hunter2");
+        assert!(handle_opening_admission(&state, &viewer).is_ok());
+        state.viewers.join("main");
+
+        let err = handle_opening_admission(&state, &viewer).unwrap_err();
+        assert_eq!(DenialReason::of(&err), DenialReason::RoomFull);
+        assert_eq!(admin::room_overviews(&state)[0].active_viewers, 1);
+
+        state.viewers.leave("main");
+        assert!(handle_opening_admission(&state, &viewer).is_ok());
+    }
+
+    #[test]
+    fn environment_variables() {
+        let vars = [
+            ("OVEN_CTRL_PORT", "8080"),
+            ("OVEN_CTRL_EXTERNAL_HOST", "ome.example.com"),
+            ("OVEN_CTRL_STREAMERS_carol", "carol-key"),
+            ("OVEN_CTRL_ROOMS_main", "new-password"),
+        ];
+        let vars = vars
+            .into_iter()
+            .map(|(name, value)| (name.to_owned(), value.to_owned()))
+            .collect();
+
+        let mut builder = config::Config::builder()
+            .add_source(config::File::from_str(CONFIG, config::FileFormat::Toml));
+        for (source, _) in config_help::environment_sources(Some(vars)) {
+            builder = builder.add_source(source);
+        }
+        let config: OvenCtrlConfig = builder.build().unwrap().try_deserialize().unwrap();
+
+        assert_eq!(config.port, 8080);
+        assert_eq!(config.external_host, "ome.example.com");
+        // The entries of the tables keep the names they had with a single environment source
+        assert_eq!(config.streamers["carol"], "carol-key");
+        assert_eq!(config.streamers["alice"], "alice-key");
+        assert!(config.rooms["main"].contains("new-password"));
+    }
+
+    #[tokio::test]
+    async fn join_script_injection() {
+        let state = Arc::new(state_with("default_outgoing_policy = \"allow\"\n"));
+        let injection = "</script><script>alert(1)//";
+        let join_form = |room: &str, password: &str| {
+            Form(JoinForm {
+                room: room.into(),
+                password: password.into(),
+                nonce: uuid::Uuid::new_v4().to_string(),
+            })
+        };
+
+        assert!(join(State(state.clone()), join_form(injection, "hunter2"))
+            .await
+            .is_err());
+
+        let page = join(State(state.clone()), join_form("open", injection))
+            .await
+            .unwrap()
+            .0;
+        assert!(!page.contains(injection), "{page}");
+        assert!(page.contains(
+            r#"file: "ws://localhost:3333/app/open?password=%3C%2Fscript%3E%3Cscript%3Ealert%281%29%2F%2F""#
+        ), "{page}");
+
+        let config = state.config();
+        let page = player_page(&config, injection, injection, ("password", "a"), "").0;
+        assert!(!page.contains(injection), "{page}");
+    }
+
+    #[tokio::test]
+    async fn export_redacts_secrets() {
+        use tower::ServiceExt;
+
+        let secrets = [
+            "alice-key",
+            "admin-secret",
+            "metrics-secret",
+            "invite-secret",
+            "ome-token",
+            "vault-token",
+            "url-secret",
+        ];
+        let state = Arc::new(state_with(
+            "admin_token = \"admin-secret\"\n\
+             admin_tokens = [{ token = \"metrics-secret\", permissions = [\"read_config\"] }]\n\
+             invite_secret = \"invite-secret\"\n\
+             ome_auth_scheme = { type = \"bearer\", token = \"ome-token\" }\n\
+             vault = { address = \"http://vault\", token = \"vault-token\", secrets_path = \"ovenctrl\" }\n\
+             room_config.main.outgoing_url_secret = \"url-secret\"\n",
+        ));
+
+        let request = axum::http::Request::get("/api/export")
+            .header(header::AUTHORIZATION, "Bearer metrics-secret")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let rsp = admin::router()
+            .with_state(state.clone())
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(rsp.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(rsp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let export = String::from_utf8(body.to_vec()).unwrap();
+
+        for secret in secrets {
+            assert!(!export.contains(secret), "{secret} in {export}");
+        }
+        assert!(export.contains("hunter2"));
+
+        // The environment of the controller can't be read through an import
+        let import = serde_json::from_value(serde_json::json!({
+            "streamers": { "mallory": "env:HOME" }
+        }))
+        .unwrap();
+        assert!(state
+            .update_config(|config| admin::apply_import(config, Default::default(), import))
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn audit_db() {
+        use tower::ServiceExt;
+
+        let path = std::env::temp_dir().join(format!("ovenctrl-{}.db", uuid::Uuid::new_v4()));
+        let mut state = state_with(&format!(
+            "admin_token = \"admin-token\"\naudit_db_path = {:?}\n",
+            path.display().to_string()
+        ));
+        state.audit_db = Some(audit_db::AuditDb::open(&path).await.unwrap());
+        let state = Arc::new(state);
+
+        for url in [
+            "rtmp://ome/app/main?name=alice&key=wrong-key",
+            "rtmp://ome/app/main?name=alice&key=alice-key",
+            "rtmp://ome/app/other?name=bob&key=bob-key",
+        ] {
+            let _ = admission(
+                State(state.clone()),
+                None,
+                HeaderMap::new(),
+                Ok(Json(incoming(url))),
+            )
+            .await
+            .unwrap();
+        }
+
+        // The events are written in the background
+        let audit_db = state.audit_db.clone().unwrap();
+        for _ in 0..100 {
+            let page = audit_db.page(0, 10, &Default::default()).await.unwrap();
+            if page.events.len() == 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let events = |query: &'static str| {
+            let state = state.clone();
+            async move {
+                let request = axum::http::Request::get(format!("/api/events?{query}"))
+                    .header(header::AUTHORIZATION, "Bearer admin-token")
+                    .body(axum::body::Body::empty())
+                    .unwrap();
+                let rsp = admin::router()
+                    .with_state(state)
+                    .oneshot(request)
+                    .await
+                    .unwrap();
+                assert_eq!(rsp.status(), StatusCode::OK);
+                let body = axum::body::to_bytes(rsp.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap()["events"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|event| {
+                        (
+                            event["room"].as_str().unwrap().to_owned(),
+                            event["allowed"].as_bool().unwrap(),
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            }
+        };
+        let event = |room: &str, allowed| (room.to_owned(), allowed);
+
+        assert_eq!(events("").await.len(), 3);
+        assert_eq!(events("room=main").await.len(), 2);
+        assert_eq!(
+            events("room=main&outcome=denied").await,
+            [event("main", false)]
+        );
+        assert_eq!(
+            events("outcome=denied").await,
+            [event("main", false), event("other", false)]
+        );
+        assert_eq!(events("outcome=allowed").await, [event("main", true)]);
+        assert_eq!(events("since=2000-01-01T00:00:00Z").await.len(), 3);
+        assert!(events("since=2100-01-01T00:00:00Z").await.is_empty());
+        assert_eq!(events("after=1&limit=1").await, [event("main", true)]);
+
+        // The events outlive the process
+        let reopened = audit_db::AuditDb::open(&path).await.unwrap();
+        let page = reopened.page(0, 10, &Default::default()).await.unwrap();
+        assert_eq!(page.events.len(), 3);
+        assert_eq!(page.events[0].allowed, Some(false));
+        assert_eq!(page.events[1].streamer.as_deref(), Some("alice"));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn dashboard_forms() {
+        use tower::ServiceExt;
+
+        let state = Arc::new(state_with("admin_token = \"admin-token\"\n"));
+        let post = |path: &str, cookie: &str, form: String| {
+            axum::http::Request::post(path)
+                .header(header::COOKIE, cookie)
+                .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .body(axum::body::Body::from(form))
+                .unwrap()
+        };
+        let send = |request| {
+            dashboard::router()
+                .with_state(state.clone())
+                .oneshot(request)
+        };
+
+        let rsp = send(post("/admin/login", "", "token=admin-token".into()))
+            .await
+            .unwrap();
+        let cookie = rsp.headers()[header::SET_COOKIE].to_str().unwrap();
+        let cookie = cookie.split(';').next().unwrap().to_owned();
+
+        let page = send(
+            axum::http::Request::get("/admin")
+                .header(header::COOKIE, &cookie)
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+        let page = axum::body::to_bytes(page.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let page = String::from_utf8(page.to_vec()).unwrap();
+        let csrf = Regex::new(r#"name="csrf" value="([0-9a-f]+)""#)
+            .unwrap()
+            .captures(&page)
+            .unwrap()[1]
+            .to_owned();
+
+        // The forms need both the session and its CSRF token
+        let form = "name=carol&key=carol-key";
+        for (cookie, csrf, status) in [
+            ("", csrf.as_str(), StatusCode::UNAUTHORIZED),
+            (cookie.as_str(), "0000", StatusCode::FORBIDDEN),
+        ] {
+            let rsp = send(post(
+                "/admin/streamers",
+                cookie,
+                format!("csrf={csrf}&{form}"),
+            ))
+            .await
+            .unwrap();
+            assert_eq!(rsp.status(), status);
+        }
+        assert!(!state.config().streamers.contains_key("carol"));
+
+        for (path, form) in [
+            ("/admin/streamers", form),
+            ("/admin/streamers/remove", "name=alice"),
+            ("/admin/rooms", "room=lobby&password=lobby-password"),
+            ("/admin/rooms/remove", "name=main"),
+        ] {
+            let rsp = send(post(path, &cookie, format!("csrf={csrf}&{form}")))
+                .await
+                .unwrap();
+            assert_eq!(rsp.status(), StatusCode::SEE_OTHER, "{path}");
+        }
+        let config = state.config();
+        assert_eq!(config.streamers["carol"], "carol-key");
+        assert!(!config.streamers.contains_key("alice"));
+        assert!(!config.allowed_streams.contains_key("alice"));
+        assert!(config.rooms["lobby"].contains("lobby-password"));
+        assert!(!config.rooms.contains_key("main"));
+
+        // Existing entries are not replaced, and invalid rooms are refused
+        for (path, form) in [
+            ("/admin/streamers", "name=bob&key=new-key"),
+            ("/admin/rooms", "room=../lobby&password=password"),
+            ("/admin/rooms/remove", "name=main"),
+        ] {
+            let rsp = send(post(path, &cookie, format!("csrf={csrf}&{form}")))
+                .await
+                .unwrap();
+            assert_eq!(rsp.status(), StatusCode::BAD_REQUEST, "{path}");
+        }
+        assert_eq!(state.config().streamers["bob"], "bob-key");
+    }
+
+    #[test]
+    fn rate_limit() {
+        let state = state_with("rate_limit = { capacity = 1.0, refill_rate = 20.0 }");
+        let alice = incoming("rtmp://ome/app/main?name=alice&key=alice-key");
+
+        assert!(handle_opening_admission(&state, &alice).unwrap().allowed);
+        let err = handle_opening_admission(&state, &alice).unwrap_err();
+        assert_eq!(DenialReason::of(&err), DenialReason::RateLimited);
+
+        // The buckets are per streamer
+        let bob = incoming("rtmp://ome/app/bob?name=bob&key=bob-key");
+        assert_ne!(
+            handle_opening_admission(&state, &bob)
+                .err()
+                .map(|err| DenialReason::of(&err)),
+            Some(DenialReason::RateLimited)
+        );
+
+        // A token is regained every 50ms
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(handle_opening_admission(&state, &alice).unwrap().allowed);
+    }
+
+    #[test]
+    fn room_name_pattern() {
+        let long = "a".repeat(65);
+        for room in ["../main", "main/..", "main\0", &long] {
+            let mut payload = incoming("rtmp://ome/app/main?name=alice&key=alice-key");
+            payload.request.url.room = room.to_owned();
+            let err = handle_opening_admission(&state(), &payload).unwrap_err();
+            assert_eq!(
+                DenialReason::of(&err),
+                DenialReason::InvalidRoomName,
+                "{room:?}"
+            );
+        }
+
+        // The room is the raw segment of the URL
+        for url in [
+            "rtmp://ome/app/..%2Fmain?name=alice&key=alice-key",
+            "rtmp://ome/app/main%00?name=alice&key=alice-key",
+        ] {
+            assert_eq!(
+                denial(incoming(url)),
+                DenialReason::InvalidRoomName,
+                "{url}"
+            );
+        }
+        assert!(state().room_name_pattern.is_match(&long[1..]));
+    }
+
+    #[test]
+    fn bypass_ips() {
+        let state = state_with("bypass_ips = [\"192.168.0.10\"]");
+
+        // Nothing is validated for the listed addresses
+        let mut payload = incoming("rtmp://ome/app/unknown?name=mallory&key=wrong");
+        let rsp = handle_opening_admission(&state, &payload).unwrap();
+        assert!(rsp.allowed);
+        assert!(rsp.new_url.is_none());
+
+        payload.client.address = "192.168.0.11".into();
+        let err = handle_opening_admission(&state, &payload).unwrap_err();
+        assert_eq!(DenialReason::of(&err), DenialReason::UnknownStreamer);
+    }
+
+    #[test]
+    fn stream_key_pattern() {
+        let state = state_with("stream_key_pattern = \"^[a-z]+-key$\"");
+        let admission = |key: &str| {
+            handle_opening_admission(
+                &state,
+                &incoming(&format!("rtmp://ome/app/main?name=alice&key={key}")),
+            )
+        };
+
+        assert!(admission("alice-key").unwrap().allowed);
+
+        // Malformed keys are refused before being compared to the key of the streamer
+        let err = admission("ALICE-KEY").unwrap_err();
+        assert_eq!(DenialReason::of(&err), DenialReason::InvalidKey);
+        assert!(err.to_string().contains("malformed key"), "{err:#}");
+
+        let err = admission("carol-key").unwrap_err();
+        assert_eq!(DenialReason::of(&err), DenialReason::InvalidKey);
+        assert!(err.to_string().contains("invalid key"), "{err:#}");
+    }
+
+    #[test]
+    fn thumbnail_allowed_ips() {
+        let thumbnail = |address: &str, url: &str| {
+            let mut payload = payload("outgoing", "opening", url);
+            payload.request.protocol = OvenProtocol::Thumbnail;
+            payload.client.address = address.into();
+            payload
+        };
+        let state = state_with("thumbnail_allowed_ips = [\"10.0.0.0/8\"]");
+
+        let rsp = handle_opening_admission(&state, &thumbnail("10.1.2.3", "http://ome/app/main"));
+        assert!(rsp.unwrap().allowed);
+        let err = handle_opening_admission(
+            &state,
+            &thumbnail("192.168.0.10", "http://ome/app/main?password=hunter2"),
+        )
+        .unwrap_err();
+        assert_eq!(DenialReason::of(&err), DenialReason::ClientNotAllowed);
+
+        // Without an allowlist, the thumbnails need the password of the room
+        let state = state_with("");
+        let rsp = handle_opening_admission(
+            &state,
+            &thumbnail("192.168.0.10", "http://ome/app/main?password=hunter2"),
+        );
+        assert!(rsp.unwrap().allowed);
+        let err = handle_opening_admission(
+            &state,
+            &thumbnail("192.168.0.10", "http://ome/app/main?password=wrong"),
+        )
+        .unwrap_err();
+        assert_eq!(DenialReason::of(&err), DenialReason::InvalidPassword);
+    }
+
+    /// Source counting the times it is read, failing with an I/O error the first `failures` times
+    #[derive(Debug, Clone)]
+    struct FlakySource {
+        reads: Arc<std::sync::atomic::AtomicU32>,
+        failures: u32,
+    }
+
+    impl config::Source for FlakySource {
+        fn clone_into_box(&self) -> Box<dyn config::Source + Send + Sync> {
+            Box::new(self.clone())
+        }
+
+        fn collect(&self) -> Result<config::Map<String, config::Value>, config::ConfigError> {
+            let reads = self
+                .reads
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            if reads < self.failures {
+                let err = std::io::Error::new(std::io::ErrorKind::NotFound, "not mounted yet");
+                return Err(config::ConfigError::Foreign(Box::new(err)));
+            }
+
+            Ok([("external_host".to_owned(), "ome.example.com".into())].into())
+        }
+    }
+
+    #[tokio::test]
+    async fn config_retries() {
+        let delay = Duration::from_millis(1);
+        let flaky = |failures| FlakySource {
+            reads: Default::default(),
+            failures,
+        };
+        let builder = |source: &FlakySource| config::Config::builder().add_source(source.clone());
+
+        let source = flaky(2);
+        let config = config_help::build_with_retries(builder(&source), 2, delay)
+            .await
+            .unwrap();
+        assert_eq!(
+            config.get_string("external_host").unwrap(),
+            "ome.example.com"
+        );
+        assert_eq!(source.reads.load(std::sync::atomic::Ordering::Relaxed), 3);
+
+        let source = flaky(3);
+        let err = config_help::build_with_retries(builder(&source), 2, delay)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("not mounted yet"), "{err:#}");
+        assert_eq!(source.reads.load(std::sync::atomic::Ordering::Relaxed), 3);
+
+        // Invalid files are not retried
+        let source = flaky(0);
+        let invalid = builder(&source).add_source(config::File::from_str(
+            "external_host = [",
+            config::FileFormat::Toml,
+        ));
+        assert!(config_help::build_with_retries(invalid, 2, delay)
+            .await
+            .is_err());
+        assert_eq!(source.reads.load(std::sync::atomic::Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,222 @@
+use std::{convert::Infallible, fmt::Write, sync::OnceLock};
+
+use axum::{
+    extract::Query,
+    http::StatusCode,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use futures_util::Stream;
+use time::OffsetDateTime;
+use tokio::sync::broadcast;
+use tracing::{
+    field::{Field, Visit},
+    span, Level, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+use crate::admin::{scope, RequiredPermission};
+
+/// Lines kept for the slow clients, older lines are skipped once they fall behind
+const LOG_TAIL_CAPACITY: usize = 1024;
+
+static LOG_TAIL: OnceLock<broadcast::Sender<LogLine>> = OnceLock::new();
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct LogLine {
+    #[serde(with = "time::serde::rfc3339")]
+    timestamp: OffsetDateTime,
+    #[serde(serialize_with = "serialize_level")]
+    level: Level,
+    target: &'static str,
+    message: String,
+    #[serde(skip)]
+    room: Option<String>,
+    #[serde(skip)]
+    streamer: Option<String>,
+}
+
+fn serialize_level<S: serde::Serializer>(level: &Level, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(level.as_str())
+}
+
+/// `room` and `streamer` fields of a record or of one of its spans
+#[derive(Default)]
+struct Scope {
+    room: Option<String>,
+    streamer: Option<String>,
+}
+
+impl Scope {
+    fn record(&mut self, field: &Field, value: String) {
+        match field.name() {
+            "room" => self.room = Some(value),
+            "streamer" => self.streamer = Some(value),
+            _ => (),
+        }
+    }
+}
+
+impl Visit for Scope {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record(field, value.to_owned());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.record(field, format!("{value:?}"));
+    }
+}
+
+/// Formats the fields of an event like the `fmt` subscriber
+struct LineVisitor {
+    message: String,
+    fields: String,
+    scope: Scope,
+}
+
+impl Visit for LineVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record_debug(field, &value)
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{value:?}");
+            return;
+        }
+
+        let formatted = format!("{value:?}");
+        self.scope
+            .record(field, formatted.trim_matches('"').to_owned());
+        let _ = write!(self.fields, " {}={formatted}", field.name());
+    }
+}
+
+/// Layer forwarding the log records to the clients of `GET /api/logs/stream`
+pub struct LogTailLayer {
+    sender: broadcast::Sender<LogLine>,
+}
+
+/// Layer to install in the `tracing` subscriber to enable `GET /api/logs/stream`
+pub fn layer() -> LogTailLayer {
+    LogTailLayer {
+        sender: LOG_TAIL
+            .get_or_init(|| broadcast::channel(LOG_TAIL_CAPACITY).0)
+            .clone(),
+    }
+}
+
+impl<S> Layer<S> for LogTailLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+
+        let mut scope = Scope::default();
+        attrs.record(&mut scope);
+        span.extensions_mut().insert(scope);
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+
+        let mut extensions = span.extensions_mut();
+        if let Some(scope) = extensions.get_mut::<Scope>() {
+            values.record(scope);
+        }
+    }
+
+    fn on_event(&self, event: &tracing::Event<'_>, ctx: Context<'_, S>) {
+        if self.sender.receiver_count() == 0 {
+            return;
+        }
+
+        let mut visitor = LineVisitor {
+            message: String::new(),
+            fields: String::new(),
+            scope: Scope::default(),
+        };
+        event.record(&mut visitor);
+
+        // The innermost spans take precedence
+        for span in ctx.event_scope(event).into_iter().flatten() {
+            let extensions = span.extensions();
+            let Some(scope) = extensions.get::<Scope>() else {
+                continue;
+            };
+            if visitor.scope.room.is_none() {
+                visitor.scope.room.clone_from(&scope.room);
+            }
+            if visitor.scope.streamer.is_none() {
+                visitor.scope.streamer.clone_from(&scope.streamer);
+            }
+        }
+
+        let metadata = event.metadata();
+        let _ = self.sender.send(LogLine {
+            timestamp: OffsetDateTime::now_utc(),
+            level: *metadata.level(),
+            target: metadata.target(),
+            message: visitor.message + &visitor.fields,
+            room: visitor.scope.room,
+            streamer: visitor.scope.streamer,
+        });
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub(crate) struct LogStreamQuery {
+    /// Most verbose level sent
+    level: Option<String>,
+    room: Option<String>,
+    streamer: Option<String>,
+}
+
+/// Stream the log lines as they are emitted, as server-sent events
+#[tracing::instrument(skip(_permission))]
+pub(crate) async fn stream(
+    _permission: RequiredPermission<scope::ReadLogs>,
+    Query(query): Query<LogStreamQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, (StatusCode, String)> {
+    let Some(sender) = LOG_TAIL.get() else {
+        return Err((StatusCode::NOT_FOUND, "log streaming is not enabled".into()));
+    };
+
+    let level = match &query.level {
+        None => Level::INFO,
+        Some(level) => level
+            .parse::<Level>()
+            .map_err(|err| (StatusCode::BAD_REQUEST, format!("invalid level: {err}")))?,
+    };
+
+    let lines = futures_util::stream::unfold(
+        (sender.subscribe(), query),
+        move |(mut receiver, query)| async move {
+            loop {
+                let line = match receiver.recv().await {
+                    Ok(line) => line,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                };
+
+                if line.level > level
+                    || (query.room.is_some() && line.room != query.room)
+                    || (query.streamer.is_some() && line.streamer != query.streamer)
+                {
+                    continue;
+                }
+
+                let event = Event::default()
+                    .json_data(&line)
+                    .unwrap_or_else(|_| Event::default().data(line.message));
+                return Some((Ok(event), (receiver, query)));
+            }
+        },
+    );
+
+    Ok(Sse::new(lines).keep_alive(KeepAlive::default()))
+}
@@ -1,356 +1,251 @@
-use std::{
-    collections::{HashMap, HashSet},
-    sync::Arc,
-};
+use std::{path::PathBuf, time::Duration};
 
 use anyhow::Context;
-use axum::{
-    extract::State,
-    http::{HeaderMap, HeaderValue, StatusCode},
-    response::{Html, Redirect},
-    routing::{get, post},
-    Form, Json, Router,
+use config::{Source, Value, ValueKind};
+use oven_ctrl::{
+    config_help::{self, ConfigField, ENV_PREFIX},
+    room_import::ImportTarget,
+    OvenCtrlConfig,
 };
-use time::OffsetDateTime;
-use tower_http::trace::TraceLayer;
 use tracing::Level;
-use tracing_subscriber::EnvFilter;
-use url::Url;
-
-#[derive(serde::Deserialize, Debug)]
-pub struct OvenClient {
-    pub address: String,
-    pub port: u16,
-    pub user_agent: String,
-}
-
-#[derive(serde::Deserialize, Debug)]
-#[serde(rename_all = "lowercase")]
-pub enum OvenDirection {
-    Incoming,
-    Outgoing,
-}
-
-#[derive(serde::Deserialize, Debug)]
-pub enum OvenProtocol {
-    WebRTC,
-    RTMP,
-    SRT,
-    LLHLS,
-    Thumbnail,
-}
-
-#[derive(serde::Deserialize, Debug)]
-#[serde(rename_all = "lowercase")]
-pub enum OvenStatus {
-    Closing,
-    Opening,
-}
-
-#[derive(serde::Deserialize, Debug)]
-pub struct OvenRequest {
-    pub direction: OvenDirection,
-    pub protocol: OvenProtocol,
-    pub status: OvenStatus,
-    pub url: Url,
-    pub new_url: Option<Url>,
-    #[serde(deserialize_with = "time::serde::iso8601::deserialize")]
-    pub time: OffsetDateTime,
-}
-
-#[derive(serde::Deserialize, Debug)]
-pub struct OvenAdmission {
-    pub client: OvenClient,
-    pub request: OvenRequest,
-}
-
-#[derive(serde::Serialize, Debug)]
-pub struct OvenClosingResponse {}
-
-#[derive(serde::Serialize, Debug)]
-pub struct OvenOpeningResponse {
-    pub allowed: bool,
-    pub new_url: Option<Url>,
-    pub lifetime: Option<u64>,
-    pub reason: Option<String>,
-}
-
-#[derive(serde::Serialize, Debug)]
-#[serde(untagged)]
-pub enum OvenResponse {
-    Closing(OvenClosingResponse),
-    Opening(OvenOpeningResponse),
-}
-
-impl From<OvenOpeningResponse> for Json<OvenResponse> {
-    fn from(value: OvenOpeningResponse) -> Self {
-        Json(OvenResponse::Opening(value))
-    }
-}
+use tracing_subscriber::{
+    fmt::writer::BoxMakeWriter, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter,
+};
 
-impl From<OvenClosingResponse> for Json<OvenResponse> {
-    fn from(value: OvenClosingResponse) -> Self {
-        Json(OvenResponse::Closing(value))
-    }
-}
+struct Args {
+    /// Configuration files, later files overriding the earlier ones
+    config_files: Vec<String>,
+    list_sources: bool,
+    help_config: bool,
+    /// Fail to start when the OME API is configured but can't be reached
+    require_ome_api: bool,
+    /// Admission requests to process instead of starting the server
+    replay: Option<PathBuf>,
+    /// Retries of the loading of the configuration files when they can't be read
+    config_retry_attempts: u32,
+    /// Delay before the first retry, doubled after each attempt
+    config_retry_delay: Duration,
+}
+
+fn parse_args() -> anyhow::Result<Args> {
+    let mut args = Args {
+        config_files: Vec::new(),
+        list_sources: false,
+        help_config: false,
+        require_ome_api: false,
+        replay: None,
+        config_retry_attempts: 0,
+        config_retry_delay: Duration::from_millis(1000),
+    };
 
-fn handle_opening_admission(
-    state: &OvenCtrlConfig,
-    payload: OvenAdmission,
-) -> anyhow::Result<OvenOpeningResponse> {
-    match payload.request.direction {
-        OvenDirection::Incoming => {
-            #[derive(serde::Deserialize)]
-            struct IngestQuery {
-                name: String,
-                key: String,
+    let mut argv = std::env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "--config" => {
+                let path = argv.next().context("Missing path after --config")?;
+                args.config_files.push(path);
             }
-
-            let query = payload
-                .request
-                .url
-                .query()
-                .context("no query parameters present")?;
-
-            let query = serde_urlencoded::from_str::<IngestQuery>(query)?;
-            let expected_key = state
-                .streamers
-                .get(&query.name)
-                .with_context(|| format!("unknown streamer: {}", query.name))?;
-
-            if expected_key != &query.key {
-                anyhow::bail!("invalid key for streamer {}", query.name)
+            "--list-sources" => args.list_sources = true,
+            "--help-config" => args.help_config = true,
+            "--require-ome-api" => args.require_ome_api = true,
+            "--replay-mode" => {
+                let path = argv.next().context("Missing path after --replay-mode")?;
+                args.replay = Some(path.into());
             }
-
-            let room = payload
-                .request
-                .url
-                .path_segments()
-                .with_context(|| format!("url '{:?}' has no segments", payload.request.url))?
-                .nth(1)
-                .with_context(|| {
-                    format!("url '{:?}' is laking a second segment", payload.request.url)
-                })?;
-
-            let allowed_streams = state.allowed_streams.get(&query.name).with_context(|| {
-                format!(
-                    "streamer '{}' does not have access to any rooms",
-                    query.name
-                )
-            })?;
-
-            if !allowed_streams.contains(room) {
-                anyhow::bail!(
-                    "streamer {} does not have access to room {room}",
-                    query.name
-                )
+            "--config-retry-attempts" => {
+                args.config_retry_attempts = argv
+                    .next()
+                    .context("Missing count after --config-retry-attempts")?
+                    .parse()
+                    .context("Invalid --config-retry-attempts")?;
             }
-        }
-        OvenDirection::Outgoing => {
-            #[derive(serde::Deserialize)]
-            struct ViewerQuery {
-                password: String,
+            "--config-retry-delay-ms" => {
+                args.config_retry_delay = Duration::from_millis(
+                    argv.next()
+                        .context("Missing delay after --config-retry-delay-ms")?
+                        .parse()
+                        .context("Invalid --config-retry-delay-ms")?,
+                );
             }
+            flag if flag.starts_with("--") => anyhow::bail!("Unknown flag {flag}"),
+            path => args.config_files.push(path.to_owned()),
+        }
+    }
 
-            let room = payload
-                .request
-                .url
-                .path_segments()
-                .with_context(|| format!("url '{:?}' has no segments", payload.request.url))?
-                .nth(1)
-                .with_context(|| {
-                    format!("url '{:?}' is laking a second segment", payload.request.url)
-                })?;
-
-            let query = payload
-                .request
-                .url
-                .query()
-                .context("no query parameters present")?;
-
-            let query = serde_urlencoded::from_str::<ViewerQuery>(query)?;
-            let expected_password = state
-                .rooms
-                .get(room)
-                .with_context(|| format!("room does not exist: {room}"))?;
+    if args.config_files.is_empty() && !args.help_config {
+        anyhow::bail!("Missing configuration");
+    }
 
-            if expected_password != &query.password {
-                anyhow::bail!("Password mismatch for room: {room}");
-            }
+    Ok(args)
+}
+
+/// Arguments of `oven-ctrl import-rooms <file.csv> (--output <file.toml> | --server <url> --token <token>)`
+fn parse_import_rooms(
+    mut argv: impl Iterator<Item = String>,
+) -> anyhow::Result<(PathBuf, ImportTarget)> {
+    let mut csv = None;
+    let mut output = None;
+    let mut server = None;
+    let mut token = None;
+
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "--output" => output = Some(argv.next().context("Missing path after --output")?),
+            "--server" => server = Some(argv.next().context("Missing URL after --server")?),
+            "--token" => token = Some(argv.next().context("Missing token after --token")?),
+            flag if flag.starts_with("--") => anyhow::bail!("Unknown flag {flag}"),
+            path => csv = Some(PathBuf::from(path)),
         }
     }
 
-    Ok(OvenOpeningResponse {
-        allowed: true,
-        lifetime: None,
-        new_url: None,
-        reason: None,
-    })
-}
+    let csv = csv.context("Missing CSV file to import")?;
+    let target = match (output, server, token) {
+        (Some(output), None, None) => ImportTarget::File(output.into()),
+        (None, Some(server), Some(token)) => ImportTarget::Server {
+            url: server.parse().context("Invalid --server")?,
+            token,
+        },
+        (None, Some(_), None) => anyhow::bail!("Missing --token for --server"),
+        _ => anyhow::bail!("Expected either --output or --server"),
+    };
 
-#[tracing::instrument(skip(state))]
-async fn admission(
-    state: State<Arc<OvenCtrlConfig>>,
-    payload: Json<OvenAdmission>,
-) -> Json<OvenResponse> {
-    tracing::trace!("Received admission request");
+    Ok((csv, target))
+}
 
-    match payload.request.status {
-        OvenStatus::Closing => OvenClosingResponse {}.into(),
-        OvenStatus::Opening => match handle_opening_admission(&state, payload.0) {
-            Err(err) => OvenOpeningResponse {
-                allowed: false,
-                new_url: None,
-                lifetime: None,
-                reason: Some(err.to_string()),
-            },
-            Ok(rsp) => rsp,
+fn print_values(prefix: &str, value: &Value) {
+    match &value.kind {
+        ValueKind::Table(table) => {
+            let mut keys = table.keys().collect::<Vec<_>>();
+            keys.sort();
+            for key in keys {
+                let prefix = match prefix {
+                    "" => key.clone(),
+                    prefix => format!("{prefix}.{key}"),
+                };
+                print_values(&prefix, &table[key]);
+            }
+        }
+        ValueKind::Array(values) => {
+            for (i, value) in values.iter().enumerate() {
+                print_values(&format!("{prefix}[{i}]"), value);
+            }
         }
-        .into(),
+        _ => println!("  {prefix} = {value}"),
     }
 }
 
-#[derive(serde::Deserialize, Debug)]
-struct JoinForm {
-    room: String,
-    password: String,
-}
-
-#[tracing::instrument(skip(state))]
-async fn join(
-    state: State<Arc<OvenCtrlConfig>>,
-    form: Form<JoinForm>,
-) -> Result<Html<String>, Redirect> {
-    let Some(room_password) = state.rooms.get(&form.room) else {
-        tracing::warn!("Invalid room");
-        return Err(Redirect::to("/not_found.html"));
-    };
-
-    if room_password != &form.password {
-        tracing::warn!("Invalid password");
-        return Err(Redirect::to("/not_found.html"));
+/// Print the fields of the tables of `field`, which can only be set in the configuration files
+fn print_nested_fields(field: &ConfigField, depth: usize) {
+    let indent = "    ".repeat(depth);
+    for nested in &field.fields {
+        println!(
+            "{indent}- {} ({}, default: {})",
+            nested.name, nested.ty, nested.default
+        );
+        println!("{indent}      {}", nested.description);
+        print_nested_fields(nested, depth + 1);
     }
-
-    Ok(Html(format!(
-        r#"
-<!DOCTYPE html>
-<html>
-    <head>
-        <meta charset="utf-8" />
-        <meta name="viewport" content="width=device-width" />
-        <title>Room: {room}</title>
-    </head>
-    <body>
-        <div id="player_id"></div>
-        <script src="dist/ovenplayer.js"></script>
-        <script>
-            const player = OvenPlayer.create('player_id', {{
-                sources: [
-                    {{
-                        label: "label_for_webrtc",
-                        type: "webrtc",
-                        file: "ws{tls}://{host}/app/{room}?password={password}"
-                    }}
-                ]
-            }})
-        </script>
-    </body>
-</html>
-        "#,
-        host = &state.external_host,
-        room = &form.room,
-        password = &form.password,
-        tls = if state.external_tls { "s" } else { "" },
-    )))
 }
 
-fn default_port() -> u16 {
-    3000
-}
+/// Print the environment variables corresponding to each field of the configuration
+fn help_config() {
+    println!("Each field of the configuration can be set by an environment variable:");
+    for field in OvenCtrlConfig::config_doc() {
+        println!();
+        println!(
+            "{ENV_PREFIX}_{} ({}, default: {})",
+            field.name.to_uppercase(),
+            field.ty,
+            field.default
+        );
+        println!("    {}", field.description);
+        print_nested_fields(&field, 1);
+    }
+    println!();
+    println!(
+        "Entries of single word tables are set with {ENV_PREFIX}_<FIELD>_<KEY> \
+         (e.g. {ENV_PREFIX}_STREAMERS_alice),"
+    );
+    println!("other tables and arrays can only be set in the configuration files.");
+}
+
+/// Print the values defined by each source, from the lowest to the highest precedence, then the
+/// effective values
+fn list_sources(
+    sources: Vec<Box<dyn Source + Send + Sync>>,
+    names: &[String],
+) -> anyhow::Result<()> {
+    for (source, name) in sources.iter().zip(names) {
+        println!("{name}:");
+        print_values("", &Value::new(None, ValueKind::Table(source.collect()?)));
+    }
 
-#[derive(serde::Deserialize, Debug)]
-struct OvenCtrlConfig {
-    #[serde(default = "default_port")]
-    port: u16,
-    external_host: String,
-    #[serde(default)]
-    external_tls: bool,
-    /// Streamer name to token
-    #[serde(default)]
-    streamers: HashMap<String, String>,
-    /// Stream room to room password
-    #[serde(default)]
-    rooms: HashMap<String, String>,
-    /// Streamer name to allowed streams
-    #[serde(default)]
-    allowed_streams: HashMap<String, HashSet<String>>,
-}
+    let effective = config::Config::builder().add_source(sources).build()?;
+    println!("effective configuration:");
+    print_values(
+        "",
+        &Value::new(None, ValueKind::Table(effective.collect()?)),
+    );
 
-fn css_header() -> HeaderMap {
-    let mut css_header = HeaderMap::new();
-    css_header.insert("Content-Type", HeaderValue::from_static("text/css"));
-    css_header
+    Ok(())
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(
+    if std::env::args().nth(1).as_deref() == Some("import-rooms") {
+        let (csv, target) = parse_import_rooms(std::env::args().skip(2))?;
+        return oven_ctrl::room_import::run(&csv, target).await;
+    }
+
+    let args = parse_args()?;
+
+    // The decisions of the replay are printed to stdout
+    let writer = match args.replay {
+        Some(_) => BoxMakeWriter::new(std::io::stderr),
+        None => BoxMakeWriter::new(std::io::stdout),
+    };
+    tracing_subscriber::registry()
+        .with(
             EnvFilter::builder()
                 .with_default_directive(Level::INFO.into())
                 .from_env_lossy(),
         )
+        .with(tracing_subscriber::fmt::layer().with_writer(writer))
+        .with(oven_ctrl::log_tail::layer())
         .init();
 
-    let settings = config::Config::builder()
-        .add_source(config::File::with_name(
-            &std::env::args().nth(1).context("Missing configuration")?,
-        ))
-        .add_source(config::Environment::with_prefix("OVEN_CTRL").separator("_"))
-        .build()?
-        .try_deserialize::<OvenCtrlConfig>()?;
-
-    let port = settings.port;
-
-    let app = Router::new()
-        .route("/oven/admission", post(admission))
-        .route("/join", post(join))
-        .route("/", get(|| async { Html(include_str!("login.html")) }))
-        .route(
-            "/not_found.html",
-            get(|| async { (StatusCode::NOT_FOUND, Html(include_str!("not_found.html"))) }),
-        )
-        .route(
-            "/dist/normalize.css",
-            get(|| async { (css_header(), include_str!("dist/normalize.css")) }),
-        )
-        .route(
-            "/dist/milligram.min.css",
-            get(|| async { (css_header(), include_str!("dist/milligram.min.css")) }),
-        )
-        .route(
-            "/dist/milligram.min.css.map",
-            get(|| async { include_str!("dist/milligram.min.css.map") }),
-        )
-        .route(
-            "/dist/ovenplayer.js",
-            get(|| async {
-                let mut js_header = HeaderMap::new();
-                js_header.insert("Content-Type", HeaderValue::from_static("text/javascript"));
-
-                (js_header, include_str!("dist/ovenplayer.js"))
-            }),
-        )
-        .route(
-            "/dist/ovenplayer.js.map",
-            get(|| async { include_str!("dist/ovenplayer.js.map") }),
-        )
-        .with_state(Arc::new(settings))
-        .layer(TraceLayer::new_for_http());
+    if args.help_config {
+        help_config();
+        return Ok(());
+    }
 
-    let listener = tokio::net::TcpListener::bind(("0.0.0.0", port)).await?;
+    let mut sources: Vec<Box<dyn Source + Send + Sync>> = Vec::new();
+    let mut names = Vec::new();
+    for path in &args.config_files {
+        sources.push(Box::new(config::File::with_name(path)));
+        names.push(format!("file {path}"));
+    }
+    // The environment takes precedence over every file
+    for (source, name) in config_help::environment_sources(None) {
+        sources.push(Box::new(source));
+        names.push(name);
+    }
 
-    tracing::info!("Starting oven-ctrl");
+    if args.list_sources {
+        return list_sources(sources, &names);
+    }
 
-    axum::serve(listener, app).await.map_err(Into::into)
+    let sources = config_help::build_with_retries(
+        config::Config::builder().add_source(sources),
+        args.config_retry_attempts,
+        args.config_retry_delay,
+    )
+    .await?;
+    let mut settings = oven_ctrl::vault::load(sources).await?;
+    settings.resolve_env_keys()?;
+
+    match &args.replay {
+        Some(path) => oven_ctrl::replay::run(settings, path).await,
+        None => oven_ctrl::run(settings, args.require_ome_api).await,
+    }
 }
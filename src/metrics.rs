@@ -0,0 +1,133 @@
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+
+use crate::{DenialReason, OvenProtocol};
+
+/// Counters of the admission decisions taken since startup
+#[derive(Debug)]
+pub struct AdmissionMetrics {
+    started: Instant,
+    total_requests: AtomicU64,
+    allowed: AtomicU64,
+    denied: AtomicU64,
+    by_reason: DashMap<DenialReason, AtomicU64>,
+    by_protocol: DashMap<OvenProtocol, AtomicU64>,
+    /// Requests by room category, rooms are not used directly to keep the number of keys bounded
+    by_category: DashMap<String, AtomicU64>,
+    /// Closing requests of connections that were not open, e.g. sent twice by OME
+    spurious_closings: AtomicU64,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+pub struct AdmissionMetricsSnapshot {
+    pub total_requests: u64,
+    pub allowed: u64,
+    pub denied: u64,
+    pub by_reason: HashMap<DenialReason, u64>,
+    pub by_protocol: HashMap<OvenProtocol, u64>,
+    pub by_category: HashMap<String, u64>,
+    #[serde(default)]
+    pub spurious_closings: u64,
+    pub uptime_secs: u64,
+}
+
+impl Default for AdmissionMetrics {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            total_requests: Default::default(),
+            allowed: Default::default(),
+            denied: Default::default(),
+            by_reason: Default::default(),
+            by_protocol: Default::default(),
+            by_category: Default::default(),
+            spurious_closings: Default::default(),
+        }
+    }
+}
+
+fn increment<K: Eq + std::hash::Hash>(map: &DashMap<K, AtomicU64>, key: K) {
+    map.entry(key).or_default().fetch_add(1, Ordering::Relaxed);
+}
+
+fn restore_map<K: Eq + std::hash::Hash>(map: &DashMap<K, AtomicU64>, snapshot: HashMap<K, u64>) {
+    map.clear();
+    for (key, count) in snapshot {
+        map.insert(key, AtomicU64::new(count));
+    }
+}
+
+fn snapshot_map<K: Eq + std::hash::Hash + Clone>(map: &DashMap<K, AtomicU64>) -> HashMap<K, u64> {
+    map.iter()
+        .map(|entry| (entry.key().clone(), entry.value().load(Ordering::Relaxed)))
+        .collect()
+}
+
+impl AdmissionMetrics {
+    /// Record an admission request, with the decision if it was an opening request
+    pub fn record(
+        &self,
+        protocol: OvenProtocol,
+        category: &str,
+        decision: Option<Result<(), DenialReason>>,
+    ) {
+        self.total_requests.fetch_add(1, Ordering::Relaxed);
+        increment(&self.by_protocol, protocol);
+        match self.by_category.get(category) {
+            Some(count) => {
+                count.fetch_add(1, Ordering::Relaxed);
+            }
+            None => increment(&self.by_category, category.to_owned()),
+        }
+
+        match decision {
+            None => (),
+            Some(Ok(())) => {
+                self.allowed.fetch_add(1, Ordering::Relaxed);
+            }
+            Some(Err(reason)) => {
+                self.denied.fetch_add(1, Ordering::Relaxed);
+                increment(&self.by_reason, reason);
+            }
+        }
+    }
+
+    pub fn record_spurious_closing(&self) {
+        self.spurious_closings.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn snapshot(&self) -> AdmissionMetricsSnapshot {
+        AdmissionMetricsSnapshot {
+            total_requests: self.total_requests.load(Ordering::Relaxed),
+            allowed: self.allowed.load(Ordering::Relaxed),
+            denied: self.denied.load(Ordering::Relaxed),
+            by_reason: snapshot_map(&self.by_reason),
+            by_protocol: snapshot_map(&self.by_protocol),
+            by_category: snapshot_map(&self.by_category),
+            spurious_closings: self.spurious_closings.load(Ordering::Relaxed),
+            uptime_secs: self.uptime().as_secs(),
+        }
+    }
+
+    /// Restore the counters of a previous [AdmissionMetrics::snapshot], the uptime is not restored
+    pub fn restore(&self, snapshot: AdmissionMetricsSnapshot) {
+        self.total_requests
+            .store(snapshot.total_requests, Ordering::Relaxed);
+        self.allowed.store(snapshot.allowed, Ordering::Relaxed);
+        self.denied.store(snapshot.denied, Ordering::Relaxed);
+        restore_map(&self.by_reason, snapshot.by_reason);
+        restore_map(&self.by_protocol, snapshot.by_protocol);
+        restore_map(&self.by_category, snapshot.by_category);
+        self.spurious_closings
+            .store(snapshot.spurious_closings, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,397 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use axum::http::{header, HeaderName, HeaderValue};
+use base64::Engine;
+use url::Url;
+
+use crate::{circuit_breaker::CircuitBreaker, OvenCtrlConfig};
+
+/// Client for the OME Manager REST API
+#[derive(Debug)]
+pub struct OmeApi {
+    client: reqwest::Client,
+    base_url: Url,
+    /// Header authenticating the requests
+    authorization: (HeaderName, HeaderValue),
+    vhost: String,
+    /// Fails the requests fast while OME is unreachable
+    breaker: CircuitBreaker,
+}
+
+/// Authentication of the requests to the OME Manager API, for setups where it sits behind a proxy
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OmeAuthScheme {
+    Bearer { token: String },
+    Basic { username: String, password: String },
+    Header { name: String, value: String },
+}
+
+impl OmeAuthScheme {
+    fn header(&self) -> anyhow::Result<(HeaderName, HeaderValue)> {
+        let base64 = &base64::engine::general_purpose::STANDARD;
+
+        Ok(match self {
+            OmeAuthScheme::Bearer { token } => (
+                header::AUTHORIZATION,
+                HeaderValue::try_from(format!("Bearer {token}")).context("invalid OME token")?,
+            ),
+            OmeAuthScheme::Basic { username, password } => (
+                header::AUTHORIZATION,
+                HeaderValue::try_from(format!(
+                    "Basic {}",
+                    base64.encode(format!("{username}:{password}"))
+                ))
+                .context("invalid OME credentials")?,
+            ),
+            OmeAuthScheme::Header { name, value } => (
+                HeaderName::try_from(name)
+                    .with_context(|| format!("invalid OME authentication header {name}"))?,
+                HeaderValue::try_from(value).with_context(|| {
+                    format!("invalid value for OME authentication header {name}")
+                })?,
+            ),
+        })
+    }
+}
+
+#[derive(serde::Serialize)]
+struct ActionRequest<'a> {
+    id: &'a str,
+}
+
+#[derive(serde::Serialize)]
+struct PushStream<'a> {
+    name: &'a str,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PushRequest<'a> {
+    id: &'a str,
+    stream: PushStream<'a>,
+    protocol: &'static str,
+    url: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_key: Option<&'a str>,
+}
+
+/// Destination of a push relay, in the form expected by OME
+#[derive(Debug, PartialEq, Eq)]
+pub struct PushTarget {
+    pub protocol: &'static str,
+    pub url: Url,
+    /// Last segment of the RTMP URLs, which OME expects separately
+    pub stream_key: Option<String>,
+}
+
+impl PushTarget {
+    pub fn from_url(target: &Url) -> anyhow::Result<Self> {
+        match target.scheme() {
+            "rtmp" | "rtmps" => {
+                let mut url = target.clone();
+                let stream_key = url
+                    .path_segments()
+                    .and_then(|mut segments| segments.next_back())
+                    .filter(|key| !key.is_empty())
+                    .map(str::to_owned)
+                    .with_context(|| format!("push relay target {target} has no stream key"))?;
+                url.path_segments_mut()
+                    .map_err(|_| anyhow::anyhow!("invalid push relay target {target}"))?
+                    .pop();
+
+                Ok(Self {
+                    protocol: "rtmp",
+                    url,
+                    stream_key: Some(stream_key),
+                })
+            }
+            "srt" => Ok(Self {
+                protocol: "srt",
+                url: target.clone(),
+                stream_key: None,
+            }),
+            "udp" | "tcp" => Ok(Self {
+                protocol: "mpegts",
+                url: target.clone(),
+                stream_key: None,
+            }),
+            scheme => anyhow::bail!("unsupported protocol {scheme} for push relay target {target}"),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct OmeResponse<T> {
+    response: T,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OmeStreamStats {
+    /// Ingest bitrate, in bits per second
+    last_throughput_in: u64,
+    total_connections: u64,
+}
+
+#[derive(serde::Deserialize)]
+struct OmeStreamInfo {
+    input: OmeStreamInput,
+}
+
+#[derive(serde::Deserialize)]
+struct OmeStreamInput {
+    #[serde(default)]
+    tracks: Vec<OmeTrack>,
+}
+
+#[derive(serde::Deserialize)]
+struct OmeTrack {
+    video: Option<OmeVideoTrack>,
+}
+
+#[derive(serde::Deserialize)]
+struct OmeVideoTrack {
+    #[serde(default)]
+    framerate: f64,
+}
+
+/// Statistics of a live stream
+#[derive(Debug, Clone)]
+pub struct StreamStats {
+    pub bitrate_bps: u64,
+    pub fps: f64,
+    pub connections: u64,
+}
+
+impl OmeApi {
+    pub fn from_config(
+        config: &OvenCtrlConfig,
+        client: reqwest::Client,
+    ) -> anyhow::Result<Option<Self>> {
+        let Some(base_url) = &config.ome_api_url else {
+            return Ok(None);
+        };
+
+        let authorization = match (&config.ome_auth_scheme, &config.ome_api_access_token) {
+            (Some(scheme), _) => scheme.header()?,
+            // OME expects the base64 encoded access token as basic credentials
+            (None, Some(access_token)) => (
+                header::AUTHORIZATION,
+                HeaderValue::try_from(format!(
+                    "Basic {}",
+                    base64::engine::general_purpose::STANDARD.encode(access_token)
+                ))
+                .context("invalid OME access token")?,
+            ),
+            (None, None) => return Ok(None),
+        };
+
+        Ok(Some(Self {
+            client,
+            base_url: base_url.clone(),
+            authorization,
+            vhost: config.ome_vhost.clone(),
+            breaker: CircuitBreaker::new(
+                "OME API",
+                config.ome_api_failure_threshold,
+                Duration::from_secs(config.ome_api_circuit_open_secs),
+            ),
+        }))
+    }
+
+    fn endpoint(&self, segments: &[&str]) -> anyhow::Result<Url> {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("OME API url can't be a base: {}", self.base_url))?
+            .pop_if_empty()
+            .extend(segments);
+        Ok(url)
+    }
+
+    /// Send an authenticated request, unless the circuit breaker is open. Unreachable servers and
+    /// server errors count as failures.
+    async fn send(&self, request: reqwest::RequestBuilder) -> anyhow::Result<reqwest::Response> {
+        self.breaker.check()?;
+
+        let rsp = request
+            .header(&self.authorization.0, &self.authorization.1)
+            .send()
+            .await;
+
+        match &rsp {
+            Ok(rsp) if !rsp.status().is_server_error() => self.breaker.record_success(),
+            _ => self.breaker.record_failure(),
+        }
+
+        Ok(rsp?)
+    }
+
+    /// Whether the requests are sent, or rejected by the circuit breaker after too many failures
+    pub fn is_available(&self) -> bool {
+        !self.breaker.is_open()
+    }
+
+    /// Check that the API is reachable, returning the OME version if it is advertised
+    pub async fn probe(&self) -> anyhow::Result<Option<String>> {
+        let url = self.endpoint(&["v1", "vhosts"])?;
+
+        let rsp = self
+            .send(self.client.get(url.clone()))
+            .await
+            .with_context(|| format!("could not reach OME API at {url}"))?
+            .error_for_status()?;
+
+        Ok(rsp
+            .headers()
+            .get(reqwest::header::SERVER)
+            .and_then(|server| server.to_str().ok())
+            .map(str::to_owned))
+    }
+
+    fn stream_endpoint(&self, app: &str, stream: &str, action: &str) -> anyhow::Result<Url> {
+        self.endpoint(&[
+            "v1",
+            "vhosts",
+            &self.vhost,
+            "apps",
+            app,
+            "streams",
+            &format!("{stream}:{action}"),
+        ])
+    }
+
+    async fn record_action(&self, app: &str, stream: &str, action: &str) -> anyhow::Result<()> {
+        let url = self.stream_endpoint(app, stream, action)?;
+
+        self.send(
+            self.client
+                .post(url.clone())
+                .json(&ActionRequest { id: stream }),
+        )
+        .await
+        .with_context(|| format!("could not reach OME API at {url}"))?
+        .error_for_status()?;
+
+        Ok(())
+    }
+
+    pub async fn start_record(&self, app: &str, stream: &str) -> anyhow::Result<()> {
+        self.record_action(app, stream, "startRecord").await
+    }
+
+    pub async fn stop_record(&self, app: &str, stream: &str) -> anyhow::Result<()> {
+        self.record_action(app, stream, "stopRecord").await
+    }
+
+    /// Ask OME to push `stream` to `target`, identifying the relay by `id`
+    pub async fn start_push(
+        &self,
+        app: &str,
+        stream: &str,
+        id: &str,
+        target: &PushTarget,
+    ) -> anyhow::Result<()> {
+        let url = self.endpoint(&[
+            "v1",
+            "vhosts",
+            &self.vhost,
+            "apps",
+            &format!("{app}:startPush"),
+        ])?;
+        let request = PushRequest {
+            id,
+            stream: PushStream { name: stream },
+            protocol: target.protocol,
+            url: target.url.as_str(),
+            stream_key: target.stream_key.as_deref(),
+        };
+
+        self.send(self.client.post(url.clone()).json(&request))
+            .await
+            .with_context(|| format!("could not reach OME API at {url}"))?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
+    pub async fn stop_push(&self, app: &str, id: &str) -> anyhow::Result<()> {
+        let url = self.endpoint(&[
+            "v1",
+            "vhosts",
+            &self.vhost,
+            "apps",
+            &format!("{app}:stopPush"),
+        ])?;
+
+        self.send(self.client.post(url.clone()).json(&ActionRequest { id }))
+            .await
+            .with_context(|| format!("could not reach OME API at {url}"))?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
+    /// GET `url`, returning `None` when OME answers `404 Not Found`
+    async fn get<T: serde::de::DeserializeOwned>(&self, url: Url) -> anyhow::Result<Option<T>> {
+        let rsp = self
+            .send(self.client.get(url.clone()))
+            .await
+            .with_context(|| format!("could not reach OME API at {url}"))?;
+
+        if rsp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let rsp = rsp
+            .error_for_status()?
+            .json::<OmeResponse<T>>()
+            .await
+            .with_context(|| format!("invalid OME API response from {url}"))?;
+
+        Ok(Some(rsp.response))
+    }
+
+    /// Statistics of a stream, `None` when it is not live
+    pub async fn stream_stats(
+        &self,
+        app: &str,
+        stream: &str,
+    ) -> anyhow::Result<Option<StreamStats>> {
+        let stats_url = self.endpoint(&[
+            "v1",
+            "stats",
+            "current",
+            "vhosts",
+            &self.vhost,
+            "apps",
+            app,
+            "streams",
+            stream,
+        ])?;
+        let Some(stats) = self.get::<OmeStreamStats>(stats_url).await? else {
+            return Ok(None);
+        };
+
+        let info_url =
+            self.endpoint(&["v1", "vhosts", &self.vhost, "apps", app, "streams", stream])?;
+        let Some(info) = self.get::<OmeStreamInfo>(info_url).await? else {
+            return Ok(None);
+        };
+
+        let fps = info
+            .input
+            .tracks
+            .iter()
+            .find_map(|track| track.video.as_ref())
+            .map(|video| video.framerate)
+            .unwrap_or(0.);
+
+        Ok(Some(StreamStats {
+            bitrate_bps: stats.last_throughput_in,
+            fps,
+            connections: stats.total_connections,
+        }))
+    }
+}
@@ -0,0 +1,106 @@
+use async_trait::async_trait;
+use url::Url;
+
+use crate::{events::AdmissionEvent, OvenAdmission, OvenCtrlConfig};
+
+/// Outcome of an [AdmissionPlugin] for an opening admission
+#[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "decision", content = "reason", rename_all = "lowercase")]
+pub enum PluginDecision {
+    /// Admit the client without consulting the next plugins
+    Allow,
+    /// Deny the client with the given reason
+    Deny(String),
+    /// Let the next plugin decide
+    Defer,
+}
+
+/// Custom admission logic that can't be expressed in the configuration
+#[async_trait]
+pub trait AdmissionPlugin {
+    async fn on_opening(
+        &self,
+        admission: &OvenAdmission,
+        config: &OvenCtrlConfig,
+    ) -> PluginDecision;
+}
+
+/// Plugins consulted in order for opening admissions.
+///
+/// The built-in checks are the head of the chain: plugins are only consulted for clients they
+/// would admit.
+#[derive(Default)]
+pub struct PluginChain {
+    plugins: Vec<Box<dyn AdmissionPlugin + Send + Sync>>,
+}
+
+impl std::fmt::Debug for PluginChain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PluginChain")
+            .field("plugins", &self.plugins.len())
+            .finish()
+    }
+}
+
+impl PluginChain {
+    pub fn push(&mut self, plugin: Box<dyn AdmissionPlugin + Send + Sync>) {
+        self.plugins.push(plugin);
+    }
+
+    /// First decision that is not [PluginDecision::Defer]
+    pub async fn decide(
+        &self,
+        admission: &OvenAdmission,
+        config: &OvenCtrlConfig,
+    ) -> PluginDecision {
+        for plugin in &self.plugins {
+            match plugin.on_opening(admission, config).await {
+                PluginDecision::Defer => continue,
+                decision => return decision,
+            }
+        }
+
+        PluginDecision::Defer
+    }
+}
+
+/// Plugin delegating the decision to an HTTP endpoint.
+///
+/// The endpoint receives the admission as an [AdmissionEvent] and answers with a JSON
+/// [PluginDecision]: `{"decision": "allow"}`, `{"decision": "deny", "reason": "..."}` or
+/// `{"decision": "defer"}`.
+#[derive(Debug)]
+pub struct HttpPlugin {
+    client: reqwest::Client,
+    url: Url,
+}
+
+impl HttpPlugin {
+    pub fn new(client: reqwest::Client, url: Url) -> Self {
+        Self { client, url }
+    }
+
+    async fn request(&self, admission: &OvenAdmission) -> reqwest::Result<PluginDecision> {
+        self.client
+            .post(self.url.clone())
+            .json(&AdmissionEvent::new(admission))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+    }
+}
+
+#[async_trait]
+impl AdmissionPlugin for HttpPlugin {
+    async fn on_opening(&self, admission: &OvenAdmission, _: &OvenCtrlConfig) -> PluginDecision {
+        match self.request(admission).await {
+            Ok(decision) => decision,
+            Err(err) => {
+                tracing::error!("Admission plugin {} failed: {err}", self.url);
+                PluginDecision::Deny("admission plugin unavailable".into())
+            }
+        }
+    }
+}
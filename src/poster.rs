@@ -0,0 +1,97 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{header, HeaderValue, StatusCode},
+    response::IntoResponse,
+};
+use dashmap::DashMap;
+use url::Url;
+
+use crate::OvenCtrlState;
+
+/// Time during which a fetched poster is served without contacting its origin again
+const POSTER_CACHE_TTL: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Clone)]
+struct CachedPoster {
+    fetched: Instant,
+    content_type: HeaderValue,
+    image: Bytes,
+}
+
+/// Room posters fetched from their configured URL, served from our origin to avoid CORS issues
+#[derive(Debug, Default)]
+pub struct PosterCache {
+    posters: DashMap<String, CachedPoster>,
+}
+
+impl PosterCache {
+    async fn get(
+        &self,
+        client: &reqwest::Client,
+        room: &str,
+        url: &Url,
+    ) -> anyhow::Result<CachedPoster> {
+        if let Some(poster) = self.posters.get(room) {
+            if poster.fetched.elapsed() < POSTER_CACHE_TTL {
+                return Ok(poster.clone());
+            }
+        }
+
+        let rsp = client
+            .get(url.clone())
+            .send()
+            .await
+            .and_then(|rsp| rsp.error_for_status())
+            .with_context(|| format!("could not fetch poster {url}"))?;
+
+        let content_type = rsp
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .cloned()
+            .unwrap_or(HeaderValue::from_static("application/octet-stream"));
+        let image = rsp
+            .bytes()
+            .await
+            .with_context(|| format!("could not read poster {url}"))?;
+
+        let poster = CachedPoster {
+            fetched: Instant::now(),
+            content_type,
+            image,
+        };
+        self.posters.insert(room.into(), poster.clone());
+
+        Ok(poster)
+    }
+}
+
+#[tracing::instrument(skip(state))]
+pub async fn room_poster(
+    state: State<Arc<OvenCtrlState>>,
+    Path(room): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let config = state.config();
+    let url = config
+        .room_meta
+        .get(&room)
+        .and_then(|meta| meta.poster_url.as_ref())
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let poster = state
+        .posters
+        .get(&state.http_client, &room, url)
+        .await
+        .map_err(|err| {
+            tracing::error!("Could not serve poster of room {room}: {err:#}");
+            StatusCode::BAD_GATEWAY
+        })?;
+
+    Ok(([(header::CONTENT_TYPE, poster.content_type)], poster.image))
+}
@@ -0,0 +1,135 @@
+use std::{collections::HashMap, sync::Arc, sync::Mutex};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use time::{Date, OffsetDateTime};
+
+use crate::{
+    admin::{scope, RequiredPermission},
+    sessions::IngestSession,
+    OvenCtrlConfig, OvenCtrlState,
+};
+
+#[derive(Debug)]
+struct Usage {
+    period_start: Date,
+    /// Bytes streamed by each streamer since `period_start`
+    bytes: HashMap<String, u64>,
+}
+
+/// Bytes streamed by each streamer during the current billing period
+#[derive(Debug)]
+pub struct QuotaUsage {
+    usage: Mutex<Usage>,
+}
+
+impl Default for QuotaUsage {
+    fn default() -> Self {
+        Self {
+            usage: Mutex::new(Usage {
+                period_start: Date::MIN,
+                bytes: HashMap::new(),
+            }),
+        }
+    }
+}
+
+/// First day of the billing period containing `today`, the periods starting on `reset_day`
+pub(crate) fn period_start(today: Date, reset_day: u8) -> Date {
+    let this_month = today
+        .replace_day(reset_day)
+        .expect("quota_reset_day is validated");
+    if this_month <= today {
+        return this_month;
+    }
+
+    let month = this_month.month().previous();
+    let year = match month {
+        time::Month::December => today.year() - 1,
+        _ => today.year(),
+    };
+    Date::from_calendar_date(year, month, reset_day).expect("quota_reset_day is validated")
+}
+
+/// Estimated number of bytes sent during an ingest session, at the bitrate limit of its room
+fn session_bytes(config: &OvenCtrlConfig, session: &IngestSession) -> u64 {
+    let kbps = config
+        .room_config
+        .get(&session.room)
+        .and_then(|room_config| room_config.max_ingest_bitrate_kbps)
+        .unwrap_or(config.quota_estimated_bitrate_kbps);
+    let duration = OffsetDateTime::now_utc() - session.connected_at;
+
+    (duration.as_seconds_f64().max(0.) * f64::from(kbps) * 1000. / 8.) as u64
+}
+
+impl QuotaUsage {
+    /// Run `f` on the usage of the current period, forgetting the previous periods
+    fn current<T>(&self, reset_day: u8, f: impl FnOnce(&mut Usage) -> T) -> T {
+        let mut usage = self.usage.lock().unwrap();
+
+        let start = period_start(OffsetDateTime::now_utc().date(), reset_day);
+        if usage.period_start != start {
+            usage.period_start = start;
+            usage.bytes.clear();
+        }
+
+        f(&mut usage)
+    }
+
+    /// Bytes streamed by `streamer` during the current period
+    pub fn used(&self, config: &OvenCtrlConfig, streamer: &str) -> u64 {
+        self.current(config.quota_reset_day, |usage| {
+            usage.bytes.get(streamer).copied().unwrap_or(0)
+        })
+    }
+
+    /// Whether `streamer` streamed all of their quota
+    pub fn exhausted(&self, config: &OvenCtrlConfig, streamer: &str) -> bool {
+        config
+            .streamer_monthly_quota_bytes
+            .get(streamer)
+            .is_some_and(|&quota| self.used(config, streamer) >= quota)
+    }
+
+    /// Count the bytes sent during an ingest session that closed
+    pub fn record(&self, config: &OvenCtrlConfig, session: &IngestSession) {
+        let bytes = session_bytes(config, session);
+        self.current(config.quota_reset_day, |usage| {
+            *usage.bytes.entry(session.streamer.clone()).or_default() += bytes;
+        });
+    }
+}
+
+#[derive(serde::Serialize, Debug)]
+pub(crate) struct QuotaStatus {
+    used_bytes: u64,
+    /// Absent for the streamers without a quota
+    limit_bytes: Option<u64>,
+    #[serde(with = "time::serde::rfc3339")]
+    period_start: OffsetDateTime,
+}
+
+/// Usage of the quota of a streamer during the current period
+#[tracing::instrument(skip(_permission, state))]
+pub(crate) async fn status(
+    _permission: RequiredPermission<scope::ReadMetrics>,
+    State(state): State<Arc<OvenCtrlState>>,
+    Path(name): Path<String>,
+) -> Result<Json<QuotaStatus>, StatusCode> {
+    let config = state.config();
+    if !config.streamers.contains_key(&name) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    Ok(Json(QuotaStatus {
+        used_bytes: state.quota_usage.used(&config, &name),
+        limit_bytes: config.streamer_monthly_quota_bytes.get(&name).copied(),
+        period_start: period_start(OffsetDateTime::now_utc().date(), config.quota_reset_day)
+            .midnight()
+            .assume_utc(),
+    }))
+}
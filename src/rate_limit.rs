@@ -0,0 +1,74 @@
+use std::time::Instant;
+
+use dashmap::DashMap;
+use ovenctrl_macros::ConfigDoc;
+
+use crate::OvenCtrlConfig;
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, ConfigDoc)]
+pub struct RateLimitConfig {
+    /// Maximum number of admissions in a burst
+    pub capacity: f64,
+    /// Number of admissions regained per second
+    pub refill_rate: f64,
+}
+
+#[derive(Debug)]
+pub struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+    capacity: f64,
+    refill_rate: f64,
+}
+
+impl TokenBucket {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            tokens: config.capacity,
+            last_refill: Instant::now(),
+            capacity: config.capacity,
+            refill_rate: config.refill_rate,
+        }
+    }
+
+    /// Last time a token was taken or refused
+    pub fn last_used(&self) -> Instant {
+        self.last_refill
+    }
+
+    /// Take a token from the bucket, returns false if the bucket is empty
+    pub fn try_consume(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * self.refill_rate).min(self.capacity);
+        self.last_refill = now;
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+/// Consume a token for the streamer, returns false if the streamer exceeded its rate limit
+pub fn consume(
+    buckets: &DashMap<String, TokenBucket>,
+    config: &OvenCtrlConfig,
+    streamer: &str,
+) -> bool {
+    let Some(limit) = config
+        .streamer_rate_limits
+        .get(streamer)
+        .or(config.rate_limit.as_ref())
+    else {
+        return true;
+    };
+
+    buckets
+        .entry(streamer.to_owned())
+        .or_insert_with(|| TokenBucket::new(*limit))
+        .try_consume()
+}
@@ -0,0 +1,70 @@
+//! Offline replay of admission requests, e.g. to benchmark the admission logic against a dump of
+//! the production traffic
+
+use std::{
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Arc,
+    time::Instant,
+};
+
+use anyhow::Context;
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tower::Service;
+
+use crate::{build_router, OvenCtrlConfig, OvenCtrlState};
+
+/// Send each line of the NDJSON file at `path` to the admission endpoint, as OME would, and print
+/// the responses to stdout as NDJSON.
+///
+/// The requests are processed in order by the full admission logic, updating the state as they
+/// would in production. Requests that are rejected are printed as `{"status": ..., "error": ...}`.
+pub async fn run(config: OvenCtrlConfig, path: &Path) -> anyhow::Result<()> {
+    let admission_path = config.admission_path.clone();
+    let state = Arc::new(OvenCtrlState::from_config(config)?);
+    let mut app = build_router(state);
+
+    let file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("could not open {}", path.display()))?;
+    let mut lines = BufReader::new(file).lines();
+    let mut stdout = BufWriter::new(std::io::stdout());
+
+    let start = Instant::now();
+    let mut count = 0;
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let request = Request::post(&admission_path)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(line))?;
+        let rsp = app.call(request).await?;
+
+        let status = rsp.status();
+        let body = axum::body::to_bytes(rsp.into_body(), usize::MAX).await?;
+        if status == StatusCode::OK {
+            stdout.write_all(&body)?;
+        } else {
+            serde_json::to_writer(
+                &mut stdout,
+                &serde_json::json!({
+                    "status": status.as_u16(),
+                    "error": String::from_utf8_lossy(&body),
+                }),
+            )?;
+        }
+        stdout.write_all(b"\n")?;
+        count += 1;
+    }
+    stdout.flush()?;
+
+    tracing::info!("Replayed {count} admissions in {:?}", start.elapsed());
+
+    Ok(())
+}
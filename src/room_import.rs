@@ -0,0 +1,178 @@
+//! `oven-ctrl import-rooms`: creation of rooms in bulk from a CSV file of
+//! `room_name,password,allowed_streamers,category` rows
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+use anyhow::Context;
+use url::Url;
+
+use crate::DEFAULT_ROOM_NAME_PATTERN;
+
+/// Destination of the imported rooms
+#[derive(Debug)]
+pub enum ImportTarget {
+    /// TOML file to use as an additional `--config`
+    File(std::path::PathBuf),
+    /// Running server, through `POST /api/import`
+    Server { url: Url, token: String },
+}
+
+/// Rooms read from the CSV file, in the layout of the configuration
+#[derive(serde::Serialize, Debug, Default, PartialEq, Eq)]
+pub(crate) struct RoomImport {
+    pub(crate) rooms: BTreeMap<String, String>,
+    pub(crate) allowed_streams: BTreeMap<String, BTreeSet<String>>,
+    pub(crate) room_category: BTreeMap<String, String>,
+}
+
+/// Header of the CSV files, which may be omitted
+const HEADER: [&str; 4] = ["room_name", "password", "allowed_streamers", "category"];
+
+/// Row of the CSV file
+#[derive(serde::Deserialize, Debug)]
+struct RoomRow {
+    room_name: String,
+    password: String,
+    allowed_streamers: String,
+    #[serde(default)]
+    category: String,
+}
+
+impl RoomImport {
+    /// Add the room of a CSV row
+    fn add_row(
+        &mut self,
+        record: &csv::StringRecord,
+        header: &csv::StringRecord,
+        room_pattern: &regex::Regex,
+    ) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            (3..=4).contains(&record.len()),
+            "expected 3 or 4 fields, found {}",
+            record.len()
+        );
+        // The category may be omitted
+        let mut record = record.clone();
+        while record.len() < header.len() {
+            record.push_field("");
+        }
+        let RoomRow {
+            room_name: room,
+            password,
+            allowed_streamers: streamers,
+            category,
+        } = record.deserialize(Some(header))?;
+
+        anyhow::ensure!(
+            room_pattern.is_match(&room),
+            "invalid room name {room:?}, it must match {DEFAULT_ROOM_NAME_PATTERN}"
+        );
+        anyhow::ensure!(!password.is_empty(), "missing password");
+        anyhow::ensure!(!self.rooms.contains_key(&room), "duplicate room {room}");
+
+        for streamer in streamers
+            .split([';', ',', ' '])
+            .filter(|streamer| !streamer.is_empty())
+        {
+            self.allowed_streams
+                .entry(streamer.to_owned())
+                .or_default()
+                .insert(room.clone());
+        }
+        if !category.is_empty() {
+            self.room_category.insert(room.clone(), category);
+        }
+        self.rooms.insert(room, password);
+
+        Ok(())
+    }
+
+    /// Read the rooms of a CSV file, returning the errors of the invalid rows with their line
+    /// number. The columns are named by the first line when it is a header, and are in the
+    /// order of [HEADER] otherwise.
+    pub(crate) fn parse(csv: &str) -> (Self, Vec<String>) {
+        let room_pattern =
+            regex::Regex::new(DEFAULT_ROOM_NAME_PATTERN).expect("the default pattern is valid");
+        let mut import = Self::default();
+        let mut errors = Vec::new();
+
+        let has_header = csv.trim_start().starts_with(HEADER[0]);
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(has_header)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(csv.as_bytes());
+        let header = match has_header {
+            true => reader.headers().cloned().unwrap_or_default(),
+            false => csv::StringRecord::from(HEADER.to_vec()),
+        };
+
+        for record in reader.records() {
+            let (line, result) = match record {
+                Ok(record) if record.iter().all(str::is_empty) => continue,
+                Ok(record) => (
+                    record.position().map(csv::Position::line),
+                    import.add_row(&record, &header, &room_pattern),
+                ),
+                Err(err) => (err.position().map(csv::Position::line), Err(err.into())),
+            };
+
+            if let Err(err) = result {
+                errors.push(format!("line {}: {err:#}", line.unwrap_or_default()));
+            }
+        }
+
+        (import, errors)
+    }
+}
+
+/// Import the rooms of the CSV file at `path` into `target`, reporting the invalid lines on
+/// stderr. The valid lines are imported even when some are invalid.
+pub async fn run(path: &Path, target: ImportTarget) -> anyhow::Result<()> {
+    let csv = tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("could not read {}", path.display()))?;
+
+    let (import, errors) = RoomImport::parse(&csv);
+    for error in &errors {
+        eprintln!("{}: {error}", path.display());
+    }
+
+    match target {
+        ImportTarget::File(output) => {
+            let toml = toml::to_string(&import).context("could not serialize the rooms")?;
+            tokio::fs::write(&output, toml)
+                .await
+                .with_context(|| format!("could not write {}", output.display()))?;
+            eprintln!("Wrote {} rooms to {}", import.rooms.len(), output.display());
+        }
+        ImportTarget::Server { url, token } => {
+            let url = url
+                .join("api/import?merge_mode=merge")
+                .context("invalid server URL")?;
+            let summary: serde_json::Value = reqwest::Client::new()
+                .post(url)
+                .bearer_auth(token)
+                .json(&import)
+                .send()
+                .await
+                .context("could not reach the server")?
+                .error_for_status()
+                .context("the server refused the import")?
+                .json()
+                .await?;
+            eprintln!("Imported {} rooms: {summary}", import.rooms.len());
+        }
+    }
+
+    anyhow::ensure!(
+        errors.is_empty(),
+        "{} invalid lines were not imported",
+        errors.len()
+    );
+
+    Ok(())
+}
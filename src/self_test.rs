@@ -0,0 +1,202 @@
+use std::sync::{Arc, RwLock};
+
+use anyhow::Context;
+use time::OffsetDateTime;
+use url::Url;
+
+use crate::{
+    analytics::PlayerAnalytics, events::EventLog, handle_opening_admission, OvenAdmission,
+    OvenClient, OvenCtrlConfig, OvenCtrlState, OvenDirection, OvenProtocol, OvenRequest,
+    OvenStatus, Policy,
+};
+
+#[derive(Debug)]
+pub struct SelfTestResult {
+    pub description: String,
+    pub expected_allowed: bool,
+    pub allowed: bool,
+    pub reason: Option<String>,
+}
+
+impl SelfTestResult {
+    pub fn passed(&self) -> bool {
+        self.expected_allowed == self.allowed
+    }
+}
+
+fn synthetic_admission(
+    direction: OvenDirection,
+    room: &str,
+    query: &[(&str, &str)],
+) -> anyhow::Result<OvenAdmission> {
+    let mut url = Url::parse("ws://self-test/app/").expect("static url is valid");
+    url.path_segments_mut()
+        .expect("ws urls have segments")
+        .pop_if_empty()
+        .push(room);
+    url.query_pairs_mut().extend_pairs(query);
+
+    Ok(OvenAdmission {
+        client: OvenClient {
+            address: "127.0.0.1".into(),
+            port: 1,
+            user_agent: "oven-ctrl-self-test".into(),
+        },
+        request: OvenRequest {
+            direction,
+            protocol: OvenProtocol::WebRTC,
+            status: OvenStatus::Opening,
+            url: url.try_into()?,
+            new_url: None,
+            time: OffsetDateTime::now_utc(),
+            bypass: None,
+        },
+    })
+}
+
+fn check(
+    state: &OvenCtrlState,
+    description: String,
+    expected_allowed: bool,
+    admission: anyhow::Result<OvenAdmission>,
+) -> SelfTestResult {
+    let outcome = admission
+        .with_context(|| format!("could not build synthetic payload for {description}"))
+        .and_then(|admission| handle_opening_admission(state, &admission));
+
+    let (allowed, reason) = match outcome {
+        Ok(rsp) => (rsp.allowed, rsp.reason),
+        Err(err) => (false, Some(err.to_string())),
+    };
+
+    SelfTestResult {
+        description,
+        expected_allowed,
+        allowed,
+        reason,
+    }
+}
+
+/// Run synthetic admission requests derived from the configuration through the admission logic
+pub fn self_test(state: &OvenCtrlState) -> Vec<SelfTestResult> {
+    let mut results = Vec::new();
+    let config = state.config();
+
+    // Rate limits would make the result depend on the number of checks, and the synthetic client
+    // is not a real connection to a real OME application
+    let state = OvenCtrlState {
+        config: RwLock::new(Arc::new(OvenCtrlConfig {
+            rate_limit: None,
+            streamer_rate_limits: Default::default(),
+            client_port_range: None,
+            bypass_ips: Vec::new(),
+            ome_app_name: None,
+            vhost: None,
+            bot_detection: Default::default(),
+            ..(*config).clone()
+        })),
+        rate_limits: Default::default(),
+        ome_api: None,
+        ome_version: Default::default(),
+        room_name_pattern: state.room_name_pattern.clone(),
+        stream_key_pattern: state.stream_key_pattern.clone(),
+        viewer_sessions: Default::default(),
+        ingest_sessions: Default::default(),
+        viewers: Default::default(),
+        runtime_lock: Default::default(),
+        join_nonces: Default::default(),
+        metrics: Default::default(),
+        events: EventLog::new(0),
+        audit_db: None,
+        plugins: Default::default(),
+        http_client: state.http_client.clone(),
+        posters: Default::default(),
+        invites: Default::default(),
+        quota_usage: Default::default(),
+        stream_events: Default::default(),
+        bots: Default::default(),
+        analytics: PlayerAnalytics::new(0),
+        ome_capabilities: Default::default(),
+        stats: Default::default(),
+        security_headers: Vec::new(),
+    };
+
+    for (streamer, key) in &config.streamers {
+        let allowed_streams = config.allowed_streams.get(streamer);
+
+        for room in allowed_streams.into_iter().flatten() {
+            results.push(check(
+                &state,
+                format!("streamer '{streamer}' ingesting to room '{room}'"),
+                true,
+                synthetic_admission(
+                    OvenDirection::Incoming,
+                    room,
+                    &[("name", streamer), ("key", key)],
+                ),
+            ));
+
+            let bad_key = format!("{key}-invalid");
+            results.push(check(
+                &state,
+                format!("streamer '{streamer}' ingesting to room '{room}' with a bad key"),
+                false,
+                synthetic_admission(
+                    OvenDirection::Incoming,
+                    room,
+                    &[("name", streamer), ("key", &bad_key)],
+                ),
+            ));
+        }
+
+        // Unassigned rooms are open to every streamer with the allow policy
+        if config.default_incoming_policy == Policy::Allow {
+            continue;
+        }
+
+        let mut wrong_room = String::from("self-test");
+        while allowed_streams.is_some_and(|rooms| rooms.contains(&wrong_room)) {
+            wrong_room.push('_');
+        }
+
+        results.push(check(
+            &state,
+            format!("streamer '{streamer}' ingesting to forbidden room '{wrong_room}'"),
+            false,
+            synthetic_admission(
+                OvenDirection::Incoming,
+                &wrong_room,
+                &[("name", streamer), ("key", key)],
+            ),
+        ));
+    }
+
+    for (room, passwords) in &config.rooms {
+        for password in passwords.iter() {
+            results.push(check(
+                &state,
+                format!("viewer joining room '{room}'"),
+                true,
+                synthetic_admission(OvenDirection::Outgoing, room, &[("password", password)]),
+            ));
+        }
+
+        if config.public_rooms.contains(room) {
+            continue;
+        }
+
+        let bad_password = format!("{}-invalid", passwords.iter().collect::<String>());
+        results.push(check(
+            &state,
+            format!("viewer joining room '{room}' with a bad password"),
+            false,
+            synthetic_admission(
+                OvenDirection::Outgoing,
+                room,
+                &[("password", &bad_password)],
+            ),
+        ));
+    }
+
+    results
+}
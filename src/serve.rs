@@ -0,0 +1,560 @@
+use std::{
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use axum::{
+    extract::ConnectInfo,
+    http::{header, HeaderName, HeaderValue, StatusCode, Version},
+    Router,
+};
+use dashmap::DashMap;
+use hyper::{body::Incoming, server::conn::http1, Request};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto::Builder,
+};
+use ppp::{v1, v2};
+use tokio::{
+    io::AsyncReadExt,
+    net::{TcpListener, TcpStream},
+};
+use tower::Service;
+
+const V1_PREFIX: &[u8] = b"PROXY";
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+/// Length of the v2 header before the addresses
+const V2_FIXED_LENGTH: usize = 16;
+/// Longest possible v1 header, including the final CRLF
+const V1_MAX_LENGTH: usize = 107;
+
+const KEEP_ALIVE: HeaderName = HeaderName::from_static("keep-alive");
+
+/// Time given to a client to send its PROXY protocol header
+const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Read the PROXY protocol header at the start of `stream`.
+///
+/// Returns the address of the client on the other side of the proxy, or `None` when the proxy
+/// does not forward it (e.g. health checks of the proxy itself).
+async fn read_proxy_header(stream: &mut TcpStream) -> anyhow::Result<Option<SocketAddr>> {
+    let mut header = vec![0; V1_PREFIX.len()];
+    stream.read_exact(&mut header).await?;
+
+    // The header is directly followed by the HTTP request, so we must not read past it
+    if header == V1_PREFIX {
+        while !header.ends_with(b"\r\n") {
+            if header.len() == V1_MAX_LENGTH {
+                anyhow::bail!("v1 header is too long");
+            }
+            header.push(stream.read_u8().await?);
+        }
+
+        let header = v1::Header::try_from(header.as_slice()).context("invalid v1 header")?;
+        Ok(match header.addresses {
+            v1::Addresses::Tcp4(addresses) => Some(SocketAddr::from((
+                addresses.source_address,
+                addresses.source_port,
+            ))),
+            v1::Addresses::Tcp6(addresses) => Some(SocketAddr::from((
+                addresses.source_address,
+                addresses.source_port,
+            ))),
+            v1::Addresses::Unknown => None,
+        })
+    } else if V2_SIGNATURE.starts_with(&header) {
+        header.resize(V2_FIXED_LENGTH, 0);
+        stream.read_exact(&mut header[V1_PREFIX.len()..]).await?;
+
+        let length = u16::from_be_bytes([header[14], header[15]]) as usize;
+        header.resize(V2_FIXED_LENGTH + length, 0);
+        stream.read_exact(&mut header[V2_FIXED_LENGTH..]).await?;
+
+        let header = v2::Header::try_from(header.as_slice()).context("invalid v2 header")?;
+        if header.command == v2::Command::Local {
+            return Ok(None);
+        }
+
+        Ok(match header.addresses {
+            v2::Addresses::IPv4(addresses) => Some(SocketAddr::from((
+                addresses.source_address,
+                addresses.source_port,
+            ))),
+            v2::Addresses::IPv6(addresses) => Some(SocketAddr::from((
+                addresses.source_address,
+                addresses.source_port,
+            ))),
+            v2::Addresses::Unspecified | v2::Addresses::Unix(_) => None,
+        })
+    } else {
+        anyhow::bail!("missing PROXY protocol header")
+    }
+}
+
+/// Number of open connections of each client address
+#[derive(Debug)]
+struct ConnectionLimits {
+    max_per_ip: usize,
+    connections: DashMap<IpAddr, AtomicUsize>,
+    /// Open connections of all the clients
+    open: AtomicUsize,
+}
+
+/// Open connection of a client, counted until it is dropped
+struct ConnectionGuard {
+    limits: Arc<ConnectionLimits>,
+    ip: IpAddr,
+}
+
+impl ConnectionLimits {
+    fn new(max_per_ip: usize) -> Arc<Self> {
+        Arc::new(Self {
+            max_per_ip,
+            connections: DashMap::new(),
+            open: AtomicUsize::new(0),
+        })
+    }
+
+    /// Count a new connection of `ip`, `None` when it has too many open connections
+    fn acquire(self: &Arc<Self>, ip: IpAddr) -> Option<ConnectionGuard> {
+        let increment = |connections: &AtomicUsize| {
+            connections
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                    (count < self.max_per_ip).then_some(count + 1)
+                })
+                .is_ok()
+        };
+
+        // The counters of the known addresses are only read locked, and the refused addresses
+        // are not inserted, e.g. every address with `max_per_ip = 0`
+        let acquired = match self.connections.get(&ip) {
+            Some(connections) => increment(&connections),
+            None if self.max_per_ip > 0 => increment(&self.connections.entry(ip).or_default()),
+            None => false,
+        };
+        if !acquired {
+            return None;
+        }
+
+        self.open.fetch_add(1, Ordering::Relaxed);
+        Some(ConnectionGuard {
+            limits: self.clone(),
+            ip,
+        })
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        if let Some(connections) = self.limits.connections.get(&self.ip) {
+            connections.fetch_sub(1, Ordering::AcqRel);
+        }
+        // Removing holds the write lock of the shard, so no connection is counted meanwhile
+        self.limits
+            .connections
+            .remove_if(&self.ip, |_, connections| {
+                connections.load(Ordering::Acquire) == 0
+            });
+        self.limits.open.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Requests being processed on a connection, to close the connections left idle
+#[derive(Debug)]
+struct IdleTracker {
+    in_flight: usize,
+    since: Instant,
+}
+
+impl IdleTracker {
+    fn new() -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(Self {
+            in_flight: 0,
+            since: Instant::now(),
+        }))
+    }
+
+    /// Time since the last response was sent, `None` while a request is processed
+    fn idle_for(&self) -> Option<Duration> {
+        (self.in_flight == 0).then(|| self.since.elapsed())
+    }
+}
+
+/// Answers every request of a client with too many open connections
+fn overloaded() -> Router {
+    Router::new().fallback(|| async {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::CONNECTION, "close")],
+            "Too many connections",
+        )
+    })
+}
+
+async fn serve_connection(
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    app: Router,
+    proxy_protocol: bool,
+    limits: Option<Arc<ConnectionLimits>>,
+    keep_alive_timeout: Option<Duration>,
+    http2: bool,
+) {
+    let client = if proxy_protocol {
+        match tokio::time::timeout(PROXY_HEADER_TIMEOUT, read_proxy_header(&mut stream)).await {
+            Ok(Ok(client)) => client.unwrap_or(peer),
+            Ok(Err(err)) => {
+                tracing::warn!("Dropping connection from {peer}: {err:#}");
+                return;
+            }
+            Err(_) => {
+                tracing::warn!(
+                    "Dropping connection from {peer}: no PROXY protocol header received"
+                );
+                return;
+            }
+        }
+    } else {
+        peer
+    };
+
+    let guard = limits.as_ref().map(|limits| limits.acquire(client.ip()));
+    let app = match &guard {
+        Some(None) => {
+            tracing::warn!(
+                "Too many connections from {} ({} open connections in total)",
+                client.ip(),
+                limits
+                    .as_ref()
+                    .map_or(0, |limits| limits.open.load(Ordering::Relaxed)),
+            );
+            overloaded()
+        }
+        _ => app,
+    };
+
+    let tracker = IdleTracker::new();
+    let keep_alive = keep_alive_timeout
+        .map(|timeout| HeaderValue::try_from(format!("timeout={}", timeout.as_secs())))
+        .transpose()
+        .expect("the keep-alive header is valid");
+
+    // Handlers can use the `ConnectInfo<SocketAddr>` extractor as with `axum::serve`
+    let service = {
+        let tracker = tracker.clone();
+        hyper::service::service_fn(move |mut request: Request<Incoming>| {
+            request.extensions_mut().insert(ConnectInfo(client));
+            let http1 = request.version() < Version::HTTP_2;
+            tracker.lock().unwrap().in_flight += 1;
+
+            let rsp = app.clone().call(request);
+            let tracker = tracker.clone();
+            let keep_alive = keep_alive.clone();
+            async move {
+                let mut rsp = rsp.await;
+                if let (true, Ok(rsp), Some(keep_alive)) = (http1, &mut rsp, keep_alive) {
+                    rsp.headers_mut().insert(KEEP_ALIVE, keep_alive);
+                }
+
+                let mut tracker = tracker.lock().unwrap();
+                tracker.in_flight -= 1;
+                tracker.since = Instant::now();
+                rsp
+            }
+        })
+    };
+
+    let io = TokioIo::new(stream);
+    let keep_alive = keep_alive_timeout.is_some();
+    let result = if http2 {
+        // Detects the HTTP/2 connection preface, and serves the other connections as HTTP/1
+        let mut builder = Builder::new(TokioExecutor::new());
+        builder.http1().keep_alive(keep_alive);
+        let connection = builder.serve_connection_with_upgrades(io, service);
+        tokio::pin!(connection);
+
+        close_when_idle(
+            connection,
+            |c| c.graceful_shutdown(),
+            &tracker,
+            keep_alive_timeout,
+        )
+        .await
+    } else {
+        let connection = http1::Builder::new()
+            .keep_alive(keep_alive)
+            .serve_connection(io, service)
+            .with_upgrades();
+        tokio::pin!(connection);
+
+        close_when_idle(
+            connection,
+            |c| c.graceful_shutdown(),
+            &tracker,
+            keep_alive_timeout,
+        )
+        .await
+        .map_err(Into::into)
+    };
+    if let Err(err) = result {
+        tracing::debug!("Error serving connection from {client}: {err}");
+    }
+}
+
+/// Drive `connection` to completion, shutting it down once it has been idle for
+/// `keep_alive_timeout`
+async fn close_when_idle<C, E>(
+    mut connection: Pin<&mut C>,
+    graceful_shutdown: impl Fn(Pin<&mut C>),
+    tracker: &Mutex<IdleTracker>,
+    keep_alive_timeout: Option<Duration>,
+) -> Result<(), E>
+where
+    C: Future<Output = Result<(), E>>,
+{
+    let Some(timeout) = keep_alive_timeout else {
+        return connection.await;
+    };
+
+    loop {
+        let idle_for = tracker.lock().unwrap().idle_for();
+        match idle_for {
+            Some(idle_for) if idle_for >= timeout => {
+                // Closes the connection once the current responses, if any, are sent
+                graceful_shutdown(connection.as_mut());
+                return connection.await;
+            }
+            _ => {
+                let wait = timeout - idle_for.unwrap_or_default();
+                tokio::select! {
+                    result = connection.as_mut() => return result,
+                    _ = tokio::time::sleep(wait) => {}
+                }
+            }
+        }
+    }
+}
+
+/// Serve `app` on `listener`, like `axum::serve` but optionally expecting PROXY protocol headers,
+/// limiting the number of connections of each client address, and closing the connections idle
+/// for `keep_alive_timeout` (`None` closing the HTTP/1 connections after each request). With
+/// `http2`, the connections starting with the HTTP/2 preface are served as cleartext HTTP/2 (h2c
+/// with prior knowledge), the other ones as HTTP/1.
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    proxy_protocol: bool,
+    max_connections_per_ip: Option<usize>,
+    keep_alive_timeout: Option<Duration>,
+    http2: bool,
+) -> anyhow::Result<()> {
+    let limits = max_connections_per_ip.map(ConnectionLimits::new);
+
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(err) => {
+                // Most likely out of file descriptors, give some time for connections to close
+                tracing::error!("Could not accept connection: {err}");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+
+        tokio::spawn(serve_connection(
+            stream,
+            peer,
+            app.clone(),
+            proxy_protocol,
+            limits.clone(),
+            keep_alive_timeout,
+            http2,
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use axum::routing::get;
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+
+    const V1_HEADER: &[u8] = b"PROXY TCP4 203.0.113.7 192.0.2.1 40000 80\r\n";
+
+    /// PROXY protocol v2 header of a TCP over IPv4 connection from 203.0.113.7:40000
+    fn v2_header() -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend([0x21, 0x11, 0, 12]);
+        header.extend([203, 0, 113, 7, 192, 0, 2, 1]);
+        header.extend(40000u16.to_be_bytes());
+        header.extend(80u16.to_be_bytes());
+        header
+    }
+
+    /// Connected pair of loopback streams
+    async fn stream_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        (client, server)
+    }
+
+    /// Read the PROXY protocol header of a connection on which the client sent `bytes`, returning
+    /// what follows it
+    async fn proxy_header(bytes: &[u8]) -> (anyhow::Result<Option<SocketAddr>>, Vec<u8>) {
+        let (mut client, mut server) = stream_pair().await;
+        client.write_all(bytes).await.unwrap();
+        drop(client);
+
+        let header = read_proxy_header(&mut server).await;
+        let mut rest = Vec::new();
+        server.read_to_end(&mut rest).await.unwrap();
+        (header, rest)
+    }
+
+    fn client() -> SocketAddr {
+        SocketAddr::from((Ipv4Addr::new(203, 0, 113, 7), 40000))
+    }
+
+    #[tokio::test]
+    async fn valid_proxy_headers() {
+        let (header, rest) = proxy_header(&[V1_HEADER, b"GET / HTTP/1.1\r\n"].concat()).await;
+        assert_eq!(header.unwrap(), Some(client()));
+        assert_eq!(rest, b"GET / HTTP/1.1\r\n");
+
+        let (header, rest) =
+            proxy_header(&[v2_header().as_slice(), b"GET / HTTP/1.1\r\n"].concat()).await;
+        assert_eq!(header.unwrap(), Some(client()));
+        assert_eq!(rest, b"GET / HTTP/1.1\r\n");
+
+        let (header, _) = proxy_header(b"PROXY UNKNOWN\r\n").await;
+        assert_eq!(header.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn local_proxy_header() {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend([0x20, 0, 0, 0]);
+        assert_eq!(proxy_header(&header).await.0.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn invalid_proxy_headers() {
+        let v2 = v2_header();
+        let mut bad_signature = v2.clone();
+        bad_signature[10] = b'X';
+        let mut bad_version = v2.clone();
+        bad_version[12] = 0x31;
+        let too_long = [b"PROXY TCP4 ".as_slice(), &[b'1'; 120], b"\r\n"].concat();
+
+        for bytes in [
+            &V1_HEADER[..20],
+            &v2[..10],
+            &v2[..V2_FIXED_LENGTH + 4],
+            &bad_signature,
+            &bad_version,
+            b"PROXY TCP4 not-an-address 192.0.2.1 40000 80\r\n",
+            &too_long,
+            b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        ] {
+            assert!(
+                proxy_header(bytes).await.0.is_err(),
+                "{:?}",
+                String::from_utf8_lossy(bytes)
+            );
+        }
+    }
+
+    /// Serve an application answering with the client address, and send it an HTTP request
+    /// preceded by `prefix`, returning the raw response
+    async fn request(
+        prefix: &[u8],
+        proxy_protocol: bool,
+        max_connections_per_ip: Option<usize>,
+    ) -> String {
+        let app = Router::new().route(
+            "/",
+            get(|ConnectInfo(client): ConnectInfo<SocketAddr>| async move { client.to_string() }),
+        );
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(
+            listener,
+            app,
+            proxy_protocol,
+            max_connections_per_ip,
+            None,
+            false,
+        ));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(prefix).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        // The connection may be reset once the response is sent, as the request was not read
+        let mut rsp = Vec::new();
+        let mut buf = [0; 1024];
+        while let Ok(read @ 1..) = stream.read(&mut buf).await {
+            rsp.extend(&buf[..read]);
+        }
+        String::from_utf8(rsp).unwrap()
+    }
+
+    #[tokio::test]
+    async fn proxy_protocol() {
+        let rsp = request(V1_HEADER, true, None).await;
+        assert!(rsp.starts_with("HTTP/1.1 200 OK"), "{rsp}");
+        assert!(rsp.ends_with("203.0.113.7:40000"), "{rsp}");
+
+        // Without a header the connection is dropped
+        assert_eq!(request(b"", true, None).await, "");
+
+        // The header of a client that is not trusted to send one is not parsed
+        let rsp = request(V1_HEADER, false, None).await;
+        assert!(rsp.starts_with("HTTP/1.1 400"), "{rsp}");
+        assert!(!rsp.contains("203.0.113.7"), "{rsp}");
+    }
+
+    #[tokio::test]
+    async fn connection_limits() {
+        let limits = ConnectionLimits::new(2);
+        let ip = IpAddr::from(Ipv4Addr::LOCALHOST);
+
+        let first = limits.acquire(ip).unwrap();
+        let second = limits.acquire(ip).unwrap();
+        assert!(limits.acquire(ip).is_none());
+        let other = limits.acquire(Ipv4Addr::new(192, 0, 2, 1).into()).unwrap();
+        assert_eq!(limits.open.load(Ordering::Relaxed), 3);
+        drop(other);
+
+        // The guards release their slot when dropped
+        drop(first);
+        let third = limits.acquire(ip).unwrap();
+        drop((second, third));
+        assert!(limits.connections.is_empty());
+        assert_eq!(limits.open.load(Ordering::Relaxed), 0);
+
+        let refused = ConnectionLimits::new(0);
+        assert!(refused.acquire(ip).is_none());
+        assert!(refused.connections.is_empty());
+
+        let rsp = request(b"", false, Some(0)).await;
+        assert!(rsp.starts_with("HTTP/1.1 503"), "{rsp}");
+        assert!(rsp.to_lowercase().contains("connection: close"), "{rsp}");
+        assert!(rsp.ends_with("Too many connections"), "{rsp}");
+    }
+}
@@ -0,0 +1,212 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use dashmap::DashMap;
+use time::OffsetDateTime;
+
+use crate::{OvenAdmission, OvenProtocol};
+
+/// Stream currently being pushed by a streamer
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+pub struct IngestSession {
+    pub session_id: u64,
+    pub streamer: String,
+    pub app: String,
+    pub room: String,
+    pub protocol: OvenProtocol,
+    pub client_ip: String,
+    pub client_port: u16,
+    #[serde(with = "time::serde::rfc3339")]
+    pub connected_at: OffsetDateTime,
+}
+
+/// Number of connection attempts kept for each streamer
+const ATTEMPTS_PER_STREAMER: usize = 20;
+
+#[derive(serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AttemptOutcome {
+    Allowed,
+    Denied,
+}
+
+/// Opening admission of a streamer, kept to investigate why they can't stream
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct ConnectionAttempt {
+    #[serde(with = "time::serde::rfc3339")]
+    pub timestamp: OffsetDateTime,
+    pub client_ip: String,
+    pub outcome: AttemptOutcome,
+    pub reason: Option<String>,
+    pub protocol: OvenProtocol,
+    /// First characters of the key that was provided, the rest is not kept
+    pub key_prefix: Option<String>,
+}
+
+/// Ingest sessions between their opening and closing admissions
+#[derive(Debug, Default)]
+pub struct IngestSessions {
+    next_id: AtomicU64,
+    /// Sessions by (client address, client port, app, room)
+    sessions: DashMap<(String, u16, String, String), IngestSession>,
+    /// Latest connection attempts of each streamer, oldest first
+    attempts: DashMap<String, VecDeque<ConnectionAttempt>>,
+}
+
+fn session_key(payload: &OvenAdmission) -> (String, u16, String, String) {
+    (
+        payload.client.address.clone(),
+        payload.client.port,
+        payload.request.url.app.clone(),
+        payload.request.url.room.clone(),
+    )
+}
+
+impl IngestSessions {
+    pub fn open(&self, payload: &OvenAdmission, streamer: String) {
+        let session = IngestSession {
+            session_id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            streamer,
+            app: payload.request.url.app.clone(),
+            room: payload.request.url.room.clone(),
+            protocol: payload.request.protocol,
+            client_ip: payload.client.address.clone(),
+            client_port: payload.client.port,
+            connected_at: OffsetDateTime::now_utc(),
+        };
+
+        self.sessions.insert(session_key(payload), session);
+    }
+
+    pub fn close(&self, payload: &OvenAdmission) -> Option<IngestSession> {
+        self.sessions
+            .remove(&session_key(payload))
+            .map(|(_, session)| session)
+    }
+
+    /// Active sessions, oldest first
+    pub fn all(&self) -> Vec<IngestSession> {
+        let mut sessions = self
+            .sessions
+            .iter()
+            .map(|session| session.clone())
+            .collect::<Vec<_>>();
+        sessions.sort_by_key(|session| session.session_id);
+        sessions
+    }
+
+    /// Replace the active sessions by `sessions`, taken from [IngestSessions::all]
+    pub fn restore(&self, sessions: Vec<IngestSession>) {
+        self.sessions.clear();
+
+        let next_id = sessions.iter().map(|s| s.session_id + 1).max().unwrap_or(0);
+        self.next_id.fetch_max(next_id, Ordering::Relaxed);
+
+        for session in sessions {
+            let key = (
+                session.client_ip.clone(),
+                session.client_port,
+                session.app.clone(),
+                session.room.clone(),
+            );
+            self.sessions.insert(key, session);
+        }
+    }
+
+    pub fn of_streamer(&self, streamer: &str) -> Vec<IngestSession> {
+        let mut sessions = self.all();
+        sessions.retain(|session| session.streamer == streamer);
+        sessions
+    }
+
+    /// Record the outcome of an opening admission of `streamer`
+    pub fn record_attempt(
+        &self,
+        payload: &OvenAdmission,
+        streamer: &str,
+        allowed: bool,
+        reason: Option<String>,
+    ) {
+        let key_prefix = payload
+            .request
+            .url
+            .url
+            .query_pairs()
+            .find(|(key, _)| key == "key")
+            .map(|(_, key)| key.chars().take(4).collect());
+
+        let mut attempts = self.attempts.entry(streamer.to_owned()).or_default();
+        if attempts.len() == ATTEMPTS_PER_STREAMER {
+            attempts.pop_front();
+        }
+        attempts.push_back(ConnectionAttempt {
+            timestamp: OffsetDateTime::now_utc(),
+            client_ip: payload.client.address.clone(),
+            outcome: match allowed {
+                true => AttemptOutcome::Allowed,
+                false => AttemptOutcome::Denied,
+            },
+            reason,
+            protocol: payload.request.protocol,
+            key_prefix,
+        });
+    }
+
+    /// Latest connection attempts of `streamer`, oldest first
+    pub fn attempts_of(&self, streamer: &str) -> Vec<ConnectionAttempt> {
+        self.attempts
+            .get(streamer)
+            .map(|attempts| attempts.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Number of viewers watching each room, between their opening and closing admissions
+#[derive(Debug, Default)]
+pub struct ViewerCounts {
+    rooms: DashMap<String, AtomicU64>,
+}
+
+impl ViewerCounts {
+    pub fn join(&self, room: &str) {
+        self.rooms
+            .entry(room.to_owned())
+            .or_default()
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a viewer leaving `room`, returning whether one was counted in it
+    pub fn leave(&self, room: &str) -> bool {
+        // Sessions opened before a restart are closed without having been counted
+        self.rooms.get(room).is_some_and(|count| {
+            count
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+                    count.checked_sub(1)
+                })
+                .is_ok()
+        })
+    }
+
+    pub fn get(&self, room: &str) -> u64 {
+        self.rooms
+            .get(room)
+            .map(|count| count.load(Ordering::Relaxed))
+            .unwrap_or(0)
+    }
+
+    pub fn snapshot(&self) -> HashMap<String, u64> {
+        self.rooms
+            .iter()
+            .map(|count| (count.key().clone(), count.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    pub fn restore(&self, rooms: HashMap<String, u64>) {
+        self.rooms.clear();
+        for (room, count) in rooms {
+            self.rooms.insert(room, AtomicU64::new(count));
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use std::{sync::Arc, time::Instant};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use dashmap::DashMap;
+
+use crate::OvenCtrlState;
+
+/// Simplified statistics of the stream of a room
+#[derive(serde::Serialize, Debug, Clone, Default)]
+pub struct RoomStats {
+    pub bitrate_kbps: u64,
+    pub fps: f64,
+    pub viewer_count: u64,
+    pub is_live: bool,
+}
+
+/// Room statistics fetched from the OME Manager API, kept for `stats_cache_ttl_secs`
+#[derive(Debug, Default)]
+pub struct StatsCache {
+    rooms: DashMap<String, (Instant, RoomStats)>,
+}
+
+#[tracing::instrument(skip(state))]
+pub async fn room_stats(
+    state: State<Arc<OvenCtrlState>>,
+    Path(room): Path<String>,
+) -> Result<Json<RoomStats>, StatusCode> {
+    let config = state.config();
+    if !config.rooms.contains_key(&room) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let Some(api) = &state.ome_api else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+
+    if let Some(cached) = state.stats.rooms.get(&room) {
+        let (fetched, stats) = &*cached;
+        if fetched.elapsed().as_secs() < config.stats_cache_ttl_secs {
+            return Ok(Json(stats.clone()));
+        }
+    }
+
+    let stats = match api.stream_stats(config.ome_app(), &room).await {
+        Ok(None) => RoomStats::default(),
+        Ok(Some(stats)) => RoomStats {
+            bitrate_kbps: stats.bitrate_bps / 1000,
+            fps: stats.fps,
+            viewer_count: stats.connections,
+            is_live: true,
+        },
+        Err(err) => {
+            tracing::error!("Could not fetch the statistics of room {room}: {err:#}");
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+    state
+        .stats
+        .rooms
+        .insert(room, (Instant::now(), stats.clone()));
+
+    Ok(Json(stats))
+}
@@ -0,0 +1,268 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use base64::Engine;
+use dashmap::DashMap;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use time::OffsetDateTime;
+use url::Url;
+
+use crate::{
+    admin::{scope, RequiredPermission},
+    OvenAdmission, OvenClient, OvenCtrlState, OvenDirection, OvenProtocol, OvenRequest, OvenStatus,
+};
+
+/// Time after which a subscription is reported unreachable by `/api/test-webhook`
+const TEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Admissions forwarded to a subscription
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamEventKind {
+    /// Admitted opening admissions
+    Open,
+    Close,
+}
+
+fn default_events() -> Vec<StreamEventKind> {
+    vec![StreamEventKind::Open, StreamEventKind::Close]
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub(crate) struct Subscription {
+    url: Url,
+    /// Key of the `X-OME-Signature` of the forwarded admissions
+    secret: String,
+    #[serde(default = "default_events")]
+    events: Vec<StreamEventKind>,
+}
+
+/// Services the admissions are forwarded to, in the format of the OME admission webhooks
+#[derive(Debug, Default)]
+pub struct StreamEventRelay {
+    subscriptions: DashMap<String, Subscription>,
+}
+
+/// Query parameters holding the credentials of the clients, e.g. stream keys
+const CREDENTIAL_PARAMETERS: &[&str] = &["key", "password", "invite"];
+
+/// `url` without the credentials of the client, which the subscribers must not receive
+fn without_credentials(url: &Url) -> Url {
+    let pairs = url
+        .query_pairs()
+        .filter(|(name, _)| !CREDENTIAL_PARAMETERS.contains(&name.as_ref()))
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect::<Vec<_>>();
+
+    let mut url = url.clone();
+    url.set_query(None);
+    if !pairs.is_empty() {
+        url.query_pairs_mut().extend_pairs(pairs);
+    }
+    url
+}
+
+/// `X-OME-Signature` of a webhook body, as computed by OME
+fn signature(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha1>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+}
+
+impl Subscription {
+    /// Request forwarding `body` to the subscription
+    fn request(&self, client: &reqwest::Client, body: Vec<u8>) -> reqwest::RequestBuilder {
+        client
+            .post(self.url.clone())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header("X-OME-Signature", signature(&self.secret, &body))
+            .body(body)
+    }
+}
+
+impl StreamEventRelay {
+    /// Forward an admission to the matching subscriptions, without waiting for them to answer
+    pub fn relay(&self, client: &reqwest::Client, payload: &OvenAdmission) {
+        let kind = match payload.request.status {
+            OvenStatus::Opening => StreamEventKind::Open,
+            OvenStatus::Closing => StreamEventKind::Close,
+        };
+
+        let body = serde_json::to_value(payload).and_then(|mut body| {
+            body["request"]["url"] = without_credentials(&payload.request.url.url)
+                .as_str()
+                .into();
+            if let Some(new_url) = &payload.request.new_url {
+                body["request"]["new_url"] = without_credentials(new_url).as_str().into();
+            }
+            serde_json::to_vec(&body)
+        });
+        let body = match body {
+            Ok(body) => body,
+            Err(err) => {
+                tracing::error!("Could not serialize the admission to relay: {err}");
+                return;
+            }
+        };
+
+        for subscription in self.subscriptions.iter() {
+            if !subscription.events.contains(&kind) {
+                continue;
+            }
+
+            let request = subscription.request(client, body.clone());
+            let id = subscription.key().clone();
+
+            tokio::spawn(async move {
+                if let Err(err) = request.send().await.and_then(|rsp| rsp.error_for_status()) {
+                    tracing::warn!("Could not relay the admission to subscription {id}: {err}");
+                }
+            });
+        }
+    }
+}
+
+#[derive(serde::Serialize, Debug)]
+pub(crate) struct SubscribeResponse {
+    subscription_id: String,
+}
+
+#[tracing::instrument(skip(_permission, state, subscription))]
+pub(crate) async fn subscribe(
+    _permission: RequiredPermission<scope::ManageWebhooks>,
+    State(state): State<Arc<OvenCtrlState>>,
+    Json(subscription): Json<Subscription>,
+) -> Result<Json<SubscribeResponse>, (StatusCode, String)> {
+    let max = state.config().max_webhook_subscribers;
+    let subscriptions = &state.stream_events.subscriptions;
+    // Not atomic with the insertion, concurrent subscriptions may briefly go over the limit
+    if subscriptions.len() >= max {
+        return Err((
+            StatusCode::CONFLICT,
+            format!("there are already {max} subscriptions"),
+        ));
+    }
+
+    let id = uuid::Uuid::new_v4().to_string();
+    tracing::info!("Relaying the admissions to {} as {id}", subscription.url);
+    subscriptions.insert(id.clone(), subscription);
+
+    Ok(Json(SubscribeResponse {
+        subscription_id: id,
+    }))
+}
+
+#[tracing::instrument(skip(_permission, state))]
+pub(crate) async fn unsubscribe(
+    _permission: RequiredPermission<scope::ManageWebhooks>,
+    State(state): State<Arc<OvenCtrlState>>,
+    Path(id): Path<String>,
+) -> StatusCode {
+    match state.stream_events.subscriptions.remove(&id) {
+        Some(_) => StatusCode::NO_CONTENT,
+        None => StatusCode::NOT_FOUND,
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub(crate) struct TestQuery {
+    subscription_id: Option<String>,
+}
+
+/// Outcome of the delivery of a test admission to a subscription
+#[derive(serde::Serialize, Debug)]
+pub(crate) struct TestResult {
+    subscription_id: String,
+    /// Status of the response, `None` when the subscription could not be reached
+    status: Option<u16>,
+    latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Opening admission of a made up viewer, sent by `/api/test-webhook`
+fn test_admission() -> OvenAdmission {
+    let url = Url::parse("ws://oven-ctrl-test/app/webhook-test").expect("static url is valid");
+
+    OvenAdmission {
+        client: OvenClient {
+            address: "127.0.0.1".into(),
+            port: 1,
+            user_agent: "oven-ctrl-webhook-test".into(),
+        },
+        request: OvenRequest {
+            direction: OvenDirection::Outgoing,
+            protocol: OvenProtocol::WebRTC,
+            status: OvenStatus::Opening,
+            url: url.try_into().expect("static url is a stream url"),
+            new_url: None,
+            time: OffsetDateTime::now_utc(),
+            bypass: None,
+        },
+    }
+}
+
+/// Send a signed test admission to a subscription (or to all of them), reporting the status and
+/// latency of their responses. The test requests have a `X-OvenCtrl-Test` header.
+#[tracing::instrument(skip(_permission, state))]
+pub(crate) async fn test(
+    _permission: RequiredPermission<scope::ManageWebhooks>,
+    State(state): State<Arc<OvenCtrlState>>,
+    Query(query): Query<TestQuery>,
+) -> Result<Json<Vec<TestResult>>, (StatusCode, &'static str)> {
+    let subscriptions = state
+        .stream_events
+        .subscriptions
+        .iter()
+        .filter(|subscription| {
+            query
+                .subscription_id
+                .as_ref()
+                .is_none_or(|id| subscription.key() == id)
+        })
+        .map(|subscription| (subscription.key().clone(), subscription.value().clone()))
+        .collect::<Vec<_>>();
+    if subscriptions.is_empty() {
+        return Err((StatusCode::NOT_FOUND, "no such subscription"));
+    }
+
+    let body = serde_json::to_vec(&test_admission()).expect("admissions can be serialized");
+    let results = subscriptions.into_iter().map(|(id, subscription)| {
+        let request = subscription
+            .request(&state.http_client, body.clone())
+            .header("X-OvenCtrl-Test", "true")
+            .timeout(TEST_TIMEOUT);
+
+        async move {
+            let start = Instant::now();
+            let rsp = request.send().await;
+            let latency_ms = start.elapsed().as_millis() as u64;
+
+            match rsp {
+                Ok(rsp) => TestResult {
+                    subscription_id: id,
+                    status: Some(rsp.status().as_u16()),
+                    latency_ms,
+                    error: None,
+                },
+                Err(err) => TestResult {
+                    subscription_id: id,
+                    status: None,
+                    latency_ms,
+                    error: Some(err.to_string()),
+                },
+            }
+        }
+    });
+
+    Ok(Json(futures_util::future::join_all(results).await))
+}
@@ -0,0 +1,65 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use axum::{
+    extract::ConnectInfo,
+    http::{Request, Response},
+};
+use tower_http::trace::{MakeSpan, OnResponse};
+use tracing::{field, Span};
+
+/// Span of each request, named after the route for the admission webhook and the room login.
+///
+/// Only the path is recorded: the queries of some routes hold tokens or passwords.
+#[derive(Debug, Clone)]
+pub struct RouteSpan {
+    pub admission_path: Arc<str>,
+    pub join_path: Arc<str>,
+}
+
+impl<B> MakeSpan<B> for RouteSpan {
+    fn make_span(&mut self, request: &Request<B>) -> Span {
+        let client = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(client)| *client);
+
+        macro_rules! route_span {
+            ($name:literal) => {
+                tracing::info_span!(
+                    $name,
+                    method = %request.method(),
+                    path = %request.uri().path(),
+                    version = ?request.version(),
+                    client = ?client,
+                    latency_ms = field::Empty,
+                )
+            };
+        }
+
+        match request.uri().path() {
+            path if path == &*self.admission_path => route_span!("admission_request"),
+            path if path == &*self.join_path => route_span!("join_request"),
+            _ => route_span!("request"),
+        }
+    }
+}
+
+/// Log the latency of the responses, at INFO when it exceeds `threshold` and DEBUG otherwise
+#[derive(Debug, Clone, Copy)]
+pub struct LatencyLog {
+    pub threshold: Duration,
+}
+
+impl<B> OnResponse<B> for LatencyLog {
+    fn on_response(self, response: &Response<B>, latency: Duration, span: &Span) {
+        let latency_ms = latency.as_millis() as u64;
+        span.record("latency_ms", latency_ms);
+
+        let status = response.status().as_u16();
+        if latency >= self.threshold {
+            tracing::info!(latency_ms, status, "Slow response");
+        } else {
+            tracing::debug!(latency_ms, status, "Response");
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use std::sync::Arc;
+
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::Engine;
+use ovenctrl_macros::ConfigDoc;
+use subtle::ConstantTimeEq;
+
+use crate::OvenCtrlState;
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, ConfigDoc)]
+pub struct BasicAuthConfig {
+    /// User name the viewers log in with
+    pub username: String,
+    /// Argon2 hash of the password in the PHC string format
+    pub password_hash: String,
+}
+
+impl BasicAuthConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        PasswordHash::new(&self.password_hash)
+            .map_err(|err| anyhow::anyhow!("invalid ui_basic_auth password_hash: {err}"))?;
+        Ok(())
+    }
+
+    fn verify(&self, username: &str, password: &str) -> bool {
+        let Ok(hash) = PasswordHash::new(&self.password_hash) else {
+            return false;
+        };
+
+        let username_matches = bool::from(self.username.as_bytes().ct_eq(username.as_bytes()));
+        let password_matches = Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok();
+
+        username_matches && password_matches
+    }
+}
+
+fn unauthorized() -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, r#"Basic realm="OvenCtrl""#)],
+    )
+        .into_response()
+}
+
+/// Require HTTP Basic credentials on the viewer pages when `ui_basic_auth` is configured
+pub async fn require_ui_auth(
+    State(state): State<Arc<OvenCtrlState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let config = state.config();
+    let Some(auth) = &config.ui_basic_auth else {
+        return next.run(request).await;
+    };
+
+    let Some((username, password)) = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|credentials| {
+            base64::engine::general_purpose::STANDARD
+                .decode(credentials)
+                .ok()
+        })
+        .and_then(|credentials| String::from_utf8(credentials).ok())
+        .and_then(|credentials| {
+            let (username, password) = credentials.split_once(':')?;
+            Some((username.to_owned(), password.to_owned()))
+        })
+    else {
+        return unauthorized();
+    };
+
+    // Hashing the password is slow on purpose, don't block the other requests meanwhile
+    let auth = auth.clone();
+    let verified = tokio::task::spawn_blocking(move || auth.verify(&username, &password))
+        .await
+        .unwrap_or(false);
+
+    if !verified {
+        tracing::warn!("Invalid UI credentials");
+        return unauthorized();
+    }
+
+    next.run(request).await
+}
@@ -0,0 +1,171 @@
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::Context;
+use ovenctrl_macros::ConfigDoc;
+use url::Url;
+
+use crate::OvenCtrlConfig;
+
+/// Attempts made to reach Vault before giving up
+const VAULT_ATTEMPTS: u32 = 5;
+/// Delay before the first retry, doubled after each attempt
+const VAULT_INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Secrets stored in the KV v2 engine of HashiCorp Vault, overriding the configuration file
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, ConfigDoc)]
+pub struct VaultConfig {
+    /// Base URL of the Vault server
+    address: Url,
+    /// Token used to read the secrets, instead of logging in with AppRole
+    token: Option<String>,
+    /// Role ID of the AppRole login
+    role_id: Option<String>,
+    /// Secret ID of the AppRole login
+    secret_id: Option<String>,
+    /// Mount path of the KV v2 engine
+    #[serde(default = "default_mount")]
+    mount: String,
+    /// Path of the secret in the KV engine
+    secrets_path: String,
+}
+
+impl VaultConfig {
+    /// Remove the credentials of the Vault login
+    pub(crate) fn redact(&mut self) {
+        self.token.iter_mut().for_each(crate::redact);
+        self.secret_id.iter_mut().for_each(crate::redact);
+    }
+}
+
+fn default_mount() -> String {
+    "secret".into()
+}
+
+#[derive(serde::Serialize)]
+struct AppRoleLogin<'a> {
+    role_id: &'a str,
+    secret_id: &'a str,
+}
+
+#[derive(serde::Deserialize)]
+struct LoginResponse {
+    auth: LoginAuth,
+}
+
+#[derive(serde::Deserialize)]
+struct LoginAuth {
+    client_token: String,
+}
+
+#[derive(serde::Deserialize)]
+struct SecretResponse {
+    data: SecretData,
+}
+
+#[derive(serde::Deserialize)]
+struct SecretData {
+    data: HashMap<String, String>,
+}
+
+/// Send the request built by `request`, retrying with an exponential backoff while Vault is
+/// unreachable or unavailable
+async fn send_with_retry<T: serde::de::DeserializeOwned>(
+    request: impl Fn() -> reqwest::RequestBuilder,
+) -> anyhow::Result<T> {
+    let mut backoff = VAULT_INITIAL_BACKOFF;
+
+    for attempt in 1.. {
+        let error = match request().send().await {
+            Ok(rsp) if rsp.status().is_server_error() => {
+                anyhow::anyhow!("Vault is unavailable: {}", rsp.status())
+            }
+            Ok(rsp) => {
+                return rsp
+                    .error_for_status()?
+                    .json()
+                    .await
+                    .context("invalid Vault response")
+            }
+            Err(err) => anyhow::Error::new(err).context("could not reach Vault"),
+        };
+
+        if attempt == VAULT_ATTEMPTS {
+            return Err(error);
+        }
+
+        tracing::warn!("{error:#}, retrying in {backoff:?}");
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+    }
+
+    unreachable!()
+}
+
+impl VaultConfig {
+    fn endpoint(&self, path: &str) -> anyhow::Result<Url> {
+        self.address
+            .join(&format!("v1/{path}"))
+            .with_context(|| format!("invalid Vault path {path}"))
+    }
+
+    async fn token(&self, client: &reqwest::Client) -> anyhow::Result<String> {
+        if let Some(token) = &self.token {
+            return Ok(token.clone());
+        }
+
+        let (Some(role_id), Some(secret_id)) = (&self.role_id, &self.secret_id) else {
+            anyhow::bail!("vault requires either token, or role_id and secret_id");
+        };
+
+        let url = self.endpoint("auth/approle/login")?;
+        let login: LoginResponse = send_with_retry(|| {
+            client
+                .post(url.clone())
+                .json(&AppRoleLogin { role_id, secret_id })
+        })
+        .await
+        .context("could not log in to Vault")?;
+
+        Ok(login.auth.client_token)
+    }
+
+    /// Read the key-value pairs of the secret
+    pub async fn secrets(
+        &self,
+        client: &reqwest::Client,
+    ) -> anyhow::Result<HashMap<String, String>> {
+        let token = self.token(client).await?;
+
+        let url = self.endpoint(&format!("{}/data/{}", self.mount, self.secrets_path))?;
+        let secret: SecretResponse =
+            send_with_retry(|| client.get(url.clone()).header("X-Vault-Token", &token))
+                .await
+                .with_context(|| format!("could not read Vault secret {}", self.secrets_path))?;
+
+        Ok(secret.data.data)
+    }
+}
+
+/// Deserialize the configuration, overriding it with the secrets stored in Vault when `vault` is
+/// configured.
+///
+/// The keys of the secret are configuration paths, e.g. `admin_token` or `streamers.alice`.
+pub async fn load(sources: config::Config) -> anyhow::Result<OvenCtrlConfig> {
+    let config = sources.clone().try_deserialize::<OvenCtrlConfig>()?;
+    let Some(vault) = &config.vault else {
+        return Ok(config);
+    };
+
+    let client = reqwest::Client::builder()
+        .user_agent(&config.http_client_user_agent)
+        .build()?;
+    let secrets = vault.secrets(&client).await?;
+    tracing::info!("Loaded {} secrets from Vault", secrets.len());
+
+    let mut builder = config::Config::builder().add_source(sources);
+    for (key, value) in secrets {
+        builder = builder.set_override(key, value)?;
+    }
+
+    Ok(builder.build()?.try_deserialize()?)
+}
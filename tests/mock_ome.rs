@@ -0,0 +1,713 @@
+//! End-to-end tests running the oven-ctrl binary against a mock OvenMediaEngine
+//!
+//! The mock plays the role of OME: it sends the admission webhooks OME would send and checks that
+//! the responses match what OME expects.
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    path::PathBuf,
+    process::{Child, Command},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use base64::Engine;
+use flate2::{write::GzEncoder, Compression};
+use hmac::{Hmac, Mac};
+use hyper::client::conn::http2::SendRequest;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use reqwest::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, VARY};
+use serde_json::{json, Value};
+use sha1::Sha1;
+
+const CONFIG: &str = r#"
+external_host = "localhost:3333"
+
+[streamers]
+alice = "alice-key"
+
+[allowed_streams]
+alice = ["main"]
+
+[rooms]
+main = "hunter2"
+"#;
+
+struct OvenCtrl {
+    child: Child,
+    port: u16,
+    config: PathBuf,
+}
+
+impl OvenCtrl {
+    fn spawn() -> Self {
+        Self::spawn_with("")
+    }
+
+    /// Start oven-ctrl with `extra` top-level configuration keys
+    fn spawn_with(extra: &str) -> Self {
+        let port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let config = std::env::temp_dir().join(format!("oven-ctrl-mock-ome-{port}.toml"));
+        std::fs::write(&config, format!("port = {port}\n{extra}{CONFIG}")).unwrap();
+
+        let child = Command::new(env!("CARGO_BIN_EXE_oven-ctrl"))
+            .arg(&config)
+            .spawn()
+            .unwrap();
+
+        let start = Instant::now();
+        while TcpStream::connect((Ipv4Addr::LOCALHOST, port)).is_err() {
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "oven-ctrl did not start"
+            );
+            std::thread::sleep(Duration::from_millis(50));
+        }
+
+        Self {
+            child,
+            port,
+            config,
+        }
+    }
+}
+
+impl Drop for OvenCtrl {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_file(&self.config);
+    }
+}
+
+/// Mock OvenMediaEngine, sending admission webhooks to oven-ctrl
+struct MockOme {
+    client: reqwest::Client,
+    admission_url: String,
+}
+
+impl MockOme {
+    fn new(ctrl: &OvenCtrl) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            admission_url: format!("http://127.0.0.1:{}/oven/admission", ctrl.port),
+        }
+    }
+
+    fn payload(direction: &str, status: &str, url: &str) -> Value {
+        json!({
+            "client": {
+                "address": "192.168.0.10",
+                "port": 40000,
+                "user_agent": "Mozilla/5.0"
+            },
+            "request": {
+                "direction": direction,
+                "protocol": "WebRTC",
+                "status": status,
+                "url": url,
+                "time": "2021-05-12T13:45:00.000Z"
+            }
+        })
+    }
+
+    async fn send(&self, payload: &Value) -> (reqwest::StatusCode, String) {
+        let rsp = self
+            .client
+            .post(&self.admission_url)
+            .json(payload)
+            .send()
+            .await
+            .unwrap();
+
+        (rsp.status(), rsp.text().await.unwrap())
+    }
+
+    async fn send_gzip(&self, body: &[u8]) -> (reqwest::StatusCode, String) {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(body).unwrap();
+
+        let rsp = self
+            .client
+            .post(&self.admission_url)
+            .header(CONTENT_TYPE, "application/json")
+            .header(CONTENT_ENCODING, "gzip")
+            .body(encoder.finish().unwrap())
+            .send()
+            .await
+            .unwrap();
+
+        (rsp.status(), rsp.text().await.unwrap())
+    }
+
+    async fn admission(&self, direction: &str, status: &str, url: &str) -> Value {
+        let (status, body) = self.send(&Self::payload(direction, status, url)).await;
+        assert_eq!(status, reqwest::StatusCode::OK, "unexpected status: {body}");
+        serde_json::from_str(&body).unwrap()
+    }
+}
+
+fn assert_opening_response(rsp: &Value, allowed: bool) {
+    let rsp = rsp.as_object().expect("response is an object");
+
+    assert_eq!(rsp["allowed"], allowed);
+    assert!(rsp["new_url"].is_null() || rsp["new_url"].is_string());
+    assert!(rsp["lifetime"].is_null() || rsp["lifetime"].is_u64());
+    if allowed {
+        assert!(rsp["reason"].is_null());
+    } else {
+        assert!(rsp["reason"].is_string());
+    }
+}
+
+#[tokio::test]
+async fn admission_contract() {
+    let ctrl = OvenCtrl::spawn();
+    let ome = MockOme::new(&ctrl);
+
+    let rsp = ome
+        .admission(
+            "incoming",
+            "opening",
+            "rtmp://ome.example.com/app/main?name=alice&key=alice-key",
+        )
+        .await;
+    assert_opening_response(&rsp, true);
+
+    let rsp = ome
+        .admission(
+            "incoming",
+            "opening",
+            "rtmp://ome.example.com/app/main?name=alice&key=wrong",
+        )
+        .await;
+    assert_opening_response(&rsp, false);
+
+    let rsp = ome
+        .admission(
+            "outgoing",
+            "opening",
+            "wss://ome.example.com/app/main?password=hunter2",
+        )
+        .await;
+    assert_opening_response(&rsp, true);
+
+    let rsp = ome
+        .admission(
+            "outgoing",
+            "opening",
+            "wss://ome.example.com/app/main?password=wrong",
+        )
+        .await;
+    assert_opening_response(&rsp, false);
+
+    let rsp = ome
+        .admission(
+            "outgoing",
+            "closing",
+            "wss://ome.example.com/app/main?password=hunter2",
+        )
+        .await;
+    assert_eq!(rsp, json!({}));
+}
+
+#[tokio::test]
+async fn malformed_admission_is_rejected() {
+    let ctrl = OvenCtrl::spawn();
+    let ome = MockOme::new(&ctrl);
+
+    let (status, _) = ome
+        .send(&MockOme::payload(
+            "incoming",
+            "opening",
+            "rtmp://ome.example.com/app",
+        ))
+        .await;
+    assert_eq!(status, reqwest::StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn gzip_admission() {
+    let ctrl = OvenCtrl::spawn();
+    let ome = MockOme::new(&ctrl);
+
+    let payload = MockOme::payload(
+        "incoming",
+        "opening",
+        "rtmp://ome.example.com/app/main?name=alice&key=alice-key",
+    );
+    let (status, body) = ome
+        .send_gzip(serde_json::to_string(&payload).unwrap().as_bytes())
+        .await;
+    assert_eq!(status, reqwest::StatusCode::OK, "unexpected status: {body}");
+    assert_opening_response(&serde_json::from_str(&body).unwrap(), true);
+}
+
+#[tokio::test]
+async fn gzip_bomb_is_rejected() {
+    let ctrl = OvenCtrl::spawn();
+    let ome = MockOme::new(&ctrl);
+
+    // Compresses to a few kilobytes, but is larger than the body limit once decompressed
+    let mut body = serde_json::to_string(&MockOme::payload(
+        "incoming",
+        "opening",
+        "rtmp://ome.example.com/app/main?name=alice&key=alice-key",
+    ))
+    .unwrap()
+    .into_bytes();
+    body.resize(body.len() + 16 * 1024 * 1024, b' ');
+
+    let (status, _) = ome.send_gzip(&body).await;
+    assert_eq!(status, reqwest::StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn responses_are_compressed() {
+    let ctrl = OvenCtrl::spawn();
+    let ome = MockOme::new(&ctrl);
+
+    let rsp = ome
+        .client
+        .get(format!("http://127.0.0.1:{}/dist/ovenplayer.js", ctrl.port))
+        .header(ACCEPT_ENCODING, "gzip")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(rsp.headers()[CONTENT_ENCODING], "gzip");
+    assert_eq!(rsp.headers()[VARY], "accept-encoding");
+
+    let rsp = ome
+        .client
+        .post(&ome.admission_url)
+        .header(ACCEPT_ENCODING, "gzip")
+        .json(&MockOme::payload(
+            "incoming",
+            "opening",
+            "rtmp://ome.example.com/app/main?name=alice&key=alice-key",
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert!(rsp.headers().get(CONTENT_ENCODING).is_none());
+}
+
+#[test]
+fn replay_mode() {
+    let dir = std::env::temp_dir();
+    let config = dir.join(format!("oven-ctrl-replay-{}.toml", std::process::id()));
+    let requests = dir.join(format!("oven-ctrl-replay-{}.ndjson", std::process::id()));
+    std::fs::write(&config, CONFIG).unwrap();
+
+    let url = "rtmp://ome.example.com/app/main?name=alice&key=alice-key";
+    let lines = [
+        MockOme::payload("incoming", "opening", url).to_string(),
+        MockOme::payload("incoming", "opening", &url.replace("alice-key", "guess")).to_string(),
+        "{}".to_owned(),
+    ];
+    std::fs::write(&requests, lines.join("\n")).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_oven-ctrl"))
+        .arg(&config)
+        .arg("--replay-mode")
+        .arg(&requests)
+        .output()
+        .unwrap();
+    let _ = std::fs::remove_file(&config);
+    let _ = std::fs::remove_file(&requests);
+    assert!(output.status.success());
+
+    let responses = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(responses.len(), 3);
+    assert_opening_response(&responses[0], true);
+    assert_opening_response(&responses[1], false);
+    assert_eq!(responses[2]["status"], 400);
+}
+
+/// Send an admission request on `stream`, returning the headers of the response
+fn raw_admission(stream: &mut BufReader<TcpStream>, body: &str) -> String {
+    write!(
+        stream.get_mut(),
+        "POST /oven/admission HTTP/1.1\r\nHost: localhost\r\n\
+         Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    )
+    .unwrap();
+
+    let mut headers = String::new();
+    while !headers.ends_with("\r\n\r\n") {
+        assert_ne!(
+            stream.read_line(&mut headers).unwrap(),
+            0,
+            "connection closed"
+        );
+    }
+    let length = headers
+        .lines()
+        .find_map(|line| {
+            line.to_lowercase()
+                .strip_prefix("content-length: ")?
+                .parse()
+                .ok()
+        })
+        .expect("content-length header");
+    stream.read_exact(&mut vec![0; length]).unwrap();
+
+    headers.to_lowercase()
+}
+
+#[test]
+fn keep_alive_connections() {
+    let ctrl = OvenCtrl::spawn_with("http_keep_alive_timeout_secs = 1\n");
+    let body = MockOme::payload(
+        "incoming",
+        "opening",
+        "rtmp://ome.example.com/app/main?name=alice&key=alice-key",
+    )
+    .to_string();
+
+    let mut stream = BufReader::new(TcpStream::connect((Ipv4Addr::LOCALHOST, ctrl.port)).unwrap());
+    stream
+        .get_ref()
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    // Both requests are answered on the same connection
+    for _ in 0..2 {
+        let headers = raw_admission(&mut stream, &body);
+        assert!(headers.starts_with("http/1.1 200"), "{headers}");
+        assert!(headers.contains("keep-alive: timeout=1\r\n"), "{headers}");
+    }
+
+    // Then it is closed once idle for the timeout
+    std::thread::sleep(Duration::from_millis(1500));
+    assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+}
+
+/// Run with `cargo test --test mock_ome -- --ignored --nocapture` to compare the latency of the
+/// admissions sent on a new connection to the latency of those sent on a kept-alive connection
+#[tokio::test]
+#[ignore]
+async fn keep_alive_benchmark() {
+    const REQUESTS: u32 = 1000;
+
+    let ctrl = OvenCtrl::spawn();
+    let payload = MockOme::payload(
+        "incoming",
+        "opening",
+        "rtmp://ome.example.com/app/main?name=alice&key=alice-key",
+    );
+
+    for (mode, client) in [
+        (
+            "new connection per request",
+            reqwest::Client::builder()
+                .pool_max_idle_per_host(0)
+                .build()
+                .unwrap(),
+        ),
+        ("keep-alive", reqwest::Client::new()),
+    ] {
+        let ome = MockOme {
+            client,
+            ..MockOme::new(&ctrl)
+        };
+
+        let start = Instant::now();
+        for _ in 0..REQUESTS {
+            let (status, _) = ome.send(&payload).await;
+            assert_eq!(status, reqwest::StatusCode::OK);
+        }
+        println!("{mode}: {:?} per admission", start.elapsed() / REQUESTS);
+    }
+}
+
+/// Connection to oven-ctrl speaking cleartext HTTP/2 from its start, as OME would with h2c
+async fn http2_connection(port: u16) -> hyper::Result<SendRequest<Body>> {
+    let stream = tokio::net::TcpStream::connect((Ipv4Addr::LOCALHOST, port))
+        .await
+        .unwrap();
+    stream.set_nodelay(true).unwrap();
+    let (sender, connection) =
+        hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(stream)).await?;
+    tokio::spawn(connection);
+
+    Ok(sender)
+}
+
+async fn http2_admission(
+    mut sender: SendRequest<Body>,
+    port: u16,
+    payload: &Value,
+) -> hyper::Result<(StatusCode, Value)> {
+    let request = Request::post(format!("http://127.0.0.1:{port}/oven/admission"))
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(payload.to_string()))
+        .unwrap();
+    let rsp = sender.send_request(request).await?;
+
+    let status = rsp.status();
+    let body = axum::body::to_bytes(Body::new(rsp.into_body()), usize::MAX)
+        .await
+        .unwrap();
+    Ok((status, serde_json::from_slice(&body).unwrap()))
+}
+
+#[tokio::test]
+async fn http2_admissions() {
+    let payload = MockOme::payload(
+        "incoming",
+        "opening",
+        "rtmp://ome.example.com/app/main?name=alice&key=alice-key",
+    );
+
+    let ctrl = OvenCtrl::spawn_with("enable_http2 = true\n");
+    let sender = http2_connection(ctrl.port).await.unwrap();
+    let responses = futures_util::future::join_all(
+        (0..100).map(|_| http2_admission(sender.clone(), ctrl.port, &payload)),
+    )
+    .await;
+    for rsp in responses {
+        let (status, rsp) = rsp.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_opening_response(&rsp, true);
+    }
+
+    // HTTP/1 is still served
+    let ome = MockOme::new(&ctrl);
+    assert_opening_response(
+        &ome.admission(
+            "incoming",
+            "opening",
+            "rtmp://ome.example.com/app/main?name=alice&key=alice-key",
+        )
+        .await,
+        true,
+    );
+
+    let ctrl = OvenCtrl::spawn();
+    let result = match http2_connection(ctrl.port).await {
+        Ok(sender) => http2_admission(sender, ctrl.port, &payload).await,
+        Err(err) => Err(err),
+    };
+    assert!(result.is_err(), "HTTP/2 is disabled by default");
+}
+
+/// Run with `cargo test --test mock_ome -- --ignored --nocapture` to compare the time taken by
+/// batches of 100 concurrent admissions over HTTP/1 connections and over a single HTTP/2
+/// connection
+#[tokio::test]
+#[ignore]
+async fn http2_benchmark() {
+    const BATCHES: u32 = 50;
+    const CONCURRENT: usize = 100;
+
+    let ctrl = OvenCtrl::spawn_with("enable_http2 = true\n");
+    let payload = MockOme::payload(
+        "incoming",
+        "opening",
+        "rtmp://ome.example.com/app/main?name=alice&key=alice-key",
+    );
+
+    let ome = MockOme::new(&ctrl);
+    let start = Instant::now();
+    for _ in 0..BATCHES {
+        for (status, _) in
+            futures_util::future::join_all((0..CONCURRENT).map(|_| ome.send(&payload))).await
+        {
+            assert_eq!(status, reqwest::StatusCode::OK);
+        }
+    }
+    println!("HTTP/1: {:?} per batch", start.elapsed() / BATCHES);
+
+    let sender = http2_connection(ctrl.port).await.unwrap();
+    let start = Instant::now();
+    for _ in 0..BATCHES {
+        for rsp in futures_util::future::join_all(
+            (0..CONCURRENT).map(|_| http2_admission(sender.clone(), ctrl.port, &payload)),
+        )
+        .await
+        {
+            assert_eq!(rsp.unwrap().0, StatusCode::OK);
+        }
+    }
+    println!("HTTP/2: {:?} per batch", start.elapsed() / BATCHES);
+}
+
+#[tokio::test]
+async fn test_webhook() {
+    let ctrl = OvenCtrl::spawn_with("admin_token = \"admin-token\"\n");
+    let client = reqwest::Client::new();
+    let api = |path: &str| {
+        client
+            .post(format!("http://127.0.0.1:{}/api/{path}", ctrl.port))
+            .bearer_auth("admin-token")
+    };
+
+    let rsp = api("test-webhook").send().await.unwrap();
+    assert_eq!(rsp.status(), reqwest::StatusCode::NOT_FOUND);
+
+    // Receiver answering 202 with the received requests
+    let (requests, mut received) = tokio::sync::mpsc::unbounded_channel();
+    let receiver = axum::Router::new().route(
+        "/hook",
+        axum::routing::post(|headers: axum::http::HeaderMap, body: String| async move {
+            requests.send((headers, body)).unwrap();
+            StatusCode::ACCEPTED
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .unwrap();
+    let hook_url = format!("http://{}/hook", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, receiver).await });
+
+    let rsp: Value = api("stream-events/subscribe")
+        .json(&json!({ "url": hook_url, "secret": "hook-secret" }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let id = rsp["subscription_id"].as_str().unwrap();
+
+    let rsp = api(&format!("test-webhook?subscription_id={id}-unknown"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(rsp.status(), reqwest::StatusCode::NOT_FOUND);
+
+    let rsp: Value = api(&format!("test-webhook?subscription_id={id}"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(rsp[0]["subscription_id"], id);
+    assert_eq!(rsp[0]["status"], 202);
+    assert!(rsp[0]["latency_ms"].is_u64());
+
+    let (headers, body) = received.recv().await.unwrap();
+    assert_eq!(headers["x-ovenctrl-test"], "true");
+    let mut mac = Hmac::<Sha1>::new_from_slice(b"hook-secret").unwrap();
+    mac.update(body.as_bytes());
+    let signature =
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+    assert_eq!(headers["x-ome-signature"], signature.as_str());
+    let admission: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(admission["request"]["status"], "opening");
+
+    // The stream keys are not relayed
+    MockOme::new(&ctrl)
+        .admission(
+            "incoming",
+            "opening",
+            "rtmp://ome.example.com/app/main?name=alice&key=alice-key",
+        )
+        .await;
+    let (_, body) = received.recv().await.unwrap();
+    let admission: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(
+        admission["request"]["url"],
+        "rtmp://ome.example.com/app/main?name=alice"
+    );
+}
+
+/// Mock OME Manager API, answering every request with `200 OK` and recording their paths.
+///
+/// Served by its own thread, so that oven-ctrl can probe it while the test waits for oven-ctrl
+/// to start.
+fn mock_ome_api() -> (String, Arc<Mutex<Vec<String>>>) {
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let api = axum::Router::new().fallback({
+        let requests = requests.clone();
+        move |uri: axum::http::Uri| async move {
+            requests.lock().unwrap().push(uri.path().to_owned());
+            "{}"
+        }
+    });
+
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async move {
+                let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+                axum::serve(listener, api).await
+            })
+    });
+
+    (url, requests)
+}
+
+#[tokio::test]
+async fn closing_without_opening() {
+    let (api_url, requests) = mock_ome_api();
+    let ctrl = OvenCtrl::spawn_with(&format!(
+        "ome_api_url = \"{api_url}\"\nome_api_access_token = \"token\"\n\
+         record_rooms = [\"main\"]\n\
+         room_config.main.push_relay_targets = [\"rtmp://relay.example.com/live/key\"]\n"
+    ));
+    let ome = MockOme::new(&ctrl);
+    let url = "rtmp://ome.example.com/app/main?name=alice&key=alice-key";
+    let actions = || async {
+        // The OME API requests are sent in the background, in any order
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let mut actions = std::mem::take(&mut *requests.lock().unwrap())
+            .into_iter()
+            .filter(|path| path.contains(':'))
+            .collect::<Vec<_>>();
+        actions.sort();
+        actions
+    };
+
+    ome.admission("incoming", "opening", url).await;
+    assert_eq!(
+        actions().await,
+        [
+            "/v1/vhosts/default/apps/app/streams/main:startRecord",
+            "/v1/vhosts/default/apps/app:startPush",
+        ]
+    );
+
+    // E.g. the closing of a denied second attempt, the live stream keeps being relayed and
+    // recorded
+    let mut closing = MockOme::payload(
+        "incoming",
+        "closing",
+        "rtmp://ome.example.com/app/main?name=alice&key=wrong-key",
+    );
+    closing["client"]["port"] = json!(40001);
+    let (status, _) = ome.send(&closing).await;
+    assert_eq!(status, reqwest::StatusCode::OK);
+    assert!(actions().await.is_empty());
+
+    ome.admission("incoming", "closing", url).await;
+    assert_eq!(
+        actions().await,
+        [
+            "/v1/vhosts/default/apps/app/streams/main:stopRecord",
+            "/v1/vhosts/default/apps/app:stopPush",
+        ]
+    );
+}